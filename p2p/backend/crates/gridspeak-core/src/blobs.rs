@@ -0,0 +1,118 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Size and content hash of a blob written to disk.
+#[derive(Debug, Clone)]
+pub struct BlobInfo {
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// Content-addressed file storage under `<data_dir>/blobs`.
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(sha256)
+    }
+
+    /// Starts writing a new blob; chunks are hashed as they are written and
+    /// the write fails once more than `max_bytes` have been received.
+    pub fn writer(&self, max_bytes: u64) -> Result<BlobWriter> {
+        let tmp_path = self.dir.join(format!(".upload-{}", Uuid::new_v4()));
+        let file = fs::File::create(&tmp_path)?;
+        Ok(BlobWriter {
+            dir: self.dir.clone(),
+            tmp_path,
+            file: Some(file),
+            hasher: Sha256::new(),
+            size: 0,
+            max_bytes,
+        })
+    }
+
+    /// Stores a blob held in memory.
+    pub fn put(&self, data: &[u8]) -> Result<BlobInfo> {
+        let mut writer = self.writer(data.len() as u64)?;
+        writer.write(data)?;
+        writer.finish()
+    }
+
+    pub fn read(&self, sha256: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(sha256))?)
+    }
+
+    /// Removes a blob; missing blobs are ignored.
+    pub fn remove(&self, sha256: &str) -> Result<()> {
+        let path = self.path(sha256);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// In-progress blob upload. Dropping it without calling [`BlobWriter::finish`]
+/// discards the partial file.
+pub struct BlobWriter {
+    dir: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<fs::File>,
+    hasher: Sha256,
+    size: u64,
+    max_bytes: u64,
+}
+
+impl BlobWriter {
+    pub fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.size += chunk.len() as u64;
+        if self.size > self.max_bytes {
+            return Err(anyhow!("upload exceeds {} bytes", self.max_bytes));
+        }
+        self.hasher.update(chunk);
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Flushes the upload and moves it to its content-addressed location.
+    pub fn finish(mut self) -> Result<BlobInfo> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let sha256 = format!("{:x}", self.hasher.clone().finalize());
+        fs::rename(&self.tmp_path, self.dir.join(&sha256))?;
+        Ok(BlobInfo {
+            sha256,
+            size: self.size,
+        })
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
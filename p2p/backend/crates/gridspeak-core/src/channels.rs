@@ -0,0 +1,974 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{ChannelRole, Hlc, RoleGrant, Signed, crypto::Sealed, persist, roles::effective_roles};
+
+/// Channel that always exists and cannot be removed.
+pub const DEFAULT_CHANNEL: &str = "general";
+
+/// How long a channel's previous name keeps resolving after a rename.
+const ALIAS_DAYS: i64 = 30;
+
+/// Length limits of a channel record's free-text fields, in characters.
+pub const MAX_DISPLAY_NAME_CHARS: usize = 64;
+pub const MAX_TOPIC_CHARS: usize = 256;
+pub const MAX_DESCRIPTION_CHARS: usize = 1024;
+pub const MAX_CATEGORY_CHARS: usize = 64;
+
+/// Most former names a record keeps.
+const MAX_ALIASES: usize = 16;
+
+/// Removals per channel kept while their signer lacks the role for them, in
+/// case the grant that authorizes them arrives later.
+const MAX_PENDING_REMOVALS: usize = 8;
+
+/// Checks that a (normalized) channel name is lowercase alphanumeric, `-` or `_`.
+pub fn validate_channel_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64 {
+        return Err(anyhow!("invalid channel name"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(anyhow!("channel name must be alphanumeric, - or _"));
+    }
+    Ok(())
+}
+
+/// Former name of a renamed channel, resolvable until `until`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelAlias {
+    pub name: String,
+    pub until: DateTime<Utc>,
+}
+
+/// Descriptive record for a channel. Records are signed by the node that last
+/// updated them and the one with the latest clock stamp wins.
+///
+/// A channel is identified by its `id`, the name it was created with plus a
+/// random suffix (see [`ChannelSet::new_id`]); stores,
+/// files and wire events use the id, so `name` can change without losing
+/// history. Records from before renames existed carry no id and use the name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Channel {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub topic: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// PeerId of the node that created the channel; unknown for channels that
+    /// predate channel records.
+    #[serde(default)]
+    pub creator: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<Hlc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<ChannelAlias>,
+    /// Invite-only channel whose messages are encrypted for its members (the
+    /// peers holding a role in it). Fixed when the channel is created.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    /// Seconds after which messages in the channel disappear, unless they
+    /// carry a shorter TTL of their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_ttl_secs: Option<u32>,
+}
+
+impl Channel {
+    /// Record for a channel created now by `creator`.
+    pub fn new(
+        id: impl Into<String>,
+        name: impl Into<String>,
+        creator: impl Into<String>,
+        hlc: Hlc,
+    ) -> Self {
+        let name = name.into();
+        Self {
+            id: Some(id.into()),
+            display_name: name.clone(),
+            creator: Some(creator.into()),
+            created_at: Some(Utc::now()),
+            hlc: Some(hlc),
+            ..Self::bare(&name)
+        }
+    }
+
+    /// Record for a channel nobody has described yet.
+    pub fn bare(id: &str) -> Self {
+        Self {
+            id: Some(id.to_string()),
+            name: id.to_string(),
+            display_name: id.to_string(),
+            topic: String::new(),
+            description: String::new(),
+            category: None,
+            creator: None,
+            created_at: None,
+            hlc: None,
+            aliases: Vec::new(),
+            private: false,
+            message_ttl_secs: None,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Changes the name, keeping the old one as an alias for a while. Expired
+    /// aliases and any alias equal to the new name are dropped, and a display
+    /// name that was just the old name follows the rename.
+    pub fn rename(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        if name == self.name {
+            return;
+        }
+        let now = Utc::now();
+        if self.display_name == self.name {
+            self.display_name = name.clone();
+        }
+        let old = std::mem::replace(&mut self.name, name);
+        self.aliases
+            .retain(|a| a.until > now && a.name != self.name);
+        self.aliases.push(ChannelAlias {
+            name: old,
+            until: now + Duration::days(ALIAS_DAYS),
+        });
+    }
+
+    /// Checks the name and the length limits of the free-text fields.
+    pub fn validate(&self) -> Result<()> {
+        validate_channel_name(self.id())?;
+        validate_channel_name(&self.name)?;
+        for (value, max_chars, label) in [
+            (
+                self.display_name.as_str(),
+                MAX_DISPLAY_NAME_CHARS,
+                "display_name",
+            ),
+            (&self.topic, MAX_TOPIC_CHARS, "topic"),
+            (&self.description, MAX_DESCRIPTION_CHARS, "description"),
+            (
+                self.category.as_deref().unwrap_or_default(),
+                MAX_CATEGORY_CHARS,
+                "category",
+            ),
+        ] {
+            if value.chars().count() > max_chars {
+                return Err(anyhow!("{label}: at most {max_chars} characters"));
+            }
+        }
+        if self.aliases.len() > MAX_ALIASES {
+            return Err(anyhow!("too many former names"));
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is a former name of this channel that still resolves.
+    pub fn has_alias(&self, name: &str) -> bool {
+        let now = Utc::now();
+        self.aliases.iter().any(|a| a.name == name && a.until > now)
+    }
+}
+
+/// Removal of a channel: tombstones the add tags its signer had observed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelRemoval {
+    /// Channel id.
+    pub channel: String,
+    pub tags: BTreeSet<Uuid>,
+    pub hlc: Hlc,
+}
+
+/// Group key of a private channel for one epoch, wrapped to each member by
+/// the owner who issued it. Removing a member starts a new epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelKey {
+    /// Channel id.
+    pub channel: String,
+    pub epoch: u32,
+    /// Wrapped group key per member PeerId.
+    pub wrapped: BTreeMap<String, Sealed>,
+    pub hlc: Hlc,
+}
+
+impl ChannelKey {
+    /// Context a member's wrapped key is bound to.
+    pub fn wrap_context(channel: &str, epoch: u32, member: &str) -> Vec<u8> {
+        format!("gridspeak-channel-key/{channel}/{epoch}/{member}").into_bytes()
+    }
+}
+
+/// Most messages pinned in one channel at a time. Should concurrent pins
+/// exceed it, the oldest drop out.
+pub const MAX_PINS: usize = 50;
+
+/// How long an unpin is remembered, so that a stale pin from a peer that
+/// missed it does not bring the message back.
+const UNPIN_HORIZON_DAYS: i64 = 30;
+
+/// Pins a message in a channel, or unpins it when `pinned` is false. Only
+/// pins signed by a channel owner or moderator (or a grid moderator) take
+/// effect; the latest one per message wins.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pin {
+    /// Channel id.
+    pub channel: String,
+    pub message_id: Uuid,
+    pub pinned: bool,
+    pub hlc: Hlc,
+}
+
+/// Replicated set of channel ids (an observed-remove set).
+///
+/// Every add creates a unique tag; a remove tombstones the tags it has seen.
+/// An id is present while it has at least one tag that is not tombstoned, so
+/// a create that races a delete survives, while a delete is never undone by
+/// stale state from a peer that missed it. Merging is a union of both sides
+/// and therefore converges regardless of order.
+///
+/// The latest [`Channel`] record per id, all role grants, the signed removals,
+/// the group keys of private channels and pinned messages travel with the set.
+/// Records, removals and pins from peers only take effect if their signer
+/// holds the required role (see [`ChannelSet::merge`]).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelSet {
+    adds: BTreeMap<String, BTreeSet<Uuid>>,
+    #[serde(default)]
+    removed: BTreeSet<Uuid>,
+    #[serde(default)]
+    meta: BTreeMap<String, Signed<Channel>>,
+    #[serde(default)]
+    grants: BTreeMap<String, Vec<Signed<RoleGrant>>>,
+    #[serde(default)]
+    removals: Vec<Signed<ChannelRemoval>>,
+    #[serde(default)]
+    keys: BTreeMap<String, BTreeMap<u32, Signed<ChannelKey>>>,
+    /// Latest pin or unpin per message, per channel id.
+    #[serde(default)]
+    pins: BTreeMap<String, BTreeMap<Uuid, Signed<Pin>>>,
+    /// Key records that lost their epoch to a concurrent rotation by another
+    /// owner, kept so messages sealed with them still open.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    superseded_keys: Vec<Signed<ChannelKey>>,
+    /// Signed removals whose signer did not hold the role for them when they
+    /// arrived; re-checked on every merge. Peers ignore them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pending_removals: Vec<Signed<ChannelRemoval>>,
+}
+
+impl ChannelSet {
+    /// Loads the set from disk, returning an empty set when the file is absent.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "channel set")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.adds
+            .get(id)
+            .is_some_and(|tags| tags.iter().any(|t| !self.removed.contains(t)))
+    }
+
+    /// Whether the id was ever added, including channels removed since.
+    pub fn knows(&self, id: &str) -> bool {
+        self.adds.contains_key(id)
+    }
+
+    /// Present channel ids in sorted order.
+    pub fn ids(&self) -> Vec<String> {
+        self.adds
+            .keys()
+            .filter(|id| self.contains(id))
+            .cloned()
+            .collect()
+    }
+
+    /// Id of the present channel currently called `name`. Should concurrent
+    /// renames give two channels the same name, the smaller id wins.
+    pub fn find_by_name(&self, name: &str) -> Option<String> {
+        self.ids()
+            .into_iter()
+            .find(|id| self.describe(id).name == name)
+    }
+
+    /// Resolves a channel reference from a user: a current name, an id, or a
+    /// recent former name, in that order of preference.
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        self.find_by_name(name)
+            .or_else(|| self.contains(name).then(|| name.to_string()))
+            .or_else(|| {
+                self.ids()
+                    .into_iter()
+                    .find(|id| self.describe(id).has_alias(name))
+            })
+    }
+
+    /// Picks an id for a new channel called `name`: the name with a random
+    /// suffix. Peers that create a channel under the same name concurrently
+    /// get two distinct channels instead of rejecting each other's record, and
+    /// a new channel never inherits an old one's creator and roles.
+    pub fn new_id(&self, name: &str) -> String {
+        // Names are ASCII, so any byte offset is a char boundary.
+        let stem = &name[..name.len().min(55)];
+        loop {
+            let id = format!("{stem}-{}", &Uuid::new_v4().simple().to_string()[..8]);
+            if !self.knows(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Adds a channel; returns `false` if it was already present.
+    pub fn add(&mut self, id: &str) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.adds
+            .entry(id.to_string())
+            .or_default()
+            .insert(Uuid::new_v4());
+        true
+    }
+
+    /// Removes a channel by tombstoning every tag observed for it, without a
+    /// signed removal; returns `false` if it was not present. The default
+    /// channel is never removed.
+    pub fn remove(&mut self, id: &str) -> bool {
+        if id == DEFAULT_CHANNEL || !self.contains(id) {
+            return false;
+        }
+        if let Some(tags) = self.adds.get(id) {
+            self.removed.extend(tags.iter().copied());
+        }
+        true
+    }
+
+    /// Removal covering every tag observed for a present channel, to be signed
+    /// and passed to [`ChannelSet::apply_removal`].
+    pub fn removal(&self, id: &str, hlc: Hlc) -> Option<ChannelRemoval> {
+        if id == DEFAULT_CHANNEL || !self.contains(id) {
+            return None;
+        }
+        Some(ChannelRemoval {
+            channel: id.to_string(),
+            tags: self.adds.get(id).cloned().unwrap_or_default(),
+            hlc,
+        })
+    }
+
+    /// Tombstones the tags named by a removal that belong to its channel;
+    /// returns `false` if the removal was already applied.
+    pub fn apply_removal(&mut self, removal: Signed<ChannelRemoval>) -> bool {
+        if removal.payload.channel == DEFAULT_CHANNEL || self.removals.contains(&removal) {
+            return false;
+        }
+        if let Some(tags) = self.adds.get(&removal.payload.channel) {
+            self.removed.extend(
+                removal
+                    .payload
+                    .tags
+                    .iter()
+                    .filter(|t| tags.contains(t))
+                    .copied(),
+            );
+        }
+        self.removals.push(removal);
+        true
+    }
+
+    /// Roles in a channel: its creator is an owner, then role grants apply.
+    pub fn roles(
+        &self,
+        id: &str,
+        is_admin: impl Fn(&str) -> bool,
+    ) -> BTreeMap<String, ChannelRole> {
+        self.roles_with_creator(id, self.describe(id).creator.as_deref(), is_admin)
+    }
+
+    fn roles_with_creator(
+        &self,
+        id: &str,
+        creator: Option<&str>,
+        is_admin: impl Fn(&str) -> bool,
+    ) -> BTreeMap<String, ChannelRole> {
+        effective_roles(creator, self.grants.get(id).into_iter().flatten(), is_admin)
+    }
+
+    /// Records a role grant; returns `false` if it was already known.
+    pub fn add_grant(&mut self, grant: Signed<RoleGrant>) -> bool {
+        let grants = self
+            .grants
+            .entry(grant.payload.channel.clone())
+            .or_default();
+        if grants.contains(&grant) {
+            return false;
+        }
+        grants.push(grant);
+        true
+    }
+
+    /// Whether a peer may edit or rename a channel: owners and moderators, or
+    /// anyone while nobody holds a role in it (channels from before roles).
+    /// Grid moderators (`is_admin`) may always, and are the only ones who may
+    /// manage the default channel, which has no creator.
+    pub fn may_manage(&self, id: &str, peer: &str, is_admin: impl Fn(&str) -> bool) -> bool {
+        if is_admin(peer) {
+            return true;
+        }
+        let roles = self.roles(id, &is_admin);
+        open_to_all(id, &roles) || roles.get(peer).is_some_and(|r| r.can_moderate())
+    }
+
+    /// Whether a peer may moderate messages in a channel.
+    pub fn may_moderate(&self, id: &str, peer: &str, is_admin: impl Fn(&str) -> bool) -> bool {
+        is_admin(peer)
+            || self
+                .roles(id, &is_admin)
+                .get(peer)
+                .is_some_and(|r| r.can_moderate())
+    }
+
+    /// Whether a peer may remove a channel or change roles in it: owners and
+    /// grid moderators only.
+    pub fn may_own(&self, id: &str, peer: &str, is_admin: impl Fn(&str) -> bool) -> bool {
+        is_admin(peer) || self.roles(id, &is_admin).get(peer) == Some(&ChannelRole::Owner)
+    }
+
+    /// Checks a channel record against the roles in this set. The creator
+    /// and creation time are fixed once a channel is known; a record for an
+    /// unknown channel must name its creator and is checked against it, so
+    /// only the creator or someone it gave a role can introduce a channel.
+    pub fn may_update(&self, record: &Signed<Channel>, is_admin: impl Fn(&str) -> bool) -> bool {
+        let id = record.payload.id();
+        let creator = if self.knows(id) || self.meta.contains_key(id) {
+            let current = self.describe(id);
+            if record.payload.created_at != current.created_at
+                || record.payload.private != current.private
+            {
+                return false;
+            }
+            current.creator
+        } else if record.payload.creator.is_some() {
+            record.payload.creator.clone()
+        } else {
+            return is_admin(&record.signer);
+        };
+        if record.payload.creator != creator {
+            return false;
+        }
+        if is_admin(&record.signer) {
+            return true;
+        }
+        let roles = self.roles_with_creator(id, creator.as_deref(), is_admin);
+        open_to_all(id, &roles) || roles.get(&record.signer).is_some_and(|r| r.can_moderate())
+    }
+
+    /// Stores a group key. The latest record per epoch becomes the one new
+    /// messages are sealed with; when two owners rotated to the same epoch the
+    /// other one is kept as superseded. Returns `true` if anything changed.
+    pub fn set_key(&mut self, key: Signed<ChannelKey>) -> bool {
+        let epochs = self.keys.entry(key.payload.channel.clone()).or_default();
+        let Some(current) = epochs.get(&key.payload.epoch) else {
+            epochs.insert(key.payload.epoch, key);
+            return true;
+        };
+        let newer = (key.payload.hlc, &key.signer) > (current.payload.hlc, &current.signer);
+        if current.signer == key.signer {
+            if newer {
+                epochs.insert(key.payload.epoch, key);
+            }
+            return newer;
+        }
+        let loser = if newer {
+            epochs
+                .insert(key.payload.epoch, key)
+                .expect("current key record")
+        } else {
+            key
+        };
+        self.supersede_key(loser)
+    }
+
+    /// Keeps the latest losing record per (channel, epoch, signer).
+    fn supersede_key(&mut self, key: Signed<ChannelKey>) -> bool {
+        let same = |k: &Signed<ChannelKey>| {
+            k.payload.channel == key.payload.channel
+                && k.payload.epoch == key.payload.epoch
+                && k.signer == key.signer
+        };
+        match self.superseded_keys.iter_mut().find(|k| same(k)) {
+            Some(existing) if existing.payload.hlc >= key.payload.hlc => false,
+            Some(existing) => {
+                *existing = key;
+                true
+            }
+            None => {
+                self.superseded_keys.push(key);
+                true
+            }
+        }
+    }
+
+    /// Group key records of a channel, oldest epoch first, followed by
+    /// superseded ones.
+    pub fn keys(&self, id: &str) -> impl Iterator<Item = &Signed<ChannelKey>> {
+        self.keys
+            .get(id)
+            .into_iter()
+            .flat_map(|epochs| epochs.values())
+            .chain(
+                self.superseded_keys
+                    .iter()
+                    .filter(move |k| k.payload.channel == id),
+            )
+    }
+
+    /// Key record of a private channel's latest epoch.
+    pub fn current_key(&self, id: &str) -> Option<&Signed<ChannelKey>> {
+        self.keys.get(id)?.values().next_back()
+    }
+
+    /// Stores a pin or unpin unless a newer one for the same message is
+    /// known; returns `true` if it was stored. Expired unpins and pins beyond
+    /// [`MAX_PINS`] are forgotten.
+    pub fn set_pin(&mut self, pin: Signed<Pin>) -> bool {
+        let message_id = pin.payload.message_id;
+        let pins = self.pins.entry(pin.payload.channel.clone()).or_default();
+        let newer = pins.get(&message_id).is_none_or(|current| {
+            (pin.payload.hlc, &pin.signer) > (current.payload.hlc, &current.signer)
+        });
+        if !newer {
+            return false;
+        }
+        pins.insert(message_id, pin);
+        prune_pins(pins);
+        pins.contains_key(&message_id)
+    }
+
+    /// Pins in force in a channel, most recent first.
+    pub fn pins(&self, id: &str) -> Vec<&Signed<Pin>> {
+        let mut pins: Vec<&Signed<Pin>> = self
+            .pins
+            .get(id)
+            .into_iter()
+            .flat_map(|pins| pins.values())
+            .filter(|pin| pin.payload.pinned)
+            .collect();
+        pins.sort_by_key(|pin| std::cmp::Reverse(pin.payload.hlc));
+        pins
+    }
+
+    pub fn is_pinned(&self, id: &str, message_id: Uuid) -> bool {
+        self.pins
+            .get(id)
+            .and_then(|pins| pins.get(&message_id))
+            .is_some_and(|pin| pin.payload.pinned)
+    }
+
+    /// Describes a channel, falling back to a bare record when none is known.
+    /// The returned record always carries its id.
+    pub fn describe(&self, id: &str) -> Channel {
+        match self.meta.get(id) {
+            Some(record) => Channel {
+                id: Some(id.to_string()),
+                ..record.payload.clone()
+            },
+            None => Channel::bare(id),
+        }
+    }
+
+    pub fn meta(&self, id: &str) -> Option<&Signed<Channel>> {
+        self.meta.get(id)
+    }
+
+    /// Stores a channel record unless a newer one is already known; returns
+    /// `true` if it was stored.
+    pub fn set_meta(&mut self, record: Signed<Channel>) -> bool {
+        let newer = match self.meta.get(record.payload.id()) {
+            Some(current) => {
+                (record.payload.hlc, &record.signer) > (current.payload.hlc, &current.signer)
+            }
+            None => true,
+        };
+        if newer {
+            self.meta.insert(record.payload.id().to_string(), record);
+        }
+        newer
+    }
+
+    /// Drops channel records rejected by `keep`, e.g. ones with bad signatures.
+    pub fn retain_meta(&mut self, mut keep: impl FnMut(&Signed<Channel>) -> bool) {
+        self.meta.retain(|_, record| keep(record));
+    }
+
+    /// Drops role grants rejected by `keep`.
+    pub fn retain_grants(&mut self, mut keep: impl FnMut(&Signed<RoleGrant>) -> bool) {
+        for grants in self.grants.values_mut() {
+            grants.retain(&mut keep);
+        }
+    }
+
+    /// Drops group keys rejected by `keep`.
+    pub fn retain_keys(&mut self, mut keep: impl FnMut(&Signed<ChannelKey>) -> bool) {
+        for epochs in self.keys.values_mut() {
+            epochs.retain(|_, key| keep(key));
+        }
+        self.superseded_keys.retain(&mut keep);
+    }
+
+    /// Drops pins rejected by `keep`.
+    pub fn retain_pins(&mut self, mut keep: impl FnMut(&Signed<Pin>) -> bool) {
+        for pins in self.pins.values_mut() {
+            pins.retain(|_, pin| keep(pin));
+        }
+    }
+
+    /// Drops channel removals rejected by `keep`.
+    pub fn retain_removals(&mut self, keep: impl FnMut(&Signed<ChannelRemoval>) -> bool) {
+        self.removals.retain(keep);
+    }
+
+    /// Splits the set into one set per channel id, for peers whose message
+    /// size limit the whole set exceeds. Merging the parts in any order gives
+    /// the same result as merging the whole. Held-back removals stay local.
+    pub fn split(&self) -> Vec<ChannelSet> {
+        let ids: BTreeSet<&String> = self
+            .adds
+            .keys()
+            .chain(self.meta.keys())
+            .chain(self.grants.keys())
+            .chain(self.keys.keys())
+            .chain(self.pins.keys())
+            .chain(self.removals.iter().map(|r| &r.payload.channel))
+            .chain(self.superseded_keys.iter().map(|k| &k.payload.channel))
+            .collect();
+        fn only<V: Clone>(map: &BTreeMap<String, V>, id: &str) -> BTreeMap<String, V> {
+            map.get_key_value(id)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .into_iter()
+                .collect()
+        }
+        ids.into_iter()
+            .map(|id| {
+                let adds = self.adds.get(id).cloned().unwrap_or_default();
+                ChannelSet {
+                    removed: self.removed.intersection(&adds).copied().collect(),
+                    adds: only(&self.adds, id),
+                    meta: only(&self.meta, id),
+                    grants: only(&self.grants, id),
+                    removals: self
+                        .removals
+                        .iter()
+                        .filter(|r| r.payload.channel == *id)
+                        .cloned()
+                        .collect(),
+                    keys: only(&self.keys, id),
+                    pins: only(&self.pins, id),
+                    superseded_keys: self
+                        .superseded_keys
+                        .iter()
+                        .filter(|k| k.payload.channel == *id)
+                        .cloned()
+                        .collect(),
+                    pending_removals: Vec::new(),
+                }
+            })
+            .collect()
+    }
+
+    /// Merges remote state into this set; returns `true` if anything changed.
+    ///
+    /// Signatures must have been checked by the caller. Role grants are kept
+    /// as they are (only authorized ones count, see [`ChannelSet::roles`]);
+    /// channel records, group keys and pins whose signer lacks the role for
+    /// them are dropped, as are invalid names. Removals whose signer lacks the
+    /// role are held back and re-checked once later grants have merged. Bare
+    /// tombstones from peers are ignored: only signed removals remove channels.
+    pub fn merge(&mut self, other: &ChannelSet, is_admin: impl Fn(&str) -> bool) -> bool {
+        let before = self.clone();
+        for (id, grants) in &other.grants {
+            for grant in grants.iter().filter(|g| g.payload.channel == *id) {
+                self.add_grant(grant.clone());
+            }
+        }
+        for (id, record) in &other.meta {
+            if id == record.payload.id()
+                && record.payload.validate().is_ok()
+                && self.may_update(record, &is_admin)
+            {
+                self.set_meta(record.clone());
+            }
+        }
+        let keys = other
+            .keys
+            .iter()
+            .flat_map(|(id, epochs)| epochs.values().filter(move |k| k.payload.channel == *id))
+            .chain(&other.superseded_keys);
+        for key in keys {
+            let id = &key.payload.channel;
+            if self.describe(id).private && self.may_own(id, &key.signer, &is_admin) {
+                self.set_key(key.clone());
+            }
+        }
+        for (id, pins) in &other.pins {
+            for pin in pins.values().filter(|p| p.payload.channel == *id) {
+                if self.may_moderate(id, &pin.signer, &is_admin) {
+                    self.set_pin(pin.clone());
+                }
+            }
+        }
+        for (name, tags) in &other.adds {
+            if validate_channel_name(name).is_err() {
+                continue;
+            }
+            self.adds
+                .entry(name.clone())
+                .or_default()
+                .extend(tags.iter().copied());
+        }
+        for removal in &other.removals {
+            if removal.payload.channel != DEFAULT_CHANNEL
+                && !self.removals.contains(removal)
+                && !self.pending_removals.contains(removal)
+            {
+                self.pending_removals.push(removal.clone());
+            }
+        }
+        self.apply_pending_removals(&is_admin);
+        *self != before
+    }
+
+    /// Applies the held-back removals whose signer now holds the role for
+    /// them, keeping the latest few of the rest per channel.
+    fn apply_pending_removals(&mut self, is_admin: impl Fn(&str) -> bool) {
+        let (ready, mut pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_removals)
+            .into_iter()
+            .partition(|r| self.may_own(&r.payload.channel, &r.signer, &is_admin));
+        for removal in ready {
+            self.apply_removal(removal);
+        }
+        pending.sort_by_key(|r| std::cmp::Reverse(r.payload.hlc));
+        let mut per_channel: BTreeMap<String, usize> = BTreeMap::new();
+        pending.retain(|r| {
+            let count = per_channel.entry(r.payload.channel.clone()).or_default();
+            *count += 1;
+            *count <= MAX_PENDING_REMOVALS
+        });
+        self.pending_removals = pending;
+    }
+}
+
+/// Forgets unpins older than [`UNPIN_HORIZON_DAYS`] and the oldest pins
+/// beyond [`MAX_PINS`].
+fn prune_pins(pins: &mut BTreeMap<Uuid, Signed<Pin>>) {
+    let horizon = Hlc::from_datetime(Utc::now() - Duration::days(UNPIN_HORIZON_DAYS));
+    pins.retain(|_, pin| pin.payload.pinned || pin.payload.hlc >= horizon);
+    let mut pinned: Vec<(Hlc, String, Uuid)> = pins
+        .values()
+        .filter(|pin| pin.payload.pinned)
+        .map(|pin| (pin.payload.hlc, pin.signer.clone(), pin.payload.message_id))
+        .collect();
+    if let Some(excess) = pinned.len().checked_sub(MAX_PINS) {
+        pinned.sort();
+        for (_, _, message_id) in &pinned[..excess] {
+            pins.remove(message_id);
+        }
+    }
+}
+
+/// Whether anyone may manage a channel: one nobody holds a role in and that
+/// predates roles. The default channel is never open to all.
+fn open_to_all(id: &str, roles: &BTreeMap<String, ChannelRole>) -> bool {
+    roles.is_empty() && id != DEFAULT_CHANNEL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hlc(wall: i64) -> Hlc {
+        Hlc { wall, logical: 0 }
+    }
+
+    /// Signatures are checked before merging, so tests sign with placeholders.
+    fn signed<T>(payload: T, signer: &str) -> Signed<T> {
+        Signed {
+            payload,
+            signer: signer.to_string(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    fn no_admins(_: &str) -> bool {
+        false
+    }
+
+    fn create(set: &mut ChannelSet, name: &str, creator: &str, wall: i64) -> String {
+        let id = set.new_id(name);
+        set.add(&id);
+        set.set_meta(signed(Channel::new(&id, name, creator, hlc(wall)), creator));
+        id
+    }
+
+    #[test]
+    fn concurrent_creates_of_a_name_converge() {
+        let mut a = ChannelSet::default();
+        let mut b = ChannelSet::default();
+        let alice = create(&mut a, "foo", "alice", 1);
+        let bob = create(&mut b, "foo", "bob", 2);
+        assert_ne!(alice, bob);
+
+        let (a_before, b_before) = (a.clone(), b.clone());
+        a.merge(&b_before, no_admins);
+        b.merge(&a_before, no_admins);
+        assert_eq!(a, b);
+        assert_eq!(a.ids().len(), 2);
+        assert_eq!(a.describe(&alice).creator.as_deref(), Some("alice"));
+        assert_eq!(a.describe(&bob).creator.as_deref(), Some("bob"));
+        assert_eq!(a.find_by_name("foo"), b.find_by_name("foo"));
+    }
+
+    #[test]
+    fn removal_arriving_before_its_grant_converges() {
+        let mut base = ChannelSet::default();
+        let id = create(&mut base, "foo", "alice", 1);
+
+        let mut granted = base.clone();
+        granted.add_grant(signed(
+            RoleGrant::new(&id, "bob", Some(ChannelRole::Owner), hlc(2)),
+            "alice",
+        ));
+        let mut removed = base.clone();
+        let removal = removed.removal(&id, hlc(3)).unwrap();
+        removed.apply_removal(signed(removal, "bob"));
+
+        let mut x = base.clone();
+        x.merge(&removed, no_admins);
+        assert!(x.contains(&id));
+        x.merge(&granted, no_admins);
+
+        let mut y = base.clone();
+        y.merge(&granted, no_admins);
+        y.merge(&removed, no_admins);
+
+        assert!(!x.contains(&id));
+        assert!(!y.contains(&id));
+        assert_eq!(x, y);
+    }
+
+    #[test]
+    fn unauthorized_removal_is_held_back() {
+        let mut base = ChannelSet::default();
+        let id = create(&mut base, "foo", "alice", 1);
+        let mut forged = base.clone();
+        let removal = forged.removal(&id, hlc(2)).unwrap();
+        forged.apply_removal(signed(removal, "mallory"));
+
+        base.merge(&forged, no_admins);
+        assert!(base.contains(&id));
+    }
+
+    #[test]
+    fn new_channels_need_their_creator_and_bounded_fields() {
+        let set = ChannelSet::default();
+        let mut unowned = Channel::new("foo-1", "foo", "mallory", hlc(1));
+        unowned.creator = None;
+        assert!(!set.may_update(&signed(unowned, "mallory"), no_admins));
+        let claimed = Channel::new("foo-2", "foo", "alice", hlc(1));
+        assert!(!set.may_update(&signed(claimed, "mallory"), no_admins));
+        let mut own = Channel::new("foo-3", "foo", "mallory", hlc(1));
+        assert!(set.may_update(&signed(own.clone(), "mallory"), no_admins));
+
+        own.topic = "x".repeat(MAX_TOPIC_CHARS + 1);
+        let mut remote = ChannelSet::default();
+        remote.add("foo-3");
+        remote.set_meta(signed(own, "mallory"));
+        let mut local = ChannelSet::default();
+        local.merge(&remote, no_admins);
+        assert!(local.meta("foo-3").is_none());
+    }
+
+    #[test]
+    fn merging_split_parts_matches_merging_whole() {
+        let mut source = ChannelSet::default();
+        let foo = create(&mut source, "foo", "alice", 1);
+        create(&mut source, "bar", "bob", 2);
+        source.add_grant(signed(
+            RoleGrant::new(&foo, "carol", Some(ChannelRole::Moderator), hlc(3)),
+            "alice",
+        ));
+        let removal = source.removal(&foo, hlc(4)).unwrap();
+        source.apply_removal(signed(removal, "alice"));
+
+        let mut whole = ChannelSet::default();
+        whole.merge(&source, no_admins);
+        let mut parts = ChannelSet::default();
+        for part in source.split().iter().rev() {
+            parts.merge(part, no_admins);
+        }
+        assert_eq!(whole, parts);
+        assert_eq!(parts.ids().len(), 1);
+    }
+
+    #[test]
+    fn pins_beyond_the_limit_drop_the_oldest() {
+        let mut set = ChannelSet::default();
+        let now = Utc::now().timestamp_millis();
+        let pin = |wall: i64, pinned: bool| {
+            signed(
+                Pin {
+                    channel: "foo".to_string(),
+                    message_id: Uuid::new_v4(),
+                    pinned,
+                    hlc: hlc(wall),
+                },
+                "alice",
+            )
+        };
+        let first = pin(now, true);
+        assert!(set.set_pin(first.clone()));
+        for i in 1..=MAX_PINS as i64 {
+            assert!(set.set_pin(pin(now + i, true)));
+        }
+        assert_eq!(set.pins("foo").len(), MAX_PINS);
+        assert!(!set.is_pinned("foo", first.payload.message_id));
+        assert!(!set.set_pin(first));
+
+        let old_unpin = pin(now - (UNPIN_HORIZON_DAYS + 1) * 24 * 60 * 60 * 1000, false);
+        assert!(!set.set_pin(old_unpin));
+    }
+
+    #[test]
+    fn default_channel_is_not_open_to_all() {
+        let mut set = ChannelSet::default();
+        set.add(DEFAULT_CHANNEL);
+        set.add("legacy");
+        assert!(!set.may_manage(DEFAULT_CHANNEL, "mallory", no_admins));
+        assert!(set.may_manage(DEFAULT_CHANNEL, "admin", |peer| peer == "admin"));
+        assert!(set.may_manage("legacy", "mallory", no_admins));
+
+        let mut forged = Channel::bare(DEFAULT_CHANNEL);
+        forged.topic = "defaced".to_string();
+        assert!(!set.may_update(&signed(forged, "mallory"), no_admins));
+    }
+}
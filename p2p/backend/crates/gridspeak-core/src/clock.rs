@@ -0,0 +1,184 @@
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// How far ahead of the local wall clock a remote timestamp may pull the clock.
+const MAX_DRIFT_MS: i64 = 5 * 60 * 1000;
+
+/// Hybrid logical clock timestamp: wall-clock milliseconds plus a logical
+/// counter that orders events sharing (or lagging behind) the same millisecond.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Hlc {
+    pub wall: i64,
+    pub logical: u32,
+}
+
+impl Hlc {
+    /// Approximates an HLC for events from nodes that predate clocks.
+    pub fn from_datetime(time: DateTime<Utc>) -> Self {
+        Self {
+            wall: time.timestamp_millis(),
+            logical: 0,
+        }
+    }
+
+    /// The smallest stamp after this one; a full logical counter carries
+    /// into the next millisecond.
+    pub fn next(self) -> Hlc {
+        match self.logical.checked_add(1) {
+            Some(logical) => Hlc {
+                wall: self.wall,
+                logical,
+            },
+            None => Hlc {
+                wall: self.wall + 1,
+                logical: 0,
+            },
+        }
+    }
+
+    /// This stamp, or the drift bound if it lies further in the future, so a
+    /// peer cannot place events arbitrarily far ahead.
+    pub fn clamped(self) -> Hlc {
+        let bound = Utc::now().timestamp_millis() + MAX_DRIFT_MS;
+        if self.wall > bound {
+            Hlc {
+                wall: bound,
+                logical: 0,
+            }
+        } else {
+            self
+        }
+    }
+}
+
+/// Issues monotonically increasing [`Hlc`] timestamps for one node.
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    last: Mutex<Hlc>,
+}
+
+impl HybridClock {
+    /// Timestamps a local event.
+    pub fn tick(&self) -> Hlc {
+        let now = Utc::now().timestamp_millis();
+        let mut last = self.last.lock();
+        *last = if now > last.wall {
+            Hlc {
+                wall: now,
+                logical: 0,
+            }
+        } else {
+            last.next()
+        };
+        *last
+    }
+
+    /// Advances the clock past a timestamp received from a peer so that local
+    /// events issued afterwards order after it. Remote timestamps further in
+    /// the future than the drift bound only advance the clock up to that bound.
+    pub fn observe(&self, remote: Hlc) -> Hlc {
+        let now = Utc::now().timestamp_millis();
+        let remote = remote.clamped();
+        let mut last = self.last.lock();
+        let wall = now.max(last.wall).max(remote.wall);
+        *last = if wall == last.wall && wall == remote.wall {
+            (*last).max(remote).next()
+        } else if wall == last.wall {
+            last.next()
+        } else if wall == remote.wall {
+            remote.next()
+        } else {
+            Hlc { wall, logical: 0 }
+        };
+        *last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> i64 {
+        Utc::now().timestamp_millis()
+    }
+
+    #[test]
+    fn tick_is_strictly_increasing() {
+        let clock = HybridClock::default();
+        let mut last = clock.tick();
+        for _ in 0..1000 {
+            let next = clock.tick();
+            assert!(next > last);
+            last = next;
+        }
+    }
+
+    #[test]
+    fn observe_orders_after_remote() {
+        let clock = HybridClock::default();
+        let remote = Hlc {
+            wall: now() + 60_000,
+            logical: 7,
+        };
+        let observed = clock.observe(remote);
+        assert_eq!(
+            observed,
+            Hlc {
+                wall: remote.wall,
+                logical: 8
+            }
+        );
+        assert!(clock.tick() > remote);
+    }
+
+    #[test]
+    fn observe_keeps_local_lead() {
+        let clock = HybridClock::default();
+        let local = clock.tick();
+        let observed = clock.observe(Hlc {
+            wall: 0,
+            logical: 3,
+        });
+        assert!(observed > local);
+    }
+
+    #[test]
+    fn far_future_stamps_are_clamped() {
+        let clock = HybridClock::default();
+        let far = Hlc {
+            wall: i64::MAX,
+            logical: u32::MAX,
+        };
+        let clamped = far.clamped();
+        assert!(clamped.wall <= now() + MAX_DRIFT_MS);
+        assert_eq!(clamped.logical, 0);
+        assert!(clock.observe(far).wall <= now() + MAX_DRIFT_MS);
+        let near = Hlc {
+            wall: now(),
+            logical: 1,
+        };
+        assert_eq!(near.clamped(), near);
+    }
+
+    #[test]
+    fn logical_overflow_carries_into_wall() {
+        let full = Hlc {
+            wall: now() + 1000,
+            logical: u32::MAX,
+        };
+        assert_eq!(
+            full.next(),
+            Hlc {
+                wall: full.wall + 1,
+                logical: 0
+            }
+        );
+        let clock = HybridClock::default();
+        let observed = clock.observe(full);
+        assert!(observed > full);
+        assert!(clock.tick() > observed);
+    }
+}
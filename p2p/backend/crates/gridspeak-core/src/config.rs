@@ -4,10 +4,12 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use dirs::data_dir;
 use serde::{Deserialize, Serialize};
 
+use crate::{RetentionConfig, channels::validate_channel_name};
+
 fn default_data_dir() -> PathBuf {
     data_dir()
         .unwrap_or_else(|| PathBuf::from("."))
@@ -26,6 +28,19 @@ fn default_channels() -> Vec<String> {
     vec!["general".to_string()]
 }
 
+/// What to do with the message file of a channel once it is removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovedChannelPolicy {
+    /// Leave `messages-<name>.json` in place; history returns if the channel is re-created.
+    Keep,
+    /// Move the file to `<data_dir>/archive/` with a timestamp suffix.
+    #[default]
+    Archive,
+    /// Delete the file.
+    Delete,
+}
+
 /// Configuration describing how a node participates in the mesh network.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
@@ -39,6 +54,14 @@ pub struct NodeConfig {
     pub nickname: String,
     #[serde(default = "default_channels")]
     pub channels: Vec<String>,
+    /// PeerIds trusted to delete other peers' messages.
+    #[serde(default)]
+    pub moderators: Vec<String>,
+    #[serde(default)]
+    pub removed_channel_files: RemovedChannelPolicy,
+    /// How much history channels keep; unlimited by default.
+    #[serde(default, skip_serializing_if = "RetentionConfig::is_empty")]
+    pub retention: RetentionConfig,
 }
 
 impl Default for NodeConfig {
@@ -49,6 +72,9 @@ impl Default for NodeConfig {
             bootstrap_nodes: vec![],
             nickname: default_nickname(),
             channels: default_channels(),
+            moderators: vec![],
+            removed_channel_files: RemovedChannelPolicy::default(),
+            retention: RetentionConfig::default(),
         }
     }
 }
@@ -61,12 +87,7 @@ impl NodeConfig {
     /// Appends a channel name if not present and saves config.
     pub fn add_channel(&mut self, name: &str, path: impl AsRef<Path>) -> Result<()> {
         let name = name.trim().to_lowercase();
-        if name.is_empty() || name.len() > 64 {
-            return Err(anyhow::anyhow!("invalid channel name"));
-        }
-        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
-            return Err(anyhow::anyhow!("channel name must be alphanumeric, - or _"));
-        }
+        validate_channel_name(&name)?;
         if self.channels.contains(&name) {
             return Ok(());
         }
@@ -74,12 +95,46 @@ impl NodeConfig {
         self.save(path)
     }
 
+    /// Lowercases hand-edited channel names the way `add_channel` does,
+    /// rejecting names that are still invalid and dropping duplicates.
+    fn normalize_channels(&mut self) -> Result<()> {
+        let mut names = Vec::with_capacity(self.channels.len());
+        for name in &self.channels {
+            let name = name.trim().to_lowercase();
+            validate_channel_name(&name)
+                .with_context(|| format!("invalid channel {name:?} in config"))?;
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        self.channels = names;
+        Ok(())
+    }
+
     /// Removes a channel by name and saves config.
     pub fn remove_channel(&mut self, name: &str, path: impl AsRef<Path>) -> Result<()> {
         self.channels.retain(|c| c != name);
         self.save(path)
     }
 
+    /// Replaces the channel list and saves config when it changed.
+    pub fn set_channels(&mut self, names: Vec<String>, path: impl AsRef<Path>) -> Result<()> {
+        if self.channels == names {
+            return Ok(());
+        }
+        self.channels = names;
+        self.save(path)
+    }
+
+    /// Replaces the nickname and saves config when it changed.
+    pub fn set_nickname(&mut self, nickname: &str, path: impl AsRef<Path>) -> Result<()> {
+        if self.nickname == nickname {
+            return Ok(());
+        }
+        self.nickname = nickname.to_string();
+        self.save(path)
+    }
+
     /// Persists configuration to disk, creating parent directories when missing.
     pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
         if let Some(parent) = path.as_ref().parent() {
@@ -98,7 +153,10 @@ pub fn load_or_create_config(path: impl AsRef<Path>) -> Result<NodeConfig> {
     let path = path.as_ref();
     if path.exists() {
         let contents = fs::read_to_string(path)?;
-        Ok(toml::from_str(&contents)?)
+        let mut config: NodeConfig = toml::from_str(&contents)?;
+        config.retention.validate()?;
+        config.normalize_channels()?;
+        Ok(config)
     } else {
         let config = NodeConfig::default();
         config.save(path)?;
@@ -0,0 +1,165 @@
+//! Encryption primitives for private channels and direct messages.
+//!
+//! Each node's X25519 key is derived from its ed25519 identity, so a peer's
+//! encryption key follows from its PeerId without any key exchange. Group keys
+//! are wrapped to members with a static-static Diffie-Hellman secret run
+//! through HKDF; content is sealed with ChaCha20-Poly1305.
+
+use anyhow::{Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD as B64};
+use chacha20poly1305::{
+    AeadCore, ChaCha20Poly1305, KeyInit,
+    aead::{Aead, OsRng, Payload},
+};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+pub use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Symmetric key shared by the members of a private channel.
+pub type GroupKey = [u8; 32];
+
+/// Ciphertext with the nonce it was sealed under (both base64).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sealed {
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// X25519 secret for an ed25519 seed, using the same clamped scalar as the
+/// signing key so it pairs with [`public_from_ed25519`].
+pub fn secret_from_ed25519(seed: &[u8]) -> StaticSecret {
+    let hash = Sha512::digest(seed);
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&hash[..32]);
+    StaticSecret::from(scalar)
+}
+
+/// X25519 public key for an ed25519 public key.
+pub fn public_from_ed25519(public: [u8; 32]) -> Option<PublicKey> {
+    let point = CompressedEdwardsY(public).decompress()?;
+    Some(PublicKey::from(point.to_montgomery().to_bytes()))
+}
+
+pub fn random_key() -> GroupKey {
+    ChaCha20Poly1305::generate_key(&mut OsRng).into()
+}
+
+/// Encrypts `plaintext`, binding it to `aad`.
+pub fn seal(key: &GroupKey, plaintext: &[u8], aad: &[u8]) -> Result<Sealed> {
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("encryption failed"))?;
+    Ok(Sealed {
+        nonce: B64.encode(nonce),
+        ciphertext: B64.encode(ciphertext),
+    })
+}
+
+pub fn open(key: &GroupKey, sealed: &Sealed, aad: &[u8]) -> Result<Vec<u8>> {
+    let nonce = B64.decode(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(anyhow!("invalid nonce"));
+    }
+    let ciphertext = B64.decode(&sealed.ciphertext)?;
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(
+            nonce.as_slice().into(),
+            Payload {
+                msg: &ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| anyhow!("decryption failed"))
+}
+
+/// Key both sides of a static-static exchange derive for `context`.
+pub fn pair_key(secret: &StaticSecret, peer: &PublicKey, context: &[u8]) -> Result<GroupKey> {
+    let shared = secret.diffie_hellman(peer);
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, shared.as_bytes())
+        .expand(context, &mut key)
+        .map_err(|_| anyhow!("key derivation failed"))?;
+    Ok(key)
+}
+
+/// Wraps a group key for one recipient; `context` should name the channel,
+/// key epoch and recipient so wrapped keys cannot be replayed elsewhere.
+pub fn wrap_key(
+    secret: &StaticSecret,
+    recipient: &PublicKey,
+    context: &[u8],
+    key: &GroupKey,
+) -> Result<Sealed> {
+    seal(&pair_key(secret, recipient, context)?, key, context)
+}
+
+/// Unwraps a group key sent by `sender` with [`wrap_key`].
+pub fn unwrap_key(
+    secret: &StaticSecret,
+    sender: &PublicKey,
+    context: &[u8],
+    sealed: &Sealed,
+) -> Result<GroupKey> {
+    let key = open(&pair_key(secret, sender, context)?, sealed, context)?;
+    key.try_into().map_err(|_| anyhow!("invalid group key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use curve25519_dalek::EdwardsPoint;
+
+    use super::*;
+
+    /// A peer's X25519 secret and public key, derived as from its identity.
+    fn peer(seed: u8) -> (StaticSecret, PublicKey) {
+        let secret = secret_from_ed25519(&[seed; 32]);
+        let public = PublicKey::from(&secret);
+        (secret, public)
+    }
+
+    #[test]
+    fn sealed_content_opens_only_with_its_key_and_aad() {
+        let key = random_key();
+        let sealed = seal(&key, b"hello", b"channel/1").unwrap();
+        assert_eq!(open(&key, &sealed, b"channel/1").unwrap(), b"hello");
+        assert!(open(&key, &sealed, b"channel/2").is_err());
+        assert!(open(&random_key(), &sealed, b"channel/1").is_err());
+    }
+
+    #[test]
+    fn wrapped_keys_unwrap_only_for_their_context() {
+        let (alice, alice_public) = peer(1);
+        let (bob, bob_public) = peer(2);
+        let key = random_key();
+        let wrapped = wrap_key(&alice, &bob_public, b"secret/1/bob", &key).unwrap();
+        assert_eq!(
+            unwrap_key(&bob, &alice_public, b"secret/1/bob", &wrapped).unwrap(),
+            key
+        );
+        assert!(unwrap_key(&bob, &alice_public, b"secret/2/bob", &wrapped).is_err());
+        let (_, mallory_public) = peer(3);
+        assert!(unwrap_key(&bob, &mallory_public, b"secret/1/bob", &wrapped).is_err());
+    }
+
+    #[test]
+    fn identity_keys_convert_to_matching_x25519_keys() {
+        let seed = [7u8; 32];
+        let hash = Sha512::digest(seed);
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&hash[..32]);
+        let ed25519_public = EdwardsPoint::mul_base_clamped(scalar).compress().to_bytes();
+        let converted = public_from_ed25519(ed25519_public).unwrap();
+        assert_eq!(converted, PublicKey::from(&secret_from_ed25519(&seed)));
+    }
+}
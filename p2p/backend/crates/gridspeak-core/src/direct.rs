@@ -0,0 +1,236 @@
+//! Direct messages between two peers.
+//!
+//! A DM is an ordinary [`ChatMessage`] sealed with a key only its two peers
+//! can derive (static-static X25519 between their identity keys), so nodes
+//! that hold it while the recipient is offline only ever see ciphertext.
+
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    ChatMessage,
+    crypto::{self, GroupKey, PublicKey, StaticSecret},
+    persist,
+};
+
+/// How long an undelivered DM is kept for its recipient.
+const OUTBOX_DAYS: i64 = 7;
+
+/// Most DMs held on behalf of other peers; the oldest are dropped first.
+const MAX_RELAYED: usize = 500;
+
+/// Most DMs held on behalf of any one sender, so a single peer cannot fill
+/// the relay queue; its oldest are dropped first.
+const MAX_RELAYED_PER_SENDER: usize = 50;
+
+/// A sealed message addressed to one peer; the sender is `message.peer_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectMessage {
+    pub to: String,
+    pub message: ChatMessage,
+}
+
+impl DirectMessage {
+    pub fn sender(&self) -> Option<&str> {
+        self.message.peer_id.as_deref()
+    }
+}
+
+/// Key for the conversation between `local` and `peer`; both sides derive the
+/// same key, and a DM that opens with it can only come from one of them.
+pub fn conversation_key(
+    secret: &StaticSecret,
+    local: &str,
+    peer: &str,
+    peer_key: &PublicKey,
+) -> Result<GroupKey> {
+    let (low, high) = if local <= peer {
+        (local, peer)
+    } else {
+        (peer, local)
+    };
+    crypto::pair_key(
+        secret,
+        peer_key,
+        format!("gridspeak-dm/{low}/{high}").as_bytes(),
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Queued {
+    dm: DirectMessage,
+    /// Held for another peer rather than sent by this node.
+    relayed: bool,
+    queued_at: DateTime<Utc>,
+}
+
+/// DMs waiting for their recipient to come online: ones this node sent and
+/// ones it holds for other peers.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Outbox {
+    entries: Vec<Queued>,
+}
+
+impl Outbox {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "outbox")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    /// Queues a DM unless it is already queued. Returns `false` for duplicates.
+    pub fn push(&mut self, dm: DirectMessage, relayed: bool) -> bool {
+        if self
+            .entries
+            .iter()
+            .any(|q| q.dm.message.id == dm.message.id)
+        {
+            return false;
+        }
+        self.entries.push(Queued {
+            dm,
+            relayed,
+            queued_at: Utc::now(),
+        });
+        let sender = self
+            .entries
+            .last()
+            .and_then(|q| q.dm.sender())
+            .map(str::to_string);
+        let from_sender = |q: &Queued| q.relayed && q.dm.sender() == sender.as_deref();
+        if self.entries.iter().filter(|q| from_sender(q)).count() > MAX_RELAYED_PER_SENDER
+            && let Some(oldest) = self.entries.iter().position(from_sender)
+        {
+            self.entries.remove(oldest);
+        }
+        let relayed_count = self.entries.iter().filter(|q| q.relayed).count();
+        if relayed_count > MAX_RELAYED
+            && let Some(oldest) = self.entries.iter().position(|q| q.relayed)
+        {
+            self.entries.remove(oldest);
+        }
+        true
+    }
+
+    /// Drops queued DMs to `peer` that it reports as delivered. Returns
+    /// whether any were dropped.
+    pub fn forget_delivered(&mut self, peer: &str, ids: &[Uuid]) -> bool {
+        let before = self.entries.len();
+        self.entries
+            .retain(|q| q.dm.to != peer || !ids.contains(&q.dm.message.id));
+        self.entries.len() != before
+    }
+
+    /// Removes and returns the DMs addressed to `peer`, oldest first.
+    pub fn take_for(&mut self, peer: &str) -> Vec<DirectMessage> {
+        let (taken, kept) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|q| q.dm.to == peer);
+        self.entries = kept;
+        taken.into_iter().map(|q: Queued| q.dm).collect()
+    }
+
+    /// Drops DMs that have waited too long. Returns whether any were dropped.
+    pub fn expire(&mut self) -> bool {
+        let cutoff = Utc::now() - Duration::days(OUTBOX_DAYS);
+        let before = self.entries.len();
+        self.entries.retain(|q| q.queued_at > cutoff);
+        self.entries.len() != before
+    }
+
+    /// Number of queued DMs sent by this node to `peer`.
+    pub fn pending_for(&self, peer: &str) -> usize {
+        self.entries
+            .iter()
+            .filter(|q| !q.relayed && q.dm.to == peer)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dm(from: &str, to: &str) -> DirectMessage {
+        DirectMessage {
+            to: to.to_string(),
+            message: ChatMessage::new(from, "sealed").with_peer_id(from),
+        }
+    }
+
+    fn ids(outbox: &Outbox) -> Vec<Uuid> {
+        outbox.entries.iter().map(|q| q.dm.message.id).collect()
+    }
+
+    #[test]
+    fn relayed_dms_are_capped_per_sender_and_overall() {
+        let mut outbox = Outbox::default();
+        let own = dm("me", "bob");
+        assert!(outbox.push(own.clone(), false));
+        assert!(!outbox.push(own.clone(), false));
+
+        let first = dm("mallory", "bob");
+        outbox.push(first.clone(), true);
+        for _ in 0..MAX_RELAYED_PER_SENDER {
+            outbox.push(dm("mallory", "bob"), true);
+        }
+        assert!(!ids(&outbox).contains(&first.message.id));
+        assert!(ids(&outbox).contains(&own.message.id));
+
+        for sender in 0..MAX_RELAYED / MAX_RELAYED_PER_SENDER {
+            for _ in 0..MAX_RELAYED_PER_SENDER {
+                outbox.push(dm(&format!("peer-{sender}"), "bob"), true);
+            }
+        }
+        assert_eq!(
+            outbox.entries.iter().filter(|q| q.relayed).count(),
+            MAX_RELAYED
+        );
+        assert_eq!(outbox.pending_for("bob"), 1);
+    }
+
+    #[test]
+    fn delivered_dms_are_forgotten_only_for_their_recipient() {
+        let mut outbox = Outbox::default();
+        let to_bob = dm("me", "bob");
+        let to_carol = dm("me", "carol");
+        outbox.push(to_bob.clone(), false);
+        outbox.push(to_carol.clone(), false);
+
+        assert!(!outbox.forget_delivered("bob", &[to_carol.message.id]));
+        assert!(outbox.forget_delivered("bob", &[to_bob.message.id]));
+        assert_eq!(ids(&outbox), [to_carol.message.id]);
+        assert_eq!(outbox.take_for("carol").len(), 1);
+        assert!(outbox.entries.is_empty());
+    }
+
+    #[test]
+    fn dms_waiting_too_long_expire() {
+        let mut outbox = Outbox::default();
+        outbox.push(dm("me", "bob"), false);
+        outbox.push(dm("me", "carol"), false);
+        outbox.entries[0].queued_at = Utc::now() - Duration::days(OUTBOX_DAYS + 1);
+
+        assert!(outbox.expire());
+        assert_eq!(outbox.pending_for("bob"), 0);
+        assert_eq!(outbox.pending_for("carol"), 1);
+        assert!(!outbox.expire());
+    }
+
+    #[test]
+    fn both_sides_derive_the_same_conversation_key() {
+        let alice = crypto::secret_from_ed25519(&[1; 32]);
+        let bob = crypto::secret_from_ed25519(&[2; 32]);
+        let alice_public = PublicKey::from(&alice);
+        let bob_public = PublicKey::from(&bob);
+        let ours = conversation_key(&alice, "alice", "bob", &bob_public).unwrap();
+        let theirs = conversation_key(&bob, "bob", "alice", &alice_public).unwrap();
+        assert_eq!(ours, theirs);
+    }
+}
@@ -4,10 +4,49 @@
 //! command-line and GUI front-ends can re-use the same configuration and
 //! storage primitives.
 
+pub mod blobs;
+pub mod channels;
+pub mod clock;
 pub mod config;
+pub mod crypto;
+pub mod direct;
+pub mod markers;
+pub mod mentions;
 pub mod message;
+pub mod moderation;
+pub mod notify;
+pub mod persist;
+pub mod presence;
+pub mod profile;
+pub mod retention;
+pub mod roles;
+pub mod schedule;
+pub mod search;
+pub mod signed;
 pub mod storage;
 
-pub use config::{NodeConfig, load_or_create_config};
-pub use message::{Attachment, ChatMessage};
-pub use storage::ChatStore;
+pub use blobs::{BlobInfo, BlobStore, BlobWriter};
+pub use channels::{
+    Channel, ChannelAlias, ChannelKey, ChannelRemoval, ChannelSet, DEFAULT_CHANNEL, MAX_PINS, Pin,
+    validate_channel_name,
+};
+pub use clock::{Hlc, HybridClock};
+pub use config::{NodeConfig, RemovedChannelPolicy, load_or_create_config};
+pub use direct::{DirectMessage, Outbox};
+pub use markers::{ReadMarker, ReadMarkers};
+pub use mentions::{Mention, parse_mentions};
+pub use message::{
+    Attachment, ChatMessage, MessageEdit, MessageRevision, Reaction, ReactionSummary,
+    SealedContent, Tombstone,
+};
+pub use moderation::{ModerationAction, ModerationList, Sanction};
+pub use notify::{ChannelNotifications, NotificationReason, NotificationSettings, NotifyLevel};
+pub use persist::{load_json, save_json, write_atomic};
+pub use presence::{Heartbeat, PresenceBook, PresenceEntry, PresenceStatus};
+pub use profile::{Avatar, Profile, ProfileBook};
+pub use retention::{RetentionConfig, RetentionRule};
+pub use roles::{ChannelRole, RoleGrant};
+pub use schedule::{Schedule, ScheduledMessage};
+pub use search::{SearchHit, SearchIndex, SearchQuery};
+pub use signed::{Signed, signing_bytes};
+pub use storage::{ChatStore, Pruned, retire_store_file};
@@ -0,0 +1,67 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{ChatMessage, Hlc, persist};
+
+/// The last message the local user has read in a channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadMarker {
+    pub message_id: Uuid,
+    /// Position of the message in the channel order, kept so the marker still
+    /// works once the message itself is deleted.
+    pub hlc: Hlc,
+    pub peer_id: String,
+    pub read_at: DateTime<Utc>,
+}
+
+impl ReadMarker {
+    pub fn at(message: &ChatMessage) -> Self {
+        let (hlc, peer_id, message_id) = message.order_key();
+        Self {
+            message_id,
+            hlc,
+            peer_id: peer_id.to_string(),
+            read_at: Utc::now(),
+        }
+    }
+
+    /// Whether the message comes at or before the marker.
+    pub fn covers(&self, message: &ChatMessage) -> bool {
+        message.order_key() <= (self.hlc, self.peer_id.as_str(), self.message_id)
+    }
+}
+
+/// Read markers per channel id. They are local to the node and never sent to peers.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReadMarkers {
+    markers: BTreeMap<String, ReadMarker>,
+}
+
+impl ReadMarkers {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "read markers")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    pub fn get(&self, channel: &str) -> Option<&ReadMarker> {
+        self.markers.get(channel)
+    }
+
+    /// Moves the channel's marker, backwards too so a channel can be marked unread.
+    pub fn set(&mut self, channel: impl Into<String>, marker: ReadMarker) {
+        self.markers.insert(channel.into(), marker);
+    }
+
+    /// Whether the message has not been read yet.
+    pub fn is_unread(&self, channel: &str, message: &ChatMessage) -> bool {
+        self.get(channel)
+            .is_none_or(|marker| !marker.covers(message))
+    }
+}
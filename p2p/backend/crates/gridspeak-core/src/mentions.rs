@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Something a message refers to, resolved by the sending node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Mention {
+    /// A peer, written `@display-name` or `@peer-id`.
+    User { peer_id: String, name: String },
+    /// A channel, written `#name`; `channel` is its id.
+    Channel { channel: String, name: String },
+    /// `@here`: peers that are online when the message arrives.
+    Here,
+    /// `@everyone`: every peer that can read the channel.
+    Everyone,
+}
+
+impl Mention {
+    /// Whether the mention addresses a whole channel rather than one peer.
+    pub fn is_broadcast(&self) -> bool {
+        matches!(self, Mention::Here | Mention::Everyone)
+    }
+}
+
+/// The `@` and `#` tokens of a body, in order, as (sigil, name). A token runs
+/// over letters, digits, `_`, `-` and `.`, and must not follow a word
+/// character, so e-mail addresses are not mentions.
+pub fn mention_tokens(body: &str) -> Vec<(char, &str)> {
+    let mut tokens = Vec::new();
+    let mut previous: Option<char> = None;
+    for (at, sigil) in body.char_indices() {
+        let follows_word = previous.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        previous = Some(sigil);
+        if !matches!(sigil, '@' | '#') || follows_word {
+            continue;
+        }
+        let rest = &body[at + 1..];
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.')))
+            .unwrap_or(rest.len());
+        let name = rest[..end].trim_end_matches(['.', '-']);
+        if !name.is_empty() {
+            tokens.push((sigil, name));
+        }
+    }
+    tokens
+}
+
+/// Parses the mentions in a body. `resolve_user` maps a name to the PeerIds it
+/// stands for and `resolve_channel` maps a channel name to its id; names that
+/// resolve to nothing are left as plain text. Each mention is listed once.
+pub fn parse_mentions(
+    body: &str,
+    resolve_user: impl Fn(&str) -> Vec<String>,
+    resolve_channel: impl Fn(&str) -> Option<String>,
+) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    for (sigil, name) in mention_tokens(body) {
+        let found = match (sigil, name.to_lowercase().as_str()) {
+            ('@', "here") => vec![Mention::Here],
+            ('@', "everyone") => vec![Mention::Everyone],
+            ('@', _) => resolve_user(name)
+                .into_iter()
+                .map(|peer_id| Mention::User {
+                    peer_id,
+                    name: name.to_string(),
+                })
+                .collect(),
+            (_, _) => resolve_channel(name)
+                .map(|channel| Mention::Channel {
+                    channel,
+                    name: name.to_string(),
+                })
+                .into_iter()
+                .collect(),
+        };
+        for mention in found {
+            if !mentions.contains(&mention) {
+                mentions.push(mention);
+            }
+        }
+    }
+    mentions
+}
@@ -1,7 +1,66 @@
-use chrono::{DateTime, Utc};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::{
+    Hlc, Mention,
+    crypto::{self, GroupKey, Sealed},
+};
+
+/// Part of a message in a private channel, encrypted with the channel's group
+/// key for `epoch`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SealedContent {
+    pub epoch: u32,
+    #[serde(flatten)]
+    pub sealed: Sealed,
+}
+
+impl SealedContent {
+    /// Seals one part (`"body"`, `"attachments"` or `"mentions"`) of a message; the message
+    /// id and part name are bound to the ciphertext.
+    pub fn seal(
+        message_id: Uuid,
+        part: &str,
+        epoch: u32,
+        key: &GroupKey,
+        plaintext: &[u8],
+    ) -> Result<Self> {
+        Ok(Self {
+            epoch,
+            sealed: crypto::seal(key, plaintext, &content_aad(message_id, part))?,
+        })
+    }
+
+    /// Decrypts the part with the first of the keys `key_for` returns for its
+    /// epoch that opens it; an epoch can have several after concurrent rotations.
+    pub fn open<K: IntoIterator<Item = GroupKey>>(
+        &self,
+        message_id: Uuid,
+        part: &str,
+        key_for: impl Fn(u32) -> K,
+    ) -> Option<Vec<u8>> {
+        let aad = content_aad(message_id, part);
+        key_for(self.epoch)
+            .into_iter()
+            .find_map(|key| crypto::open(&key, &self.sealed, &aad).ok())
+    }
+
+    /// Decrypts a sealed body.
+    pub fn open_text<K: IntoIterator<Item = GroupKey>>(
+        &self,
+        message_id: Uuid,
+        key_for: impl Fn(u32) -> K,
+    ) -> Option<String> {
+        String::from_utf8(self.open(message_id, "body", key_for)?).ok()
+    }
+}
+
+fn content_aad(message_id: Uuid, part: &str) -> Vec<u8> {
+    format!("{message_id}/{part}").into_bytes()
+}
+
 /// A single file/image/audio/video attachment (inline base64 for small files).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Attachment {
@@ -11,6 +70,12 @@ pub struct Attachment {
     pub filename: String,
     /// Inline data (base64). Keep small to avoid huge gossip payloads (e.g. < 500 KB total per message).
     pub data_base64: String,
+    /// Decoded size in bytes, when computed by the uploading node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Hex-encoded SHA-256 of the decoded data, when computed by the uploading node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
 }
 
 /// A replicated chat message shared over the mesh.
@@ -22,6 +87,38 @@ pub struct ChatMessage {
     pub timestamp: DateTime<Utc>,
     #[serde(default)]
     pub attachments: Vec<Attachment>,
+    /// PeerId of the node that published the message; only that node may edit it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    /// Time of the latest applied edit, if the body has been edited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// Message this one directly replies to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Uuid>,
+    /// First message of the thread this reply belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_root: Option<Uuid>,
+    /// Hybrid logical clock stamp used for ordering instead of `timestamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<Hlc>,
+    /// Encrypted body of a message in a private channel; `body` is then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_body: Option<SealedContent>,
+    /// Encrypted attachments of a message in a private channel; `attachments`
+    /// is then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_attachments: Option<SealedContent>,
+    /// Peers, channels and broadcasts the body mentions, resolved when sent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<Mention>,
+    /// Encrypted mentions of a message in a private channel; `mentions` is
+    /// then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_mentions: Option<SealedContent>,
+    /// Seconds after its clock stamp at which the message disappears.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u32>,
 }
 
 impl ChatMessage {
@@ -32,6 +129,16 @@ impl ChatMessage {
             body: body.into(),
             timestamp: Utc::now(),
             attachments: Vec::new(),
+            peer_id: None,
+            edited_at: None,
+            reply_to: None,
+            thread_root: None,
+            hlc: None,
+            sealed_body: None,
+            sealed_attachments: None,
+            mentions: Vec::new(),
+            sealed_mentions: None,
+            ttl_secs: None,
         }
     }
 
@@ -46,6 +153,327 @@ impl ChatMessage {
             body: body.into(),
             timestamp: Utc::now(),
             attachments,
+            peer_id: None,
+            edited_at: None,
+            reply_to: None,
+            thread_root: None,
+            hlc: None,
+            sealed_body: None,
+            sealed_attachments: None,
+            mentions: Vec::new(),
+            sealed_mentions: None,
+            ttl_secs: None,
+        }
+    }
+
+    /// Makes the message a reply to `parent`, joining the parent's thread.
+    pub fn in_reply_to(mut self, parent: &ChatMessage) -> Self {
+        self.reply_to = Some(parent.id);
+        self.thread_root = Some(parent.thread_root.unwrap_or(parent.id));
+        self
+    }
+
+    pub fn with_hlc(mut self, hlc: Hlc) -> Self {
+        self.hlc = Some(hlc);
+        self
+    }
+
+    /// Position in the total order all peers agree on: clock stamp, then the
+    /// publishing peer, then the message id.
+    pub fn order_key(&self) -> (Hlc, &str, Uuid) {
+        (
+            self.hlc
+                .unwrap_or_else(|| Hlc::from_datetime(self.timestamp)),
+            self.peer_id.as_deref().unwrap_or(&self.author),
+            self.id,
+        )
+    }
+
+    /// The message with its stamp limited to the clock's drift bound, as it
+    /// is stored: a peer's far-future stamp would otherwise order, retain and
+    /// expire by a time that has not come yet.
+    pub fn with_clamped_stamp(mut self) -> Self {
+        let stamp = self.order_key().0;
+        let clamped = stamp.clamped();
+        if clamped != stamp {
+            self.hlc = Some(clamped);
         }
+        self
+    }
+
+    pub fn with_mentions(mut self, mentions: Vec<Mention>) -> Self {
+        self.mentions = mentions;
+        self
     }
+
+    pub fn with_ttl(mut self, ttl_secs: Option<u32>) -> Self {
+        self.ttl_secs = ttl_secs;
+        self
+    }
+
+    /// When the message disappears: its own TTL or the channel's, whichever
+    /// is shorter, counted from its clock stamp. Stamps out of chrono's range
+    /// are never considered to expire.
+    pub fn expires_at(&self, channel_ttl: Option<u32>) -> Option<DateTime<Utc>> {
+        let ttl = match (self.ttl_secs, channel_ttl) {
+            (Some(own), Some(channel)) => own.min(channel),
+            (own, channel) => own.or(channel)?,
+        };
+        DateTime::from_timestamp_millis(self.order_key().0.wall)?
+            .checked_add_signed(Duration::seconds(i64::from(ttl)))
+    }
+
+    pub fn is_expired(&self, channel_ttl: Option<u32>) -> bool {
+        self.expires_at(channel_ttl)
+            .is_some_and(|at| at <= Utc::now())
+    }
+
+    /// Whether the message mentions the peer by name.
+    pub fn mentions_peer(&self, peer_id: &str) -> bool {
+        self.mentions
+            .iter()
+            .any(|m| matches!(m, Mention::User { peer_id: p, .. } if p == peer_id))
+    }
+
+    /// Marks the message as published by the given node.
+    pub fn with_peer_id(mut self, peer_id: impl Into<String>) -> Self {
+        self.peer_id = Some(peer_id.into());
+        self
+    }
+
+    /// Encrypts body, attachments and mentions with a private channel's group key.
+    pub fn seal(mut self, epoch: u32, key: &GroupKey) -> Result<Self> {
+        let body = std::mem::take(&mut self.body);
+        self.sealed_body = Some(SealedContent::seal(
+            self.id,
+            "body",
+            epoch,
+            key,
+            body.as_bytes(),
+        )?);
+        if !self.attachments.is_empty() {
+            let attachments = serde_json::to_vec(&std::mem::take(&mut self.attachments))?;
+            self.sealed_attachments = Some(SealedContent::seal(
+                self.id,
+                "attachments",
+                epoch,
+                key,
+                &attachments,
+            )?);
+        }
+        if !self.mentions.is_empty() {
+            let mentions = serde_json::to_vec(&std::mem::take(&mut self.mentions))?;
+            self.sealed_mentions = Some(SealedContent::seal(
+                self.id, "mentions", epoch, key, &mentions,
+            )?);
+        }
+        Ok(self)
+    }
+
+    /// Whether the message carries any unencrypted content; messages in a
+    /// private channel must not.
+    pub fn has_plaintext(&self) -> bool {
+        !self.body.is_empty() || !self.attachments.is_empty() || !self.mentions.is_empty()
+    }
+
+    /// Whether any part of the message is still encrypted.
+    pub fn is_sealed(&self) -> bool {
+        self.sealed_body.is_some()
+            || self.sealed_attachments.is_some()
+            || self.sealed_mentions.is_some()
+    }
+
+    /// Decrypts the sealed parts whose epoch `key_for` has a key for; parts
+    /// that cannot be decrypted stay sealed.
+    pub fn opened<K: IntoIterator<Item = GroupKey>>(mut self, key_for: impl Fn(u32) -> K) -> Self {
+        if let Some(body) = self
+            .sealed_body
+            .as_ref()
+            .and_then(|s| s.open_text(self.id, &key_for))
+        {
+            self.body = body;
+            self.sealed_body = None;
+        }
+        if let Some(attachments) = self
+            .sealed_attachments
+            .as_ref()
+            .and_then(|s| s.open(self.id, "attachments", &key_for))
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+        {
+            self.attachments = attachments;
+            self.sealed_attachments = None;
+        }
+        if let Some(mentions) = self
+            .sealed_mentions
+            .as_ref()
+            .and_then(|s| s.open(self.id, "mentions", &key_for))
+            .and_then(|raw| serde_json::from_slice(&raw).ok())
+        {
+            self.mentions = mentions;
+            self.sealed_mentions = None;
+        }
+        self
+    }
+}
+
+/// A replacement body for an existing message, signed by its publishing node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageEdit {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub body: String,
+    pub edited_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<Hlc>,
+    /// Encrypted body for messages in private channels; `body` is then empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_body: Option<SealedContent>,
+}
+
+impl MessageEdit {
+    pub fn new(message_id: Uuid, body: impl Into<String>, hlc: Hlc) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            message_id,
+            body: body.into(),
+            edited_at: Utc::now(),
+            hlc: Some(hlc),
+            sealed_body: None,
+        }
+    }
+
+    /// Encrypts the new body with a private channel's group key.
+    pub fn seal(mut self, epoch: u32, key: &GroupKey) -> Result<Self> {
+        let body = std::mem::take(&mut self.body);
+        self.sealed_body = Some(SealedContent::seal(
+            self.message_id,
+            "body",
+            epoch,
+            key,
+            body.as_bytes(),
+        )?);
+        Ok(self)
+    }
+
+    /// Later edits win; the id breaks ties between concurrent edits.
+    pub fn order_key(&self) -> (Hlc, Uuid) {
+        (
+            self.hlc
+                .unwrap_or_else(|| Hlc::from_datetime(self.edited_at)),
+            self.id,
+        )
+    }
+}
+
+/// One entry of a message's edit history; the first entry is the original body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageRevision {
+    pub body: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_body: Option<SealedContent>,
+}
+
+impl MessageRevision {
+    /// Decrypts a sealed revision of the given message when `key_for` has its key.
+    pub fn opened<K: IntoIterator<Item = GroupKey>>(
+        mut self,
+        message_id: Uuid,
+        key_for: impl Fn(u32) -> K,
+    ) -> Self {
+        if let Some(body) = self
+            .sealed_body
+            .as_ref()
+            .and_then(|s| s.open_text(message_id, key_for))
+        {
+            self.body = body;
+            self.sealed_body = None;
+        }
+        self
+    }
+}
+
+/// Marks a message as deleted. Tombstones outlive the message so that copies
+/// arriving later, e.g. from a peer that was offline, are dropped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub message_id: Uuid,
+    pub deleted_at: DateTime<Utc>,
+    /// Set when the signer deletes someone else's message as a moderator.
+    #[serde(default)]
+    pub as_moderator: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<Hlc>,
+}
+
+impl Tombstone {
+    pub fn new(message_id: Uuid, as_moderator: bool, hlc: Hlc) -> Self {
+        Self {
+            message_id,
+            deleted_at: Utc::now(),
+            as_moderator,
+            hlc: Some(hlc),
+        }
+    }
+
+    /// Whether this tombstone, signed by `signer`, removes `message`.
+    pub fn applies_to(&self, signer: &str, message: &ChatMessage) -> bool {
+        self.message_id == message.id
+            && (self.as_moderator || message.peer_id.as_deref() == Some(signer))
+    }
+}
+
+/// Longest reaction emoji, in bytes.
+pub const MAX_EMOJI_BYTES: usize = 32;
+
+/// Adds or removes one author's emoji reaction on a message. For each
+/// (message, signer, emoji) the latest event wins, so peers converge no matter
+/// in which order they receive them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub emoji: String,
+    /// `true` to add the reaction, `false` to remove it.
+    pub active: bool,
+    pub at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hlc: Option<Hlc>,
+}
+
+impl Reaction {
+    /// Whether `emoji` may be used as a reaction: non-empty, at most
+    /// [`MAX_EMOJI_BYTES`] (room for multi-codepoint sequences) and free of
+    /// whitespace and control characters.
+    pub fn valid_emoji(emoji: &str) -> bool {
+        !emoji.is_empty()
+            && emoji.len() <= MAX_EMOJI_BYTES
+            && !emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+    }
+
+    pub fn new(message_id: Uuid, emoji: impl Into<String>, active: bool, hlc: Hlc) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            message_id,
+            emoji: emoji.into(),
+            active,
+            at: Utc::now(),
+            hlc: Some(hlc),
+        }
+    }
+
+    pub fn order_key(&self) -> (Hlc, Uuid) {
+        (
+            self.hlc.unwrap_or_else(|| Hlc::from_datetime(self.at)),
+            self.id,
+        )
+    }
+}
+
+/// Aggregated reactions with one emoji on a message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    /// PeerIds of the nodes currently reacting with this emoji.
+    pub peers: Vec<String>,
 }
@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Hlc, Signed, persist};
+
+/// What a moderator can impose on a peer across the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sanction {
+    /// Everything the peer publishes is rejected and its messages are hidden.
+    Ban,
+    /// The peer may read but its new messages, edits and reactions are rejected.
+    Mute,
+}
+
+/// Imposes a sanction on a peer, or lifts it when `active` is false. Only
+/// actions signed by a grid moderator take effect.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModerationAction {
+    pub peer: String,
+    pub sanction: Sanction,
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// When a temporary sanction lapses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    pub hlc: Hlc,
+}
+
+impl ModerationAction {
+    /// A sanction lasting `duration_secs` from now, or indefinitely; durations
+    /// beyond chrono's range are indefinite too.
+    pub fn impose(
+        peer: impl Into<String>,
+        sanction: Sanction,
+        reason: Option<String>,
+        duration_secs: Option<i64>,
+        hlc: Hlc,
+    ) -> Self {
+        Self {
+            peer: peer.into(),
+            sanction,
+            active: true,
+            reason,
+            until: duration_secs
+                .and_then(Duration::try_seconds)
+                .and_then(|duration| Utc::now().checked_add_signed(duration)),
+            hlc,
+        }
+    }
+
+    pub fn lift(peer: impl Into<String>, sanction: Sanction, hlc: Hlc) -> Self {
+        Self {
+            peer: peer.into(),
+            sanction,
+            active: false,
+            reason: None,
+            until: None,
+            hlc,
+        }
+    }
+
+    /// Whether the sanction is in force now.
+    pub fn in_force(&self) -> bool {
+        self.active && self.until.is_none_or(|until| until > Utc::now())
+    }
+}
+
+/// The grid's moderation state: the latest action per (peer, sanction).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ModerationList {
+    actions: Vec<Signed<ModerationAction>>,
+}
+
+impl ModerationList {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "moderation list")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    /// Keeps the action if it is newer, by (clock, signer), than the one
+    /// recorded for its peer and sanction. Returns whether it was kept.
+    pub fn apply(&mut self, action: Signed<ModerationAction>) -> bool {
+        let key = (action.payload.peer.as_str(), action.payload.sanction);
+        match self
+            .actions
+            .iter_mut()
+            .find(|a| (a.payload.peer.as_str(), a.payload.sanction) == key)
+        {
+            Some(current) => {
+                if (action.payload.hlc, &action.signer) <= (current.payload.hlc, &current.signer) {
+                    return false;
+                }
+                *current = action;
+            }
+            None => self.actions.push(action),
+        }
+        true
+    }
+
+    pub fn actions(&self) -> &[Signed<ModerationAction>] {
+        &self.actions
+    }
+
+    pub fn has(&self, peer: &str, sanction: Sanction) -> bool {
+        self.actions.iter().any(|a| {
+            a.payload.peer == peer && a.payload.sanction == sanction && a.payload.in_force()
+        })
+    }
+
+    pub fn is_banned(&self, peer: &str) -> bool {
+        self.has(peer, Sanction::Ban)
+    }
+
+    /// Banned peers count as muted too.
+    pub fn is_muted(&self, peer: &str) -> bool {
+        self.is_banned(peer) || self.has(peer, Sanction::Mute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hlc(wall: i64) -> Hlc {
+        Hlc { wall, logical: 0 }
+    }
+
+    /// Signatures and moderator rights are checked before actions are applied.
+    fn signed(payload: ModerationAction, signer: &str) -> Signed<ModerationAction> {
+        Signed {
+            payload,
+            signer: signer.to_string(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    fn ban(wall: i64, signer: &str) -> Signed<ModerationAction> {
+        signed(
+            ModerationAction::impose("mallory", Sanction::Ban, None, None, hlc(wall)),
+            signer,
+        )
+    }
+
+    fn unban(wall: i64, signer: &str) -> Signed<ModerationAction> {
+        signed(
+            ModerationAction::lift("mallory", Sanction::Ban, hlc(wall)),
+            signer,
+        )
+    }
+
+    #[test]
+    fn latest_action_wins_in_any_order() {
+        let actions = [ban(1, "mod-a"), unban(2, "mod-a"), ban(2, "mod-b")];
+        let mut forward = ModerationList::default();
+        let mut backward = ModerationList::default();
+        for action in actions.iter().cloned() {
+            forward.apply(action);
+        }
+        for action in actions.iter().rev().cloned() {
+            backward.apply(action);
+        }
+        // Equal clocks are settled by the signer.
+        assert!(forward.is_banned("mallory") && backward.is_banned("mallory"));
+        assert_eq!(forward.actions(), backward.actions());
+        assert!(!forward.apply(unban(1, "mod-a")));
+    }
+
+    #[test]
+    fn sanctions_are_in_force_until_they_lapse_or_are_lifted() {
+        let mut list = ModerationList::default();
+        list.apply(signed(
+            ModerationAction::impose("mallory", Sanction::Mute, None, Some(3600), hlc(1)),
+            "mod",
+        ));
+        assert!(list.is_muted("mallory") && !list.is_banned("mallory"));
+        list.apply(ban(1, "mod"));
+        assert!(list.is_banned("mallory"));
+
+        let lapsed = ModerationAction::impose("eve", Sanction::Ban, None, Some(-1), hlc(1));
+        assert!(!lapsed.in_force());
+        let forever = ModerationAction::impose("eve", Sanction::Ban, None, Some(i64::MAX), hlc(1));
+        assert!(forever.until.is_none() && forever.in_force());
+
+        list.apply(unban(2, "mod"));
+        assert!(!list.is_banned("mallory") && list.is_muted("mallory"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{ChatMessage, Mention, persist};
+
+/// Which messages in a channel notify the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyLevel {
+    All,
+    /// Mentions of the user, `@here` and `@everyone`.
+    #[default]
+    Mentions,
+    Nothing,
+}
+
+/// Why a message notifies the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationReason {
+    Mention,
+    Here,
+    Everyone,
+    /// Any message, in a channel set to [`NotifyLevel::All`].
+    Message,
+}
+
+impl NotificationReason {
+    /// Whether the message addressed the user rather than just arriving.
+    pub fn is_mention(self) -> bool {
+        self != NotificationReason::Message
+    }
+}
+
+/// The user's notification settings for one channel.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChannelNotifications {
+    #[serde(default)]
+    pub level: NotifyLevel,
+    /// Until when the channel is muted, whatever its level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub muted_until: Option<DateTime<Utc>>,
+    /// Ignore `@here` and `@everyone`.
+    #[serde(default)]
+    pub suppress_everyone: bool,
+}
+
+impl ChannelNotifications {
+    /// Settings muting the channel for `mute_secs` from now, if given.
+    pub fn new(level: NotifyLevel, suppress_everyone: bool, mute_secs: Option<i64>) -> Self {
+        Self {
+            level,
+            muted_until: mute_secs
+                .and_then(Duration::try_seconds)
+                .and_then(|duration| Utc::now().checked_add_signed(duration)),
+            suppress_everyone,
+        }
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.level == NotifyLevel::Nothing
+            || self.muted_until.is_some_and(|until| until > Utc::now())
+    }
+
+    /// Why `message` should notify `peer_id`, if it should. `may_broadcast`
+    /// says whether its sender may use `@here` and `@everyone` in the channel,
+    /// and `online` whether the user currently counts as online for `@here`.
+    pub fn reason(
+        &self,
+        message: &ChatMessage,
+        peer_id: &str,
+        may_broadcast: bool,
+        online: bool,
+    ) -> Option<NotificationReason> {
+        if self.is_muted() {
+            return None;
+        }
+        if message.mentions_peer(peer_id) {
+            return Some(NotificationReason::Mention);
+        }
+        if may_broadcast && !self.suppress_everyone {
+            if message.mentions.contains(&Mention::Everyone) {
+                return Some(NotificationReason::Everyone);
+            }
+            if online && message.mentions.contains(&Mention::Here) {
+                return Some(NotificationReason::Here);
+            }
+        }
+        (self.level == NotifyLevel::All).then_some(NotificationReason::Message)
+    }
+}
+
+/// Notification settings per channel id; channels without an entry use the
+/// defaults. Local to the node.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    channels: BTreeMap<String, ChannelNotifications>,
+}
+
+impl NotificationSettings {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "notification settings")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    pub fn get(&self, channel: &str) -> ChannelNotifications {
+        self.channels.get(channel).cloned().unwrap_or_default()
+    }
+
+    /// Stores a channel's settings; default settings remove its entry.
+    pub fn set(&mut self, channel: impl Into<String>, settings: ChannelNotifications) {
+        let channel = channel.into();
+        if settings == ChannelNotifications::default() {
+            self.channels.remove(&channel);
+        } else {
+            self.channels.insert(channel, settings);
+        }
+    }
+}
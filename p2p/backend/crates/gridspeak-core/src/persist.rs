@@ -0,0 +1,47 @@
+//! JSON files holding a node's local state.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::{Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+/// Reads a JSON state file, returning the default when it does not exist
+/// yet. `what` names the contents in errors, e.g. "channel set".
+pub fn load_json<T: DeserializeOwned + Default>(path: impl AsRef<Path>, what: &str) -> Result<T> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let raw = fs::read_to_string(path).with_context(|| format!("unable to read {:?}", path))?;
+    serde_json::from_str(&raw).with_context(|| format!("invalid {what} {:?}", path))
+}
+
+/// Writes `value` as pretty JSON, creating parent directories as needed.
+pub fn save_json<T: Serialize + ?Sized>(path: impl AsRef<Path>, value: &T) -> Result<()> {
+    write_atomic(path, serde_json::to_string_pretty(value)?.as_bytes())
+}
+
+/// Replaces the file at `path` with `contents` by writing a temporary file
+/// beside it and renaming it into place, so a crash mid-write leaves either
+/// the old contents or the new ones, never a truncated file. Each write uses
+/// its own temporary file, so concurrent writers cannot interleave.
+pub fn write_atomic(path: impl AsRef<Path>, contents: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.file_name().map(OsString::from).unwrap_or_default();
+    tmp_name.push(format!(".{}.tmp", Uuid::new_v4().simple()));
+    let tmp = path.with_file_name(tmp_name);
+    let mut file = File::create(&tmp).with_context(|| format!("unable to write {:?}", tmp))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path).with_context(|| format!("unable to replace {:?}", path))?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How often a node announces its presence.
+pub const HEARTBEAT_SECS: u64 = 30;
+
+/// How long a peer counts as present after its last heartbeat.
+const PRESENCE_TTL_SECS: i64 = 3 * HEARTBEAT_SECS as i64;
+
+/// Status a user picks for their node. `Invisible` nodes send no heartbeats
+/// and so appear offline to everyone else.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    #[default]
+    Online,
+    Idle,
+    Dnd,
+    Invisible,
+    /// Reported for peers whose heartbeats stopped; never sent.
+    Offline,
+}
+
+/// Heartbeat payload sent over gossip; the sender is the gossip message source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub status: PresenceStatus,
+}
+
+/// A peer's presence as last heard.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceEntry {
+    pub peer_id: String,
+    pub status: PresenceStatus,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Latest heartbeat per peer. Peers silent for longer than the TTL are
+/// reported offline with the time they were last seen.
+#[derive(Debug, Default)]
+pub struct PresenceBook {
+    peers: HashMap<String, (PresenceStatus, DateTime<Utc>)>,
+}
+
+impl PresenceBook {
+    pub fn record(&mut self, peer_id: &str, heartbeat: Heartbeat) {
+        let status = match heartbeat.status {
+            PresenceStatus::Invisible => PresenceStatus::Offline,
+            status => status,
+        };
+        self.peers.insert(peer_id.to_string(), (status, Utc::now()));
+    }
+
+    pub fn entries(&self) -> Vec<PresenceEntry> {
+        let cutoff = Utc::now() - Duration::seconds(PRESENCE_TTL_SECS);
+        let mut entries: Vec<PresenceEntry> = self
+            .peers
+            .iter()
+            .map(|(peer_id, &(status, last_seen))| PresenceEntry {
+                peer_id: peer_id.clone(),
+                status: if last_seen < cutoff {
+                    PresenceStatus::Offline
+                } else {
+                    status
+                },
+                last_seen,
+            })
+            .collect();
+        entries.sort_by_key(|e| Reverse(e.last_seen));
+        entries
+    }
+}
@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::{Hlc, Signed, persist};
+
+/// Largest avatar accepted, small enough to travel with a profile over gossip.
+pub const MAX_AVATAR_BYTES: usize = 32 * 1024;
+
+/// Image types an avatar may have. Only raster formats: anything else, SVG
+/// included, could run script when a browser opens the avatar URL.
+pub const AVATAR_CONTENT_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Length limits of the profile fields, in characters.
+pub const MAX_DISPLAY_NAME_CHARS: usize = 64;
+pub const MAX_BIO_CHARS: usize = 500;
+pub const MAX_PRONOUNS_CHARS: usize = 40;
+pub const MAX_STATUS_CHARS: usize = 128;
+
+/// Avatar image kept in the blob store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Avatar {
+    pub sha256: String,
+    pub content_type: String,
+    pub size: u64,
+}
+
+impl Avatar {
+    pub fn validate(&self) -> Result<()> {
+        if !AVATAR_CONTENT_TYPES.contains(&self.content_type.as_str()) {
+            return Err(anyhow!("avatar must be a PNG, JPEG, GIF or WebP image"));
+        }
+        if self.size > MAX_AVATAR_BYTES as u64 {
+            return Err(anyhow!("avatar exceeds {} KB", MAX_AVATAR_BYTES / 1024));
+        }
+        Ok(())
+    }
+}
+
+/// How a peer presents itself. Each node signs its own profile; the signer's
+/// PeerId is the identity the profile describes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub display_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<Avatar>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pronouns: Option<String>,
+    /// Free-form status text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub hlc: Hlc,
+}
+
+impl Profile {
+    pub fn new(display_name: impl Into<String>, hlc: Hlc) -> Self {
+        Self {
+            display_name: display_name.into(),
+            avatar: None,
+            bio: None,
+            pronouns: None,
+            status: None,
+            hlc,
+        }
+    }
+
+    /// Checks the limits the API enforces, for profiles received from peers.
+    pub fn validate(&self) -> Result<()> {
+        let name = self.display_name.trim();
+        if name.is_empty() || name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+            return Err(anyhow!(
+                "display_name must be 1-{MAX_DISPLAY_NAME_CHARS} characters"
+            ));
+        }
+        for (value, max_chars, label) in [
+            (&self.bio, MAX_BIO_CHARS, "bio"),
+            (&self.pronouns, MAX_PRONOUNS_CHARS, "pronouns"),
+            (&self.status, MAX_STATUS_CHARS, "status"),
+        ] {
+            if value
+                .as_ref()
+                .is_some_and(|v| v.chars().count() > max_chars)
+            {
+                return Err(anyhow!("{label}: at most {max_chars} characters"));
+            }
+        }
+        if let Some(avatar) = &self.avatar {
+            avatar.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// The latest known profile of every peer.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ProfileBook {
+    profiles: BTreeMap<String, Signed<Profile>>,
+}
+
+impl ProfileBook {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "profile book")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    /// Keeps the profile if it is newer than the one known for its signer.
+    /// Returns whether it was kept.
+    pub fn apply(&mut self, profile: Signed<Profile>) -> bool {
+        if let Some(current) = self.profiles.get(&profile.signer)
+            && current.payload.hlc >= profile.payload.hlc
+        {
+            return false;
+        }
+        self.profiles.insert(profile.signer.clone(), profile);
+        true
+    }
+
+    pub fn get(&self, peer_id: &str) -> Option<&Signed<Profile>> {
+        self.profiles.get(peer_id)
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Signed<Profile>> {
+        self.profiles.values()
+    }
+
+    /// Whether any profile uses the blob as its avatar.
+    pub fn references(&self, sha256: &str) -> bool {
+        self.profiles.values().any(|p| {
+            p.payload
+                .avatar
+                .as_ref()
+                .is_some_and(|a| a.sha256 == sha256)
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::Hlc;
+
+/// Longest age limit a rule may set, about a century.
+pub const MAX_RETENTION_DAYS: u32 = 36_500;
+
+/// Limits on the history a channel keeps; unset limits do not apply. Ages are
+/// measured by a message's clock stamp, which every peer agrees on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_messages: Option<usize>,
+    /// Largest size of the channel's messages, as stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// Attachments older than this are dropped; their messages stay.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment_max_age_days: Option<u32>,
+}
+
+impl RetentionRule {
+    /// This rule, with limits it leaves unset taken from `fallback`.
+    pub fn or(&self, fallback: &RetentionRule) -> RetentionRule {
+        RetentionRule {
+            max_age_days: self.max_age_days.or(fallback.max_age_days),
+            max_messages: self.max_messages.or(fallback.max_messages),
+            max_bytes: self.max_bytes.or(fallback.max_bytes),
+            attachment_max_age_days: self
+                .attachment_max_age_days
+                .or(fallback.attachment_max_age_days),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        *self == RetentionRule::default()
+    }
+
+    /// Rejects age limits beyond [`MAX_RETENTION_DAYS`].
+    pub fn validate(&self) -> Result<()> {
+        for (key, days) in [
+            ("max_age_days", self.max_age_days),
+            ("attachment_max_age_days", self.attachment_max_age_days),
+        ] {
+            if days.is_some_and(|days| days > MAX_RETENTION_DAYS) {
+                return Err(anyhow!(
+                    "retention {key} must be at most {MAX_RETENTION_DAYS}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stamps before this are past the age limit.
+    pub fn cutoff(&self) -> Option<Hlc> {
+        self.max_age_days.and_then(days_ago)
+    }
+
+    /// Attachments stamped before this are dropped.
+    pub fn attachment_cutoff(&self) -> Option<Hlc> {
+        self.attachment_max_age_days.and_then(days_ago)
+    }
+}
+
+/// `None` when the date falls outside chrono's range, so nothing is old enough.
+fn days_ago(days: u32) -> Option<Hlc> {
+    Utc::now()
+        .checked_sub_signed(Duration::days(i64::from(days)))
+        .map(Hlc::from_datetime)
+}
+
+/// Retention rules from the config: a global rule and overrides per channel,
+/// keyed by channel name or id.
+///
+/// ```toml
+/// [retention]
+/// max_age_days = 365
+///
+/// [retention.channels.random]
+/// max_messages = 1000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    #[serde(flatten)]
+    pub default: RetentionRule,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, RetentionRule>,
+}
+
+impl RetentionConfig {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_unlimited() && self.channels.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.default.validate()?;
+        for (channel, rule) in &self.channels {
+            rule.validate()
+                .map_err(|e| anyhow!("{e} (channel {channel})"))?;
+        }
+        Ok(())
+    }
+
+    /// The rule for a channel: its override (by id, else by name) on top of
+    /// the global rule.
+    pub fn rule_for(&self, id: &str, name: &str) -> RetentionRule {
+        match self.channels.get(id).or_else(|| self.channels.get(name)) {
+            Some(rule) => rule.or(&self.default),
+            None => self.default.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_overrides_fill_unset_limits_from_the_default() {
+        let mut config = RetentionConfig {
+            default: RetentionRule {
+                max_age_days: Some(30),
+                max_messages: Some(100),
+                ..Default::default()
+            },
+            channels: BTreeMap::new(),
+        };
+        config.channels.insert(
+            "random".to_string(),
+            RetentionRule {
+                max_messages: Some(10),
+                ..Default::default()
+            },
+        );
+        config.channels.insert(
+            "random-1234".to_string(),
+            RetentionRule {
+                max_messages: Some(5),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            config.rule_for("random-1234", "random").max_messages,
+            Some(5)
+        );
+        let by_name = config.rule_for("random-5678", "random");
+        assert_eq!(
+            (by_name.max_age_days, by_name.max_messages),
+            (Some(30), Some(10))
+        );
+        assert_eq!(config.rule_for("general", "general"), config.default);
+    }
+
+    #[test]
+    fn age_limits_are_bounded() {
+        let rule = RetentionRule {
+            attachment_max_age_days: Some(MAX_RETENTION_DAYS + 1),
+            ..Default::default()
+        };
+        assert!(rule.validate().is_err());
+        let mut config = RetentionConfig::default();
+        config.channels.insert("random".to_string(), rule);
+        assert!(config.validate().is_err());
+        assert!(
+            RetentionRule {
+                max_age_days: Some(MAX_RETENTION_DAYS),
+                ..Default::default()
+            }
+            .cutoff()
+            .is_some()
+        );
+    }
+}
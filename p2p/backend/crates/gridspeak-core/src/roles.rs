@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Hlc, Signed};
+
+/// Role of a peer within one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelRole {
+    Owner,
+    Moderator,
+    Member,
+}
+
+impl ChannelRole {
+    /// Whether the role may edit and rename the channel and moderate its messages.
+    pub fn can_moderate(self) -> bool {
+        matches!(self, ChannelRole::Owner | ChannelRole::Moderator)
+    }
+}
+
+/// Gives a peer a role in a channel, or takes it away when `role` is `None`.
+/// Only grants signed by an owner (or a grid moderator) take effect.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoleGrant {
+    /// Channel id.
+    pub channel: String,
+    pub peer: String,
+    pub role: Option<ChannelRole>,
+    pub hlc: Hlc,
+}
+
+impl RoleGrant {
+    pub fn new(
+        channel: impl Into<String>,
+        peer: impl Into<String>,
+        role: Option<ChannelRole>,
+        hlc: Hlc,
+    ) -> Self {
+        Self {
+            channel: channel.into(),
+            peer: peer.into(),
+            role,
+            hlc,
+        }
+    }
+}
+
+/// Replays grants in clock order on top of the creator's ownership. A grant
+/// counts only if its signer was an owner (or `is_admin`) at that point, so
+/// every peer holding the same grants arrives at the same roles.
+pub fn effective_roles<'a>(
+    creator: Option<&str>,
+    grants: impl IntoIterator<Item = &'a Signed<RoleGrant>>,
+    is_admin: impl Fn(&str) -> bool,
+) -> BTreeMap<String, ChannelRole> {
+    let mut roles = BTreeMap::new();
+    if let Some(creator) = creator {
+        roles.insert(creator.to_string(), ChannelRole::Owner);
+    }
+    let mut grants: Vec<_> = grants.into_iter().collect();
+    grants.sort_by(|a, b| (a.payload.hlc, &a.signer).cmp(&(b.payload.hlc, &b.signer)));
+    for grant in grants {
+        if !is_admin(&grant.signer) && roles.get(&grant.signer) != Some(&ChannelRole::Owner) {
+            continue;
+        }
+        match grant.payload.role {
+            Some(role) => roles.insert(grant.payload.peer.clone(), role),
+            None => roles.remove(&grant.payload.peer),
+        };
+    }
+    roles
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{ChatMessage, persist};
+
+/// A message held by the node until `send_at`. It is kept unsealed and
+/// unstamped; both happen when it is sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub channel: String,
+    pub send_at: DateTime<Utc>,
+    pub message: ChatMessage,
+}
+
+impl ScheduledMessage {
+    /// Schedules `message` for `send_at`, an RFC 3339 time in the future.
+    pub fn new(channel: impl Into<String>, message: ChatMessage, send_at: &str) -> Result<Self> {
+        let send_at = DateTime::parse_from_rfc3339(send_at)
+            .map_err(|_| anyhow!("send_at must be an RFC 3339 time"))?
+            .with_timezone(&Utc);
+        if send_at <= Utc::now() {
+            return Err(anyhow!("send_at must be in the future"));
+        }
+        Ok(Self {
+            channel: channel.into(),
+            send_at,
+            message,
+        })
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.message.id
+    }
+
+    /// The message as sent now, timestamped at the time it goes out.
+    pub fn into_message(self) -> ChatMessage {
+        ChatMessage {
+            timestamp: Utc::now(),
+            ..self.message
+        }
+    }
+}
+
+/// Messages waiting to be sent, earliest first. They are local to the node
+/// until they go out.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    messages: Vec<ScheduledMessage>,
+}
+
+impl Schedule {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        persist::load_json(path, "schedule")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        persist::save_json(path, self)
+    }
+
+    pub fn add(&mut self, scheduled: ScheduledMessage) {
+        let index = self
+            .messages
+            .partition_point(|m| m.send_at <= scheduled.send_at);
+        self.messages.insert(index, scheduled);
+    }
+
+    /// Removes a pending message; `None` when it is unknown or already sent.
+    pub fn cancel(&mut self, id: Uuid) -> Option<ScheduledMessage> {
+        let index = self.messages.iter().position(|m| m.id() == id)?;
+        Some(self.messages.remove(index))
+    }
+
+    /// The messages whose time has come, earliest first. They stay pending
+    /// until [`Schedule::cancel`] takes them out to be sent.
+    pub fn due(&self) -> Vec<ScheduledMessage> {
+        let now = Utc::now();
+        let due = self.messages.partition_point(|m| m.send_at <= now);
+        self.messages[..due].to_vec()
+    }
+
+    pub fn pending(&self) -> &[ScheduledMessage] {
+        &self.messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A message due `secs` seconds from now; scheduling in the past is only
+    /// refused by [`ScheduledMessage::new`].
+    fn scheduled(body: &str, secs: i64) -> ScheduledMessage {
+        ScheduledMessage {
+            channel: "general".to_string(),
+            send_at: Utc::now() + chrono::Duration::seconds(secs),
+            message: ChatMessage::new("alice", body),
+        }
+    }
+
+    fn bodies(messages: &[ScheduledMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.message.body.as_str()).collect()
+    }
+
+    #[test]
+    fn due_messages_come_earliest_first() {
+        let mut schedule = Schedule::default();
+        for (body, secs) in [("later", 60), ("second", -5), ("first", -10), ("third", -5)] {
+            schedule.add(scheduled(body, secs));
+        }
+        assert_eq!(bodies(&schedule.due()), ["first", "second", "third"]);
+        assert_eq!(
+            bodies(schedule.pending()),
+            ["first", "second", "third", "later"]
+        );
+
+        let first = schedule.due()[0].id();
+        assert!(schedule.cancel(first).is_some());
+        assert!(schedule.cancel(first).is_none());
+        assert_eq!(bodies(&schedule.due()), ["second", "third"]);
+    }
+
+    #[test]
+    fn send_at_must_be_a_future_rfc3339_time() {
+        let message = || ChatMessage::new("alice", "hi");
+        assert!(ScheduledMessage::new("general", message(), "tomorrow").is_err());
+        assert!(ScheduledMessage::new("general", message(), "2000-01-01T00:00:00Z").is_err());
+        let later = (Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        assert!(ScheduledMessage::new("general", message(), &later).is_ok());
+    }
+}
@@ -0,0 +1,382 @@
+//! Local full-text search over message bodies and attachment filenames.
+//!
+//! The index lives in memory and is rebuilt from the stores when a node
+//! starts. Query words match indexed words they are a prefix of, every query
+//! word must match, and hits are ranked with BM25.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::ChatMessage;
+
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// Characters of context kept before the first match in a snippet.
+const SNIPPET_CONTEXT: usize = 40;
+
+/// A search request with its optional filters.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    /// Channel id.
+    #[serde(default)]
+    pub channel: Option<String>,
+    /// PeerId or author name (case-insensitive).
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub after: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub before: Option<DateTime<Utc>>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+/// A matching message and its relevance.
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub message_id: Uuid,
+    pub channel: String,
+    pub score: f64,
+}
+
+#[derive(Debug)]
+struct Doc {
+    channel: String,
+    peer_id: Option<String>,
+    author: String,
+    timestamp: DateTime<Utc>,
+    len: u32,
+    /// Distinct words, to find the postings to drop when the doc goes.
+    words: Vec<String>,
+}
+
+/// Inverted index from words to the messages containing them.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    docs: HashMap<Uuid, Doc>,
+    postings: BTreeMap<String, HashMap<Uuid, u32>>,
+    total_len: u64,
+}
+
+impl SearchIndex {
+    /// Indexes a message, replacing any earlier version of it. Sealed messages
+    /// are left out; their plaintext never reaches the index.
+    pub fn insert(&mut self, channel: &str, message: &ChatMessage) {
+        self.remove(message.id);
+        if message.is_sealed() {
+            return;
+        }
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        let filenames = message.attachments.iter().map(|a| a.filename.as_str());
+        let texts = std::iter::once(message.body.as_str()).chain(filenames);
+        for text in texts {
+            for (_, word) in words(text) {
+                *counts.entry(word.to_lowercase()).or_default() += 1;
+            }
+        }
+        if counts.is_empty() {
+            return;
+        }
+        let len = counts.values().sum();
+        for (word, count) in &counts {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(message.id, *count);
+        }
+        self.total_len += u64::from(len);
+        self.docs.insert(
+            message.id,
+            Doc {
+                channel: channel.to_string(),
+                peer_id: message.peer_id.clone(),
+                author: message.author.clone(),
+                timestamp: message.timestamp,
+                len,
+                words: counts.into_keys().collect(),
+            },
+        );
+    }
+
+    pub fn remove(&mut self, id: Uuid) {
+        let Some(doc) = self.docs.remove(&id) else {
+            return;
+        };
+        self.total_len -= u64::from(doc.len);
+        for word in doc.words {
+            if let Some(posting) = self.postings.get_mut(&word) {
+                posting.remove(&id);
+                if posting.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    pub fn remove_channel(&mut self, channel: &str) {
+        let ids: Vec<Uuid> = self
+            .docs
+            .iter()
+            .filter(|(_, doc)| doc.channel == channel)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            self.remove(id);
+        }
+    }
+
+    /// Messages matching every word of the query and its filters, best first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let terms = query_terms(&query.q);
+        if terms.is_empty() || self.docs.is_empty() {
+            return Vec::new();
+        }
+        let doc_count = self.docs.len() as f64;
+        let avg_len = self.total_len as f64 / doc_count;
+        let mut scores: HashMap<Uuid, (usize, f64)> = HashMap::new();
+        for term in &terms {
+            // Best contribution per doc among the words the term is a prefix of.
+            let mut best: HashMap<Uuid, f64> = HashMap::new();
+            let expansions = self
+                .postings
+                .range(term.clone()..)
+                .take_while(|(word, _)| word.starts_with(term.as_str()));
+            for (_, posting) in expansions {
+                let n = posting.len() as f64;
+                let idf = (1.0 + (doc_count - n + 0.5) / (n + 0.5)).ln();
+                for (id, &tf) in posting {
+                    let Some(doc) = self.docs.get(id) else {
+                        continue;
+                    };
+                    let tf = f64::from(tf);
+                    let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * f64::from(doc.len) / avg_len);
+                    let score = idf * tf * (BM25_K1 + 1.0) / (tf + norm);
+                    let entry = best.entry(*id).or_default();
+                    *entry = entry.max(score);
+                }
+            }
+            for (id, score) in best {
+                let entry = scores.entry(id).or_default();
+                entry.0 += 1;
+                entry.1 += score;
+            }
+        }
+        let mut hits: Vec<(SearchHit, DateTime<Utc>)> = scores
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == terms.len())
+            .filter_map(|(id, (_, score))| {
+                let doc = self.docs.get(&id)?;
+                matches_filters(doc, query).then(|| {
+                    let hit = SearchHit {
+                        message_id: id,
+                        channel: doc.channel.clone(),
+                        score,
+                    };
+                    (hit, doc.timestamp)
+                })
+            })
+            .collect();
+        hits.sort_by(|(a, at), (b, bt)| b.score.total_cmp(&a.score).then(bt.cmp(at)));
+        hits.into_iter()
+            .take(query.limit)
+            .map(|(hit, _)| hit)
+            .collect()
+    }
+}
+
+fn matches_filters(doc: &Doc, query: &SearchQuery) -> bool {
+    query
+        .channel
+        .as_ref()
+        .is_none_or(|channel| doc.channel == *channel)
+        && query.author.as_ref().is_none_or(|author| {
+            doc.peer_id.as_deref() == Some(author.as_str())
+                || doc.author.eq_ignore_ascii_case(author)
+        })
+        && query.after.is_none_or(|after| doc.timestamp >= after)
+        && query.before.is_none_or(|before| doc.timestamp < before)
+}
+
+/// Words of a text (runs of letters and digits) with their byte offsets.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+/// The distinct, lower-cased words of a query.
+pub fn query_terms(q: &str) -> Vec<String> {
+    let mut terms: Vec<String> = words(q).map(|(_, word)| word.to_lowercase()).collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+/// A snippet of `text` of at most `max_chars` characters around its first
+/// word matching one of `terms`, with matching words wrapped in `<mark>` and
+/// everything else HTML-escaped. `None` when no word matches.
+pub fn highlight(text: &str, terms: &[String], max_chars: usize) -> Option<String> {
+    let matches: Vec<(usize, usize)> = words(text)
+        .filter(|(_, word)| {
+            let word = word.to_lowercase();
+            terms.iter().any(|term| word.starts_with(term.as_str()))
+        })
+        .map(|(at, word)| (at, at + word.len()))
+        .collect();
+    let first = matches.first()?.0;
+    let start = text[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map_or(0, |(i, _)| i);
+    let end = text[start..]
+        .char_indices()
+        .nth(max_chars)
+        .map_or(text.len(), |(i, _)| start + i);
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut at = start;
+    for (from, to) in matches.into_iter().filter(|(_, to)| *to <= end) {
+        snippet.push_str(&escape_html(&text[at..from]));
+        snippet.push_str("<mark>");
+        snippet.push_str(&escape_html(&text[from..to]));
+        snippet.push_str("</mark>");
+        at = to;
+    }
+    snippet.push_str(&escape_html(&text[at..end]));
+    if end < text.len() {
+        snippet.push('…');
+    }
+    Some(snippet)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(q: &str) -> SearchQuery {
+        SearchQuery {
+            q: q.to_string(),
+            channel: None,
+            author: None,
+            after: None,
+            before: None,
+            limit: default_limit(),
+        }
+    }
+
+    fn index(bodies: &[&str]) -> (SearchIndex, Vec<Uuid>) {
+        let mut index = SearchIndex::default();
+        let ids = bodies
+            .iter()
+            .map(|body| {
+                let message = ChatMessage::new("alice", *body);
+                index.insert("general", &message);
+                message.id
+            })
+            .collect();
+        (index, ids)
+    }
+
+    #[test]
+    fn highlight_marks_matches_and_escapes() {
+        let snippet = highlight("Fish & <chips> for fishers", &query_terms("fish"), 100).unwrap();
+        assert_eq!(
+            snippet,
+            "<mark>Fish</mark> &amp; &lt;chips&gt; for <mark>fishers</mark>"
+        );
+        assert_eq!(highlight("nothing here", &query_terms("fish"), 100), None);
+    }
+
+    #[test]
+    fn highlight_slices_on_char_boundaries() {
+        let text = format!("{}ünïcödé match {}", "é".repeat(60), "日本語".repeat(30));
+        let snippet = highlight(&text, &query_terms("match"), 50).unwrap();
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>match</mark>"));
+        let visible = snippet.replace("<mark>", "").replace("</mark>", "");
+        assert_eq!(visible.chars().count(), 50 + 2);
+
+        let emoji = "🎉🎉 party 🎉";
+        assert_eq!(
+            highlight(emoji, &query_terms("par"), 100).unwrap(),
+            "🎉🎉 <mark>party</mark> 🎉"
+        );
+    }
+
+    #[test]
+    fn every_term_must_match_by_prefix() {
+        let (index, ids) = index(&["deploy the server", "deploy the client", "server logs"]);
+        let hits = index.search(&query("dep serv"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, ids[0]);
+    }
+
+    #[test]
+    fn bm25_prefers_frequent_terms_in_shorter_messages() {
+        let (index, ids) = index(&[
+            "rust rust rust",
+            "rust is a language with a long description of many things",
+            "python",
+        ]);
+        let hits = index.search(&query("rust"));
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].message_id, ids[0]);
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn bm25_weights_rare_terms_higher() {
+        let (index, ids) = index(&[
+            "common rare",
+            "common filler",
+            "common words",
+            "common text",
+        ]);
+        let hits = index.search(&query("common rare"));
+        assert_eq!(hits[0].message_id, ids[0]);
+        let rare = index.search(&query("rare"))[0].score;
+        let common = index
+            .search(&query("common"))
+            .into_iter()
+            .find(|hit| hit.message_id == ids[0])
+            .unwrap()
+            .score;
+        assert!(rare > common);
+    }
+
+    #[test]
+    fn removed_messages_are_not_found() {
+        let (mut index, ids) = index(&["hello world"]);
+        index.remove(ids[0]);
+        assert!(index.search(&query("hello")).is_empty());
+        assert_eq!(index.total_len, 0);
+    }
+}
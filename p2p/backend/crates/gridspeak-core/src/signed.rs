@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A replicated payload together with the node identity that signed it.
+///
+/// Signing and verification live with the networking layer; this type only
+/// carries the data so stores can persist and re-broadcast signed events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Signed<T> {
+    pub payload: T,
+    /// PeerId of the signing node.
+    pub signer: String,
+    /// Protobuf-encoded public key of the signer (base64).
+    pub public_key: String,
+    /// Signature over [`signing_bytes`] of the payload (base64).
+    pub signature: String,
+}
+
+/// Canonical bytes a payload is signed over: its JSON form with object keys sorted.
+pub fn signing_bytes<T: Serialize>(payload: &T) -> Result<Vec<u8>> {
+    let value = canonicalize(serde_json::to_value(payload)?);
+    Ok(serde_json::to_vec(&value)?)
+}
+
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<String, Value> =
+                map.into_iter().map(|(k, v)| (k, canonicalize(v))).collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonicalize).collect()),
+        other => other,
+    }
+}
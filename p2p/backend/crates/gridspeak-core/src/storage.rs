@@ -1,23 +1,118 @@
 use std::{
+    collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::ChatMessage;
+use crate::{
+    ChatMessage, Hlc, MessageEdit, MessageRevision, Reaction, ReactionSummary,
+    RemovedChannelPolicy, RetentionRule, Signed, Tombstone, persist,
+};
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 struct ChatSnapshot {
     messages: Vec<ChatMessage>,
+    /// Every signed edit received, kept as revision history.
+    #[serde(default)]
+    edits: Vec<Signed<MessageEdit>>,
+    /// Deletions, kept so removed messages are not accepted again.
+    #[serde(default)]
+    tombstones: Vec<Signed<Tombstone>>,
+    /// Latest reaction event per (message, signer, emoji).
+    #[serde(default)]
+    reactions: Vec<Signed<Reaction>>,
+}
+
+impl ChatSnapshot {
+    fn is_tombstoned(&self, message: &ChatMessage) -> bool {
+        self.tombstones
+            .iter()
+            .any(|t| t.payload.applies_to(&t.signer, message))
+    }
+
+    /// Edits signed by the message's publisher, oldest first.
+    fn edits_for<'a>(&'a self, message: &ChatMessage) -> Vec<&'a MessageEdit> {
+        let mut edits: Vec<&MessageEdit> = self
+            .edits
+            .iter()
+            .filter(|e| {
+                e.payload.message_id == message.id && message.peer_id.as_ref() == Some(&e.signer)
+            })
+            .map(|e| &e.payload)
+            .collect();
+        edits.sort_by_key(|e| e.order_key());
+        edits
+    }
+
+    /// Returns messages with their latest valid edit applied.
+    fn materialized(&self) -> Vec<ChatMessage> {
+        let authors: HashMap<Uuid, &String> = self
+            .messages
+            .iter()
+            .filter_map(|m| m.peer_id.as_ref().map(|p| (m.id, p)))
+            .collect();
+        let mut latest: HashMap<Uuid, &MessageEdit> = HashMap::new();
+        for edit in &self.edits {
+            let e = &edit.payload;
+            if authors.get(&e.message_id) != Some(&&edit.signer) {
+                continue;
+            }
+            if latest
+                .get(&e.message_id)
+                .is_some_and(|cur| cur.order_key() >= e.order_key())
+            {
+                continue;
+            }
+            latest.insert(e.message_id, e);
+        }
+        self.messages
+            .iter()
+            .map(|m| {
+                let mut m = m.clone();
+                if let Some(edit) = latest.get(&m.id) {
+                    m.body = edit.body.clone();
+                    m.sealed_body = edit.sealed_body.clone();
+                    m.edited_at = Some(edit.edited_at);
+                }
+                m
+            })
+            .collect()
+    }
 }
 
-/// Simple append-only log persisted as JSON on disk.
+/// What [`ChatStore::prune`] removed.
+#[derive(Debug, Default)]
+pub struct Pruned {
+    /// Ids of the messages removed.
+    pub messages: Vec<Uuid>,
+    /// Hashes of attachments removed, with their messages or on their own.
+    pub blobs: Vec<String>,
+    /// Number of kept messages whose attachments were removed.
+    pub stripped: usize,
+}
+
+impl Pruned {
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.stripped == 0
+    }
+}
+
+/// Source of store revisions, shared by all stores.
+static REVISIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Append-only message log with edit history, persisted as JSON on disk.
 pub struct ChatStore {
     path: PathBuf,
     data: RwLock<ChatSnapshot>,
+    /// Changes with every change to the store, so derived views know when to
+    /// rebuild. Drawn from [`REVISIONS`], so a reopened store never repeats one.
+    revision: AtomicU64,
 }
 
 impl ChatStore {
@@ -30,44 +125,755 @@ impl ChatStore {
         let data = if path.exists() {
             let raw =
                 fs::read_to_string(&path).with_context(|| format!("unable to read {:?}", path))?;
-            serde_json::from_str(&raw)
-                .with_context(|| format!("invalid store format {:?}", path))?
+            let mut snapshot: ChatSnapshot = serde_json::from_str(&raw)
+                .with_context(|| format!("invalid store format {:?}", path))?;
+            snapshot
+                .messages
+                .sort_by(|a, b| a.order_key().cmp(&b.order_key()));
+            snapshot
         } else {
             let snapshot = ChatSnapshot::default();
-            let serialized = serde_json::to_string_pretty(&snapshot)?;
-            fs::write(&path, serialized)?;
+            persist::save_json(&path, &snapshot)?;
             snapshot
         };
 
         Ok(Self {
             path,
             data: RwLock::new(data),
+            revision: AtomicU64::new(REVISIONS.fetch_add(1, Ordering::Relaxed)),
         })
     }
 
-    pub fn append(&self, message: ChatMessage) -> Result<()> {
-        {
+    /// Stores a message, its stamp clamped to the clock's drift bound. Returns
+    /// `false` when it is already stored or was deleted; edits and reactions
+    /// that arrived before a deleted message are dropped then.
+    pub fn append(&self, message: ChatMessage) -> Result<bool> {
+        let message = message.with_clamped_stamp();
+        let appended = {
             let mut guard = self.data.write();
             if guard.messages.iter().any(|m| m.id == message.id) {
-                return Ok(());
+                return Ok(false);
+            }
+            if guard.is_tombstoned(&message) {
+                let before = (guard.edits.len(), guard.reactions.len());
+                guard.edits.retain(|e| e.payload.message_id != message.id);
+                guard
+                    .reactions
+                    .retain(|r| r.payload.message_id != message.id);
+                if (guard.edits.len(), guard.reactions.len()) == before {
+                    return Ok(false);
+                }
+                false
+            } else {
+                let index = guard
+                    .messages
+                    .partition_point(|m| m.order_key() < message.order_key());
+                guard.messages.insert(index, message);
+                true
+            }
+        };
+        self.flush()?;
+        Ok(appended)
+    }
+
+    /// Whether `rule` would keep the message: it is within the age limit, and
+    /// newer than the oldest message when the store is at its count limit.
+    /// Messages it would prune straight away are not taken back from peers.
+    pub fn admits(&self, rule: &RetentionRule, message: &ChatMessage) -> bool {
+        let hlc = message.order_key().0.clamped();
+        if rule.cutoff().is_some_and(|cutoff| hlc < cutoff) {
+            return false;
+        }
+        let guard = self.data.read();
+        match (rule.max_messages, guard.messages.first()) {
+            (Some(max), Some(oldest)) => {
+                guard.messages.len() < max || message.order_key() > oldest.order_key()
+            }
+            _ => true,
+        }
+    }
+
+    /// Applies a retention rule: drops messages past the age, count and size
+    /// limits (oldest first) and attachments past the attachment age, along
+    /// with the edits, reactions and tombstones that only concern dropped
+    /// history, then rewrites the file.
+    pub fn prune(&self, rule: &RetentionRule) -> Result<Pruned> {
+        let mut pruned = Pruned::default();
+        {
+            let mut guard = self.data.write();
+            let cutoff = rule.cutoff();
+            // Stamps are clamped as on append, covering files written before
+            // that; clamping keeps the order, so pruning still drops a prefix.
+            let stamp = |m: &ChatMessage| m.order_key().0.clamped();
+            let mut keep_from = cutoff.map_or(0, |cutoff| {
+                guard.messages.partition_point(|m| stamp(m) < cutoff)
+            });
+            if let Some(max) = rule.max_messages {
+                keep_from = keep_from.max(guard.messages.len().saturating_sub(max));
+            }
+            if let Some(max_bytes) = rule.max_bytes {
+                let sizes: Vec<u64> = guard
+                    .messages
+                    .iter()
+                    .map(|m| serde_json::to_vec(m).map_or(0, |raw| raw.len() as u64))
+                    .collect();
+                let mut total: u64 = sizes[keep_from..].iter().sum();
+                while total > max_bytes && keep_from < sizes.len() {
+                    total -= sizes[keep_from];
+                    keep_from += 1;
+                }
+            }
+            let removed: Vec<ChatMessage> = guard.messages.drain(..keep_from).collect();
+            for message in &removed {
+                pruned.messages.push(message.id);
+                pruned
+                    .blobs
+                    .extend(message.attachments.iter().filter_map(|a| a.sha256.clone()));
+            }
+            if let Some(cutoff) = rule.attachment_cutoff() {
+                for message in guard.messages.iter_mut().filter(|m| stamp(m) < cutoff) {
+                    if message.attachments.is_empty() && message.sealed_attachments.is_none() {
+                        continue;
+                    }
+                    pruned.stripped += 1;
+                    pruned
+                        .blobs
+                        .extend(message.attachments.drain(..).filter_map(|a| a.sha256));
+                    message.sealed_attachments = None;
+                }
+            }
+            let kept: HashSet<Uuid> = guard.messages.iter().map(|m| m.id).collect();
+            let dropped: HashSet<Uuid> = pruned.messages.iter().copied().collect();
+            let before = (
+                guard.edits.len(),
+                guard.reactions.len(),
+                guard.tombstones.len(),
+            );
+            // Edits and reactions waiting for a message that has not arrived
+            // go once they are past the age limit, like the message would.
+            let current = |id: &Uuid, hlc: Option<Hlc>| {
+                kept.contains(id)
+                    || (!dropped.contains(id) && cutoff.is_none_or(|c| hlc.is_some_and(|h| h >= c)))
+            };
+            guard
+                .edits
+                .retain(|e| current(&e.payload.message_id, e.payload.hlc));
+            guard
+                .reactions
+                .retain(|r| current(&r.payload.message_id, r.payload.hlc));
+            // A message deleted before the cutoff was stamped before it too,
+            // so it would be refused anyway.
+            if let Some(cutoff) = cutoff {
+                guard
+                    .tombstones
+                    .retain(|t| t.payload.hlc.is_none_or(|h| h >= cutoff));
+            }
+            let after = (
+                guard.edits.len(),
+                guard.reactions.len(),
+                guard.tombstones.len(),
+            );
+            if pruned.is_empty() && before == after {
+                return Ok(pruned);
+            }
+        }
+        self.flush()?;
+        Ok(pruned)
+    }
+
+    /// Drops messages whose TTL, or the channel's `channel_ttl`, has run out,
+    /// along with their edits and reactions.
+    pub fn expire(&self, channel_ttl: Option<u32>) -> Result<Pruned> {
+        let mut pruned = Pruned::default();
+        {
+            let mut guard = self.data.write();
+            if !guard.messages.iter().any(|m| m.is_expired(channel_ttl)) {
+                return Ok(pruned);
+            }
+            guard.messages.retain(|message| {
+                if !message.is_expired(channel_ttl) {
+                    return true;
+                }
+                pruned.messages.push(message.id);
+                pruned
+                    .blobs
+                    .extend(message.attachments.iter().filter_map(|a| a.sha256.clone()));
+                false
+            });
+            let dropped: HashSet<Uuid> = pruned.messages.iter().copied().collect();
+            guard
+                .edits
+                .retain(|e| !dropped.contains(&e.payload.message_id));
+            guard
+                .reactions
+                .retain(|r| !dropped.contains(&r.payload.message_id));
+        }
+        self.flush()?;
+        Ok(pruned)
+    }
+
+    /// Records a signed edit. Edits for messages not yet received are kept and
+    /// take effect once the message arrives, provided the signer published it.
+    /// Returns `false` when the edit was already known.
+    pub fn apply_edit(&self, edit: Signed<MessageEdit>) -> Result<bool> {
+        {
+            let mut guard = self.data.write();
+            if guard.edits.iter().any(|e| e.payload.id == edit.payload.id) {
+                return Ok(false);
+            }
+            let deleted = guard.tombstones.iter().any(|t| {
+                t.payload.message_id == edit.payload.message_id
+                    && (t.payload.as_moderator || t.signer == edit.signer)
+            });
+            if deleted {
+                return Ok(false);
+            }
+            if let Some(message) = guard
+                .messages
+                .iter()
+                .find(|m| m.id == edit.payload.message_id)
+                && message.peer_id.as_ref() != Some(&edit.signer)
+            {
+                return Err(anyhow!("only the author can edit a message"));
+            }
+            guard.edits.push(edit);
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Records a signed deletion and removes the message together with its edit
+    /// history. Tombstones for messages not held yet are kept and prevent the
+    /// message from being appended later. Returns the removed message, if any.
+    pub fn delete(&self, tombstone: Signed<Tombstone>) -> Result<Option<ChatMessage>> {
+        let removed = {
+            let mut guard = self.data.write();
+            let known = guard.tombstones.iter().any(|t| {
+                t.payload.message_id == tombstone.payload.message_id && t.signer == tombstone.signer
+            });
+            if known {
+                return Ok(None);
+            }
+            let id = tombstone.payload.message_id;
+            let position = guard.messages.iter().position(|m| m.id == id);
+            if let Some(index) = position
+                && !tombstone
+                    .payload
+                    .applies_to(&tombstone.signer, &guard.messages[index])
+            {
+                return Err(anyhow!(
+                    "only the author or a moderator can delete a message"
+                ));
+            }
+            let removed = position.map(|index| guard.messages.remove(index));
+            if removed.is_some() {
+                guard.edits.retain(|e| e.payload.message_id != id);
+                guard.reactions.retain(|r| r.payload.message_id != id);
+            }
+            guard.tombstones.push(tombstone);
+            removed
+        };
+        self.flush()?;
+        Ok(removed)
+    }
+
+    /// Records a signed reaction if it is newer than the signer's last event for
+    /// the same message and emoji. Returns `false` when it was superseded.
+    /// Reactions with an invalid emoji are refused. Like edits, reactions for
+    /// messages not held yet are kept; whether a tombstone removes the message
+    /// can only be told once it arrives, and [`ChatStore::append`] drops them then.
+    pub fn apply_reaction(&self, reaction: Signed<Reaction>) -> Result<bool> {
+        if !Reaction::valid_emoji(&reaction.payload.emoji) {
+            return Err(anyhow!("invalid reaction emoji"));
+        }
+        {
+            let mut guard = self.data.write();
+            let key = |r: &Signed<Reaction>| {
+                (
+                    r.payload.message_id,
+                    r.signer.clone(),
+                    r.payload.emoji.clone(),
+                )
+            };
+            let incoming = key(&reaction);
+            if let Some(index) = guard.reactions.iter().position(|r| key(r) == incoming) {
+                let current = &guard.reactions[index].payload;
+                if current.order_key() >= reaction.payload.order_key() {
+                    return Ok(false);
+                }
+                guard.reactions[index] = reaction;
+            } else {
+                guard.reactions.push(reaction);
             }
-            guard.messages.push(message);
         }
-        self.flush()
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Active reactions per held message, grouped by emoji in first-seen order.
+    pub fn reactions(&self) -> HashMap<Uuid, Vec<ReactionSummary>> {
+        let guard = self.data.read();
+        let held: HashSet<Uuid> = guard.messages.iter().map(|m| m.id).collect();
+        let mut summaries: HashMap<Uuid, Vec<ReactionSummary>> = HashMap::new();
+        for reaction in guard
+            .reactions
+            .iter()
+            .filter(|r| r.payload.active && held.contains(&r.payload.message_id))
+        {
+            let entry = summaries.entry(reaction.payload.message_id).or_default();
+            match entry.iter_mut().find(|s| s.emoji == reaction.payload.emoji) {
+                Some(summary) => summary.peers.push(reaction.signer.clone()),
+                None => entry.push(ReactionSummary {
+                    emoji: reaction.payload.emoji.clone(),
+                    peers: vec![reaction.signer.clone()],
+                }),
+            }
+        }
+        summaries
+    }
+
+    pub fn tombstones(&self) -> Vec<Signed<Tombstone>> {
+        self.data.read().tombstones.clone()
+    }
+
+    /// Returns a single message with its latest edit applied.
+    pub fn message(&self, id: Uuid) -> Option<ChatMessage> {
+        self.messages().into_iter().find(|m| m.id == id)
+    }
+
+    /// Returns a thread's root message followed by its replies, in message order.
+    pub fn thread(&self, root: Uuid) -> Vec<ChatMessage> {
+        self.messages()
+            .into_iter()
+            .filter(|m| m.id == root || m.thread_root == Some(root))
+            .collect()
+    }
+
+    /// Returns the original body followed by each applied edit, oldest first.
+    pub fn history(&self, id: Uuid) -> Option<Vec<MessageRevision>> {
+        let guard = self.data.read();
+        let message = guard.messages.iter().find(|m| m.id == id)?;
+        let mut revisions = vec![MessageRevision {
+            body: message.body.clone(),
+            timestamp: message.timestamp,
+            sealed_body: message.sealed_body.clone(),
+        }];
+        revisions.extend(
+            guard
+                .edits_for(message)
+                .into_iter()
+                .map(|e| MessageRevision {
+                    body: e.body.clone(),
+                    timestamp: e.edited_at,
+                    sealed_body: e.sealed_body.clone(),
+                }),
+        );
+        Some(revisions)
+    }
+
+    /// Adds the hash of every stored attachment to `hashes`.
+    pub fn attachment_hashes(&self, hashes: &mut HashSet<String>) {
+        let guard = self.data.read();
+        let attachments = guard.messages.iter().flat_map(|m| &m.attachments);
+        hashes.extend(attachments.filter_map(|a| a.sha256.clone()));
+    }
+
+    /// Counter that changes whenever the store does.
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
     }
 
     fn flush(&self) -> Result<()> {
+        self.revision
+            .store(REVISIONS.fetch_add(1, Ordering::Relaxed), Ordering::Release);
         let snapshot = self.data.read().clone();
-        let contents = serde_json::to_string_pretty(&snapshot)?;
-        fs::write(&self.path, contents)?;
-        Ok(())
+        persist::save_json(&self.path, &snapshot)
+    }
+
+    /// Latest clock stamp of any stored message, used to restore the node clock.
+    pub fn latest_hlc(&self) -> Option<Hlc> {
+        self.data.read().messages.iter().filter_map(|m| m.hlc).max()
     }
 
+    /// Returns messages in their agreed total order (see [`ChatMessage::order_key`]).
     pub fn messages(&self) -> Vec<ChatMessage> {
-        self.data.read().messages.clone()
+        self.data.read().materialized()
     }
 
     pub fn len(&self) -> usize {
         self.data.read().messages.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.read().messages.is_empty()
+    }
+}
+
+/// Disposes of the file of a store that is no longer in use according to
+/// `policy`. Archived files go to `archive_dir` with a UTC timestamp suffix.
+pub fn retire_store_file(
+    path: &Path,
+    archive_dir: &Path,
+    policy: RemovedChannelPolicy,
+) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    match policy {
+        RemovedChannelPolicy::Keep => {}
+        RemovedChannelPolicy::Delete => fs::remove_file(path)?,
+        RemovedChannelPolicy::Archive => {
+            fs::create_dir_all(archive_dir)?;
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("messages");
+            let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S");
+            fs::rename(path, archive_dir.join(format!("{stem}-{stamp}.json")))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Deref;
+
+    use super::*;
+    use crate::Attachment;
+
+    /// A store in a temporary file, removed again when the test ends.
+    struct TempStore(ChatStore);
+
+    impl TempStore {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("gridspeak-store-{}.json", Uuid::new_v4()));
+            Self(ChatStore::open(path).unwrap())
+        }
+    }
+
+    impl Deref for TempStore {
+        type Target = ChatStore;
+
+        fn deref(&self) -> &ChatStore {
+            &self.0
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0.path);
+        }
+    }
+
+    fn hlc(wall: i64) -> Hlc {
+        Hlc { wall, logical: 0 }
+    }
+
+    /// Signatures are checked before events reach the store.
+    fn signed<T>(payload: T, signer: &str) -> Signed<T> {
+        Signed {
+            payload,
+            signer: signer.to_string(),
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    fn message(author: &str, body: &str, wall: i64) -> ChatMessage {
+        ChatMessage::new(author, body)
+            .with_peer_id(author)
+            .with_hlc(hlc(wall))
+    }
+
+    #[derive(Clone)]
+    enum Event {
+        Message(Box<ChatMessage>),
+        Reaction(Signed<Reaction>),
+        Tombstone(Signed<Tombstone>),
+    }
+
+    /// Applies `events` in every order and returns the reactions each order
+    /// leaves on the message, along with whether the message is held.
+    fn outcomes(message_id: Uuid, events: &[Event]) -> Vec<(bool, Vec<String>)> {
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        orders
+            .iter()
+            .map(|order| {
+                let store = TempStore::new();
+                for &i in order {
+                    // Refused events are part of what is being tested.
+                    let _ = match events[i].clone() {
+                        Event::Message(m) => store.append(*m).map(drop),
+                        Event::Reaction(r) => store.apply_reaction(r).map(drop),
+                        Event::Tombstone(t) => store.delete(t).map(drop),
+                    };
+                }
+                (
+                    store.message(message_id).is_some(),
+                    reacting_peers(&store, message_id),
+                )
+            })
+            .collect()
+    }
+
+    fn edit(message: &ChatMessage, body: &str, wall: i64, signer: &str) -> Signed<MessageEdit> {
+        signed(MessageEdit::new(message.id, body, hlc(wall)), signer)
+    }
+
+    #[test]
+    fn edits_apply_in_clock_order_and_only_from_the_author() {
+        let store = TempStore::new();
+        let m = message("alice", "v1", 1);
+        store.append(m.clone()).unwrap();
+
+        assert!(store.apply_edit(edit(&m, "v3", 3, "alice")).unwrap());
+        assert!(store.apply_edit(edit(&m, "v2", 2, "alice")).unwrap());
+        assert!(store.apply_edit(edit(&m, "forged", 4, "mallory")).is_err());
+
+        assert_eq!(store.message(m.id).unwrap().body, "v3");
+        let history: Vec<String> = store
+            .history(m.id)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.body)
+            .collect();
+        assert_eq!(history, ["v1", "v2", "v3"]);
+    }
+
+    #[test]
+    fn early_edits_take_effect_only_if_the_author_signed_them() {
+        let store = TempStore::new();
+        let m = message("alice", "v1", 1);
+        let known = edit(&m, "v2", 2, "alice");
+        assert!(store.apply_edit(edit(&m, "forged", 3, "mallory")).unwrap());
+        assert!(store.apply_edit(known.clone()).unwrap());
+        assert!(!store.apply_edit(known).unwrap());
+        store.append(m.clone()).unwrap();
+
+        assert_eq!(store.message(m.id).unwrap().body, "v2");
+        assert_eq!(store.history(m.id).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn tombstones_block_later_appends_unless_forged() {
+        let store = TempStore::new();
+        let deleted = message("alice", "gone", 1);
+        assert!(
+            store
+                .delete(signed(Tombstone::new(deleted.id, false, hlc(2)), "alice"))
+                .unwrap()
+                .is_none()
+        );
+        assert!(!store.append(deleted.clone()).unwrap());
+        assert!(store.message(deleted.id).is_none());
+
+        let kept = message("alice", "kept", 3);
+        store
+            .delete(signed(Tombstone::new(kept.id, false, hlc(4)), "mallory"))
+            .unwrap();
+        assert!(store.append(kept.clone()).unwrap());
+        assert!(store.message(kept.id).is_some());
+    }
+
+    #[test]
+    fn only_authors_and_moderators_delete_held_messages() {
+        let store = TempStore::new();
+        let m = message("alice", "hi", 1);
+        store.append(m.clone()).unwrap();
+        store.apply_edit(edit(&m, "edited", 2, "alice")).unwrap();
+
+        assert!(
+            store
+                .delete(signed(Tombstone::new(m.id, false, hlc(3)), "mallory"))
+                .is_err()
+        );
+        let removed = store
+            .delete(signed(Tombstone::new(m.id, true, hlc(3)), "moderator"))
+            .unwrap();
+        assert_eq!(removed.map(|r| r.id), Some(m.id));
+        assert!(store.is_empty());
+        assert!(store.history(m.id).is_none());
+        assert!(!store.append(m).unwrap());
+    }
+
+    fn react(message: &ChatMessage, active: bool, wall: i64) -> Signed<Reaction> {
+        signed(Reaction::new(message.id, "👍", active, hlc(wall)), "bob")
+    }
+
+    fn reacting_peers(store: &ChatStore, message_id: Uuid) -> Vec<String> {
+        store
+            .reactions()
+            .remove(&message_id)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|s| s.peers)
+            .collect()
+    }
+
+    #[test]
+    fn latest_reaction_event_wins() {
+        let store = TempStore::new();
+        let m = message("alice", "hi", 1);
+        store.append(m.clone()).unwrap();
+
+        assert!(store.apply_reaction(react(&m, true, 2)).unwrap());
+        assert!(store.apply_reaction(react(&m, false, 4)).unwrap());
+        assert!(!store.apply_reaction(react(&m, true, 3)).unwrap());
+        assert!(reacting_peers(&store, m.id).is_empty());
+
+        assert!(store.apply_reaction(react(&m, true, 5)).unwrap());
+        assert_eq!(reacting_peers(&store, m.id), ["bob"]);
+
+        let bad = signed(Reaction::new(m.id, "no spaces", true, hlc(6)), "bob");
+        assert!(store.apply_reaction(bad).is_err());
+    }
+
+    /// Clock wall time `days` days ago.
+    fn days_ago(days: i64) -> i64 {
+        chrono::Utc::now().timestamp_millis() - days * 24 * 60 * 60 * 1000
+    }
+
+    #[test]
+    fn prune_drops_history_past_the_age_and_count_limits() {
+        let store = TempStore::new();
+        let old = message("alice", "old", days_ago(10));
+        let mid = message("alice", "mid", days_ago(5));
+        let new = message("alice", "new", days_ago(1));
+        for m in [&new, &old, &mid] {
+            store.append(m.clone()).unwrap();
+        }
+        store
+            .apply_reaction(react(&old, true, days_ago(9)))
+            .unwrap();
+
+        let by_age = RetentionRule {
+            max_age_days: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(store.prune(&by_age).unwrap().messages, [old.id]);
+        assert!(store.data.read().reactions.is_empty());
+
+        let by_count = RetentionRule {
+            max_messages: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(store.prune(&by_count).unwrap().messages, [mid.id]);
+        assert_eq!(
+            store
+                .messages()
+                .into_iter()
+                .map(|m| m.id)
+                .collect::<Vec<_>>(),
+            [new.id]
+        );
+        assert!(store.prune(&by_count).unwrap().is_empty());
+    }
+
+    #[test]
+    fn prune_strips_old_attachments_but_keeps_their_messages() {
+        let store = TempStore::new();
+        let attachment = Attachment {
+            content_type: "text/plain".to_string(),
+            filename: "notes.txt".to_string(),
+            data_base64: "aGk=".to_string(),
+            size: Some(2),
+            sha256: Some("abc".to_string()),
+        };
+        let m = ChatMessage::with_attachments("alice", "see file", vec![attachment])
+            .with_peer_id("alice")
+            .with_hlc(hlc(days_ago(3)));
+        store.append(m.clone()).unwrap();
+
+        let rule = RetentionRule {
+            attachment_max_age_days: Some(2),
+            ..Default::default()
+        };
+        let pruned = store.prune(&rule).unwrap();
+        assert!(pruned.messages.is_empty());
+        assert_eq!(
+            (pruned.stripped, pruned.blobs),
+            (1, vec!["abc".to_string()])
+        );
+        assert!(store.message(m.id).unwrap().attachments.is_empty());
+    }
+
+    #[test]
+    fn messages_the_rule_would_prune_are_not_admitted() {
+        let store = TempStore::new();
+        store.append(message("alice", "new", days_ago(1))).unwrap();
+        let rule = RetentionRule {
+            max_age_days: Some(7),
+            max_messages: Some(1),
+            ..Default::default()
+        };
+
+        assert!(!store.admits(&rule, &message("bob", "ancient", days_ago(30))));
+        assert!(!store.admits(&rule, &message("bob", "older", days_ago(2))));
+        assert!(store.admits(&rule, &message("bob", "newer", days_ago(0))));
+    }
+
+    #[test]
+    fn expire_drops_messages_past_their_own_or_the_channel_ttl() {
+        let store = TempStore::new();
+        let sent = chrono::Utc::now().timestamp_millis() - 60 * 1000;
+        let short = message("alice", "short", sent).with_ttl(Some(30));
+        let long = message("alice", "long", sent).with_ttl(Some(3600));
+        let plain = message("alice", "plain", sent);
+        for m in [&short, &long, &plain] {
+            store.append(m.clone()).unwrap();
+        }
+        store
+            .apply_edit(edit(&short, "edited", sent + 1, "alice"))
+            .unwrap();
+        store.apply_reaction(react(&short, true, sent + 1)).unwrap();
+
+        assert_eq!(store.expire(None).unwrap().messages, [short.id]);
+        assert!(store.data.read().edits.is_empty() && store.data.read().reactions.is_empty());
+        assert!(store.expire(None).unwrap().is_empty());
+
+        let expired = store.expire(Some(10)).unwrap().messages;
+        assert_eq!(expired.len(), 2);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn reactions_converge_whatever_the_order_of_deletions() {
+        let m = message("alice", "hi", 1);
+        let reaction = Event::Reaction(signed(Reaction::new(m.id, "👍", true, hlc(2)), "bob"));
+
+        let forged = Event::Tombstone(signed(Tombstone::new(m.id, false, hlc(3)), "mallory"));
+        let results = outcomes(
+            m.id,
+            &[
+                Event::Message(Box::new(m.clone())),
+                reaction.clone(),
+                forged,
+            ],
+        );
+        assert!(
+            results
+                .iter()
+                .all(|r| *r == (true, vec!["bob".to_string()])),
+            "{results:?}"
+        );
+
+        let deleted = Event::Tombstone(signed(Tombstone::new(m.id, false, hlc(3)), "alice"));
+        let results = outcomes(
+            m.id,
+            &[Event::Message(Box::new(m.clone())), reaction, deleted],
+        );
+        assert!(
+            results.iter().all(|r| *r == (false, Vec::new())),
+            "{results:?}"
+        );
+    }
 }
@@ -1,17 +1,44 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, hash_map::Entry},
+    convert::Infallible,
     fs,
+    io::Write as _,
     net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, anyhow};
-use axum::{Json, Router, extract::{Path as AxumPath, Query, State}, http::StatusCode, response::IntoResponse, routing::{delete, get, post}};
+use axum::{
+    Json, Router,
+    extract::{Multipart, Path as AxumPath, Query, State},
+    http::{StatusCode, header},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{delete, get, patch, post, put},
+};
 use clap::{Args, Parser, Subcommand};
-use futures::StreamExt;
-use gridspeak_core::{Attachment, ChatMessage, ChatStore, NodeConfig, load_or_create_config};
+use futures::{Stream, StreamExt};
+use gridspeak_core::{
+    Attachment, Avatar, BlobStore, Channel, ChannelKey, ChannelNotifications, ChannelRemoval,
+    ChannelRole, ChannelSet, ChatMessage, ChatStore, DEFAULT_CHANNEL, DirectMessage, Heartbeat,
+    Hlc, HybridClock, MAX_PINS, Mention, MessageEdit, MessageRevision, ModerationAction,
+    ModerationList, NodeConfig, NotificationReason, NotificationSettings, NotifyLevel, Outbox, Pin,
+    PresenceBook, PresenceEntry, PresenceStatus, Profile, ProfileBook, Reaction, ReactionSummary,
+    ReadMarker, ReadMarkers, RemovedChannelPolicy, RetentionConfig, RetentionRule, RoleGrant,
+    Sanction, Schedule, ScheduledMessage, SearchHit, SearchIndex, SearchQuery, Signed, Tombstone,
+    crypto::{self, GroupKey, PublicKey, StaticSecret},
+    direct, load_json, load_or_create_config, parse_mentions,
+    presence::HEARTBEAT_SECS,
+    profile::{
+        AVATAR_CONTENT_TYPES, MAX_AVATAR_BYTES, MAX_BIO_CHARS, MAX_DISPLAY_NAME_CHARS,
+        MAX_PRONOUNS_CHARS, MAX_STATUS_CHARS,
+    },
+    retire_store_file, save_json, search, signing_bytes, validate_channel_name,
+};
 use libp2p::{
     Multiaddr, PeerId, Swarm, SwarmBuilder, gossipsub, identify, identity, mdns, noise,
     swarm::SwarmEvent, tcp, yamux,
@@ -21,10 +48,11 @@ use serde::{Deserialize, Serialize};
 use tokio::{
     io::{self, AsyncBufReadExt, BufReader},
     net::TcpListener,
-    sync::mpsc,
+    sync::{broadcast, mpsc, oneshot},
 };
 use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "GridSpeak mesh node", long_about = None)]
@@ -102,20 +130,62 @@ pub struct VoiceSignal {
     pub data: String,
 }
 
-/// API request: send a message to a channel, broadcast channel list, or broadcast channel removed.
+/// API request: send, edit, delete or react to a message, send a direct
+/// message, issue a moderation action, update this node's profile, announce
+/// its presence status or typing state, or broadcast the channel set.
 pub enum ApiRequest {
-    SendMessage { channel: String, message: ChatMessage },
-    BroadcastChannelList,
-    BroadcastChannelRemoved(String),
+    SendMessage {
+        channel: String,
+        message: ChatMessage,
+        sent: oneshot::Sender<Result<()>>,
+    },
+    SendDirect {
+        dm: DirectMessage,
+    },
+    EditMessage {
+        channel: String,
+        edit: Signed<MessageEdit>,
+    },
+    DeleteMessage {
+        channel: String,
+        tombstone: Signed<Tombstone>,
+    },
+    React {
+        channel: String,
+        reaction: Signed<Reaction>,
+    },
+    Moderate {
+        action: Signed<ModerationAction>,
+    },
+    UpdateProfile {
+        profile: Signed<Profile>,
+    },
+    AnnouncePresence,
+    Typing {
+        channel: String,
+        typing: bool,
+    },
+    BroadcastChannels,
 }
 
-/// Per-channel message stores and channel list (synced via gossip).
+/// Per-channel message stores and the replicated channel set (synced via gossip).
 #[derive(Clone)]
 struct ChannelState {
     data_dir: PathBuf,
     config_path: PathBuf,
-    channels: Arc<RwLock<Vec<String>>>,
+    channels: Arc<RwLock<ChannelSet>>,
     stores: Arc<RwLock<HashMap<String, Arc<ChatStore>>>>,
+    blobs: Arc<BlobStore>,
+    moderators: Arc<Vec<String>>,
+    /// Grid-wide bans and mutes issued by moderators.
+    moderation: Arc<RwLock<ModerationList>>,
+    /// Latest signed profile of every known peer.
+    profiles: Arc<RwLock<ProfileBook>>,
+    /// Full-text index over the plaintext messages of all stores.
+    search: Arc<RwLock<SearchIndex>>,
+    clock: Arc<HybridClock>,
+    removed_channel_files: RemovedChannelPolicy,
+    retention: Arc<RetentionConfig>,
 }
 
 impl ChannelState {
@@ -125,689 +195,4701 @@ impl ChannelState {
         if legacy.exists() && !general_path.exists() {
             fs::rename(&legacy, &general_path)?;
         }
+        // Channels listed in the config but never seen by the set (first run,
+        // or added by hand) are seeded into it.
+        let set_path = config.data_dir.join("channels.json");
+        let mut channels = ChannelSet::load(&set_path)?;
+        let mut seeded = false;
+        for ch in config
+            .channels
+            .iter()
+            .map(String::as_str)
+            .chain([DEFAULT_CHANNEL])
+        {
+            if validate_channel_name(ch).is_ok()
+                && !channels.knows(ch)
+                && channels.find_by_name(ch).is_none()
+            {
+                seeded |= channels.add(ch);
+            }
+        }
+        if seeded {
+            channels.save(&set_path)?;
+        }
         let mut stores = HashMap::new();
-        for ch in &config.channels {
+        let clock = HybridClock::default();
+        let mut search = SearchIndex::default();
+        for ch in channels.ids() {
             let path = config.data_dir.join(format!("messages-{}.json", ch));
-            stores.insert(ch.clone(), Arc::new(ChatStore::open(path)?));
+            let store = ChatStore::open(path)?;
+            if let Some(hlc) = store.latest_hlc() {
+                clock.observe(hlc);
+            }
+            for message in store.messages() {
+                search.insert(&ch, &message);
+            }
+            stores.insert(ch, Arc::new(store));
         }
-        Ok(Self {
+        let state = Self {
             data_dir: config.data_dir.clone(),
             config_path: config_path.to_path_buf(),
-            channels: Arc::new(RwLock::new(config.channels.clone())),
+            channels: Arc::new(RwLock::new(channels)),
             stores: Arc::new(RwLock::new(stores)),
-        })
+            blobs: Arc::new(BlobStore::open(config.data_dir.join("blobs"))?),
+            moderators: Arc::new(config.moderators.clone()),
+            moderation: Arc::new(RwLock::new(ModerationList::load(
+                config.data_dir.join("moderation.json"),
+            )?)),
+            profiles: Arc::new(RwLock::new(ProfileBook::load(
+                config.data_dir.join("profiles.json"),
+            )?)),
+            search: Arc::new(RwLock::new(search)),
+            clock: Arc::new(clock),
+            removed_channel_files: config.removed_channel_files,
+            retention: Arc::new(config.retention.clone()),
+        };
+        state.persist_channel_list()?;
+        Ok(state)
     }
 
-    fn list(&self) -> Vec<String> {
+    /// Ids of all present channels; stores and message files are keyed by id.
+    fn ids(&self) -> Vec<String> {
+        self.channels.read().ids()
+    }
+
+    /// Resolves a channel name, id or recent former name to its id.
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.channels.read().resolve(&name.trim().to_lowercase())
+    }
+
+    fn find_by_name(&self, name: &str) -> Option<String> {
+        self.channels.read().find_by_name(name)
+    }
+
+    fn channel_set(&self) -> ChannelSet {
         self.channels.read().clone()
     }
 
+    fn channel_set_path(&self) -> PathBuf {
+        self.data_dir.join("channels.json")
+    }
+
+    /// Messages of a channel that have not expired yet.
     fn messages(&self, channel: &str) -> Vec<ChatMessage> {
-        self.stores
-            .read()
-            .get(channel)
+        let ttl = self.message_ttl(channel);
+        let mut messages = self
+            .get_store(channel)
             .map(|s| s.messages())
-            .unwrap_or_default()
+            .unwrap_or_default();
+        messages.retain(|m| !m.is_expired(ttl));
+        messages
+    }
+
+    /// Default TTL of the channel's messages, set on its record.
+    fn message_ttl(&self, channel: &str) -> Option<u32> {
+        self.channels
+            .read()
+            .meta(channel)
+            .and_then(|record| record.payload.message_ttl_secs)
     }
 
     fn get_store(&self, channel: &str) -> Option<Arc<ChatStore>> {
         self.stores.read().get(channel).cloned()
     }
 
-    fn add_channel_local(&self, name: &str) -> Result<()> {
-        let name = name.trim().to_lowercase();
-        if name.is_empty() || name.len() > 64 {
-            return Err(anyhow!("invalid channel name"));
+    fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        self.search.read().search(query)
+    }
+
+    /// Records of all present channels, in name order.
+    fn describe_channels(&self) -> Vec<Channel> {
+        let channels = self.channels.read();
+        channels
+            .ids()
+            .iter()
+            .map(|id| channels.describe(id))
+            .collect()
+    }
+
+    fn describe_channel(&self, id: &str) -> Option<Channel> {
+        let channels = self.channels.read();
+        channels.contains(id).then(|| channels.describe(id))
+    }
+
+    /// Creates a channel called `name` unless one already exists; `record`
+    /// builds its signed record from the id chosen for it. Returns the id of
+    /// the new channel.
+    fn add_channel_local(
+        &self,
+        name: &str,
+        record: impl FnOnce(&str) -> Result<Signed<Channel>>,
+    ) -> Result<Option<String>> {
+        validate_channel_name(name)?;
+        let id = {
+            let mut channels = self.channels.write();
+            if channels.find_by_name(name).is_some() {
+                return Ok(None);
+            }
+            let id = channels.new_id(name);
+            let record = record(&id)?;
+            record.payload.validate()?;
+            channels.add(&id);
+            channels.set_meta(record);
+            channels.save(self.channel_set_path())?;
+            id
+        };
+        self.apply_membership()?;
+        Ok(Some(id))
+    }
+
+    /// Stores an updated record for an existing channel.
+    fn update_channel_local(&self, record: Signed<Channel>) -> Result<()> {
+        record.payload.validate()?;
+        let mut channels = self.channels.write();
+        if !channels.contains(record.payload.id()) {
+            return Err(anyhow!("unknown channel"));
         }
-        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
-            return Err(anyhow!("channel name must be alphanumeric, - or _"));
+        if channels.set_meta(record) {
+            channels.save(self.channel_set_path())?;
         }
+        Ok(())
+    }
+
+    /// Merges a peer's channel set; returns `true` if the local set changed.
+    fn merge_channels_from_remote(&self, remote: &ChannelSet) -> Result<bool> {
         {
-            let ch = self.channels.read();
-            if ch.contains(&name) {
-                return Ok(());
+            let mut channels = self.channels.write();
+            if !channels.merge(remote, |peer| self.is_moderator(peer)) {
+                return Ok(false);
+            }
+            channels.save(self.channel_set_path())?;
+        }
+        self.apply_membership()?;
+        Ok(true)
+    }
+
+    /// Handles a bare name list from nodes that predate the replicated set.
+    /// Names the set has never seen are added; known ones are left alone so a
+    /// stale list cannot resurrect a deleted channel.
+    fn merge_legacy_channel_list(&self, list: &[String]) -> Result<()> {
+        let mut remote = self.channel_set();
+        for name in list {
+            if validate_channel_name(name).is_ok() && !remote.knows(name) {
+                remote.add(name);
             }
         }
-        let path = self.data_dir.join(format!("messages-{}.json", name));
-        let store = Arc::new(ChatStore::open(path)?);
+        self.merge_channels_from_remote(&remote).map(|_| ())
+    }
+
+    /// Removes a channel with a removal signed by `sign`.
+    fn remove_channel_local(
+        &self,
+        id: &str,
+        sign: impl FnOnce(ChannelRemoval) -> Result<Signed<ChannelRemoval>>,
+    ) -> Result<()> {
+        if id == DEFAULT_CHANNEL {
+            return Err(anyhow!("cannot delete #general"));
+        }
         {
-            let mut stores = self.stores.write();
-            stores.insert(name.clone(), store);
+            let mut channels = self.channels.write();
+            let Some(removal) = channels.removal(id, self.clock.tick()) else {
+                return Ok(());
+            };
+            channels.apply_removal(sign(removal)?);
+            channels.save(self.channel_set_path())?;
         }
+        self.apply_membership()
+    }
+
+    /// Removes a channel on the word of a node that predates signed removals.
+    fn remove_channel(&self, name: &str) -> Result<()> {
         {
             let mut channels = self.channels.write();
-            channels.push(name.clone());
+            if !channels.remove(name) {
+                return Ok(());
+            }
+            channels.save(self.channel_set_path())?;
         }
-        let mut config = load_or_create_config(&self.config_path)?;
-        config.add_channel(&name, &self.config_path)?;
-        Ok(())
+        self.apply_membership()
     }
 
-    fn merge_channels_from_remote(&self, list: &[String]) {
-        let mut channels = self.channels.write();
-        let mut stores = self.stores.write();
-        for name in list {
-            if !channels.contains(name) {
-                channels.push(name.clone());
-                let path = self.data_dir.join(format!("messages-{}.json", name));
-                if let Ok(store) = ChatStore::open(path) {
-                    stores.insert(name.clone(), Arc::new(store));
+    /// Brings stores, message files and the config in line with the channel
+    /// set after any change, whether it was made locally or learned from a
+    /// peer. Files of removed channels are handled per `removed_channel_files`.
+    fn apply_membership(&self) -> Result<()> {
+        let ids = self.ids();
+        {
+            let mut stores = self.stores.write();
+            let removed: Vec<String> = stores
+                .keys()
+                .filter(|id| !ids.contains(id))
+                .cloned()
+                .collect();
+            for id in removed {
+                stores.remove(&id);
+                self.search.write().remove_channel(&id);
+                let path = self.data_dir.join(format!("messages-{}.json", id));
+                retire_store_file(
+                    &path,
+                    &self.data_dir.join("archive"),
+                    self.removed_channel_files,
+                )?;
+                info!(channel = %id, policy = ?self.removed_channel_files, "channel removed");
+            }
+            for id in &ids {
+                if let Entry::Vacant(entry) = stores.entry(id.clone()) {
+                    let path = self.data_dir.join(format!("messages-{}.json", id));
+                    let store = ChatStore::open(path)?;
+                    let mut search = self.search.write();
+                    for message in store.messages() {
+                        search.insert(id, &message);
+                    }
+                    entry.insert(Arc::new(store));
                 }
             }
         }
+        self.persist_channel_list()
     }
 
-    fn remove_channel_local(&self, name: &str) -> Result<()> {
-        if name == "general" {
-            return Err(anyhow!("cannot delete #general"));
-        }
-        self.remove_channel_in_memory(name);
+    /// Mirrors the channel set (by id) into `channels` in the config file.
+    fn persist_channel_list(&self) -> Result<()> {
         let mut config = load_or_create_config(&self.config_path)?;
-        config.remove_channel(name, &self.config_path)?;
-        Ok(())
+        config.set_channels(self.ids(), &self.config_path)
     }
 
-    fn remove_channel_in_memory(&self, name: &str) {
-        let mut channels = self.channels.write();
-        channels.retain(|c| c != name);
-        let mut stores = self.stores.write();
-        stores.remove(name);
+    /// Stores and indexes a message. Returns `false` when the channel is
+    /// unknown, the message was already stored or deleted, or it has expired
+    /// or is past the channel's retention rule.
+    fn append_message(&self, channel: &str, message: ChatMessage) -> Result<bool> {
+        let Some(store) = self.get_store(channel) else {
+            return Ok(false);
+        };
+        let message = message.with_clamped_stamp();
+        if message.is_expired(self.message_ttl(channel)) {
+            return Ok(false);
+        }
+        if !store.admits(&self.retention_rule(channel), &message)
+            || !store.append(message.clone())?
+        {
+            return Ok(false);
+        }
+        self.search.write().insert(channel, &message);
+        Ok(true)
+    }
+
+    fn retention_rule(&self, channel: &str) -> RetentionRule {
+        let name = self.channels.read().describe(channel).name;
+        self.retention.rule_for(channel, &name)
     }
 
-    fn append_message(&self, channel: &str, message: ChatMessage) -> Result<()> {
-        if let Some(store) = self.stores.read().get(channel) {
-            store.append(message)?;
+    /// Prunes every channel to its retention rule, dropping the pruned
+    /// messages from the search index and their blobs from the blob store.
+    fn apply_retention(&self) -> Result<()> {
+        for channel in self.ids() {
+            let rule = self.retention_rule(&channel);
+            let Some(store) = self.get_store(&channel).filter(|_| !rule.is_unlimited()) else {
+                continue;
+            };
+            let pruned = store.prune(&rule)?;
+            if pruned.is_empty() {
+                continue;
+            }
+            {
+                let mut search = self.search.write();
+                for id in &pruned.messages {
+                    search.remove(*id);
+                }
+                if pruned.stripped > 0 {
+                    for message in store.messages() {
+                        search.insert(&channel, &message);
+                    }
+                }
+            }
+            self.release_blobs(pruned.blobs.iter().map(String::as_str))?;
+            info!(%channel, messages = pruned.messages.len(), stripped = pruned.stripped, "pruned channel history");
         }
         Ok(())
     }
-}
-
-#[derive(Clone)]
-struct ApiContext {
-    channel_state: ChannelState,
-    sender: mpsc::Sender<ApiRequest>,
-    fallback_author: String,
-    telemetry: Telemetry,
-    peer_id: String,
-    voice_tx: mpsc::Sender<VoiceSignal>,
-    voice_signals: Arc<RwLock<Vec<VoiceSignal>>>,
-}
 
-#[derive(Clone, Default)]
-struct Telemetry {
-    peers: Arc<RwLock<HashSet<String>>>,
-    last_message: Arc<RwLock<Option<String>>>,
-}
+    /// Deletes expired messages from every channel, along with their blobs
+    /// and search entries.
+    fn expire_messages(&self) -> Result<()> {
+        for channel in self.ids() {
+            let Some(store) = self.get_store(&channel) else {
+                continue;
+            };
+            let expired = store.expire(self.message_ttl(&channel))?;
+            if expired.is_empty() {
+                continue;
+            }
+            {
+                let mut search = self.search.write();
+                for id in &expired.messages {
+                    search.remove(*id);
+                }
+            }
+            self.release_blobs(expired.blobs.iter().map(String::as_str))?;
+            info!(%channel, messages = expired.messages.len(), "expired messages deleted");
+        }
+        Ok(())
+    }
 
-impl Telemetry {
-    fn note_peer_online(&self, peer: &PeerId) {
-        self.peers.write().insert(peer.to_string());
+    /// Returns `false` when the channel is unknown or the edit was already applied.
+    fn apply_edit(&self, channel: &str, edit: Signed<MessageEdit>) -> Result<bool> {
+        let Some(store) = self.get_store(channel) else {
+            return Ok(false);
+        };
+        let message_id = edit.payload.message_id;
+        if !store.apply_edit(edit)? {
+            return Ok(false);
+        }
+        if let Some(message) = store.message(message_id) {
+            self.search.write().insert(channel, &message);
+        }
+        Ok(true)
     }
 
-    fn note_peer_offline(&self, peer: &PeerId) {
-        self.peers.write().remove(&peer.to_string());
+    /// Advances the node clock past a stamp carried by a received event.
+    fn observe(&self, hlc: Option<Hlc>) {
+        if let Some(hlc) = hlc {
+            self.clock.observe(hlc);
+        }
     }
 
-    fn note_message(&self, timestamp: String) {
-        *self.last_message.write() = Some(timestamp);
+    /// Whether the peer is a grid moderator (listed under `moderators` in the config).
+    fn is_moderator(&self, peer_id: &str) -> bool {
+        self.moderators.iter().any(|m| m == peer_id)
     }
 
-    fn snapshot(&self) -> TelemetrySnapshot {
-        TelemetrySnapshot {
-            peers: self.peers.read().iter().cloned().collect(),
-            last_message: self.last_message.read().clone(),
+    /// Records a moderation action; only grid moderators may issue them.
+    /// Returns `false` when a newer action for the same peer and sanction is known.
+    fn apply_moderation(&self, action: Signed<ModerationAction>) -> Result<bool> {
+        if !self.is_moderator(&action.signer) {
+            return Err(anyhow!(
+                "moderation action from non-moderator {}",
+                action.signer
+            ));
         }
+        let mut list = self.moderation.write();
+        if !list.apply(action) {
+            return Ok(false);
+        }
+        list.save(self.data_dir.join("moderation.json"))?;
+        Ok(true)
     }
-}
 
-struct TelemetrySnapshot {
-    peers: Vec<String>,
-    last_message: Option<String>,
-}
+    fn moderation_actions(&self) -> Vec<Signed<ModerationAction>> {
+        self.moderation.read().actions().to_vec()
+    }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    tracing_subscriber::fmt()
-        .with_env_filter(env_filter)
-        .with_target(false)
-        .init();
+    fn is_banned(&self, peer_id: &str) -> bool {
+        self.moderation.read().is_banned(peer_id)
+    }
 
-    let cli = Cli::parse();
-    let config_path = cli.config.unwrap_or_else(default_config_path);
-    match cli
-        .command
-        .unwrap_or_else(|| Commands::Run(RunCommand::default()))
-    {
-        Commands::Run(run) => run_node(run, config_path).await,
-        Commands::PrintConfig => {
-            let cfg = load_or_create_config(&config_path)?;
-            println!("{}", toml::to_string_pretty(&cfg)?);
-            Ok(())
+    fn is_muted(&self, peer_id: &str) -> bool {
+        self.moderation.read().is_muted(peer_id)
+    }
+
+    fn profile(&self, peer_id: &str) -> Option<Signed<Profile>> {
+        self.profiles.read().get(peer_id).cloned()
+    }
+
+    fn profiles(&self) -> Vec<Signed<Profile>> {
+        self.profiles.read().all().cloned().collect()
+    }
+
+    /// Records a signed profile, keeping the newest per peer. Avatar bytes are
+    /// stored when they match the avatar of the peer's current profile, and a
+    /// replaced avatar is released. Returns `false` when the profile was stale.
+    fn apply_profile(&self, profile: Signed<Profile>, avatar: Option<&[u8]>) -> Result<bool> {
+        let peer_id = profile.signer.clone();
+        let previous = self.profile(&peer_id).and_then(|p| p.payload.avatar);
+        let applied = {
+            let mut profiles = self.profiles.write();
+            let applied = profiles.apply(profile);
+            if applied {
+                profiles.save(self.data_dir.join("profiles.json"))?;
+            }
+            applied
+        };
+        let current = self.profile(&peer_id).and_then(|p| p.payload.avatar);
+        if let (Some(current), Some(bytes)) = (&current, avatar)
+            && !self.blobs.path(&current.sha256).exists()
+        {
+            let info = self.blobs.put(bytes)?;
+            if info.sha256 != current.sha256 {
+                self.release_blobs([info.sha256.as_str()])?;
+                warn!(%peer_id, "dropping avatar that does not match the profile");
+            }
         }
+        if let Some(previous) = previous
+            && current.as_ref().is_none_or(|c| c.sha256 != previous.sha256)
+        {
+            self.release_blobs([previous.sha256.as_str()])?;
+        }
+        Ok(applied)
     }
-}
 
-fn default_config_path() -> PathBuf {
-    NodeConfig::config_file(&NodeConfig::default().data_dir)
-}
+    fn roles(&self, channel: &str) -> BTreeMap<String, ChannelRole> {
+        self.channels
+            .read()
+            .roles(channel, |peer| self.is_moderator(peer))
+    }
 
-async fn run_node(args: RunCommand, config_path: PathBuf) -> Result<()> {
-    let RunCommand {
-        listen,
-        topic,
-        api_bind,
-    } = args;
-    let config = load_or_create_config(&config_path)
-        .with_context(|| format!("unable to load config {:?}", config_path))?;
+    fn may_manage(&self, channel: &str, peer_id: &str) -> bool {
+        self.channels
+            .read()
+            .may_manage(channel, peer_id, |peer| self.is_moderator(peer))
+    }
 
-    let listen_addr: Multiaddr = listen
-        .as_deref()
-        .unwrap_or("/ip4/0.0.0.0/tcp/0")
-        .parse()
-        .map_err(|e| anyhow!("invalid listen address: {e}"))?;
+    fn may_moderate(&self, channel: &str, peer_id: &str) -> bool {
+        self.channels
+            .read()
+            .may_moderate(channel, peer_id, |peer| self.is_moderator(peer))
+    }
 
-    let api_socket = {
-        let trimmed = api_bind.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(
-                trimmed
-                    .parse::<SocketAddr>()
-                    .map_err(|err| anyhow!("invalid API bind address: {err}"))?,
-            )
-        }
-    };
+    fn may_own(&self, channel: &str, peer_id: &str) -> bool {
+        self.channels
+            .read()
+            .may_own(channel, peer_id, |peer| self.is_moderator(peer))
+    }
 
-    let topic_name = topic.unwrap_or(config.topic.clone());
-    let topic = gossipsub::IdentTopic::new(topic_name.clone());
+    fn is_private(&self, channel: &str) -> bool {
+        self.channels.read().describe(channel).private
+    }
 
-    let config_path = config_path.clone();
-    let channel_state = ChannelState::open(&config, &config_path)?;
+    /// Members of a private channel are the peers holding any role in it.
+    fn is_member(&self, channel: &str, peer_id: &str) -> bool {
+        self.roles(channel).contains_key(peer_id)
+    }
 
-    let telemetry = Telemetry::default();
+    fn channel_keys(&self, channel: &str) -> Vec<Signed<ChannelKey>> {
+        self.channels.read().keys(channel).cloned().collect()
+    }
 
-    let identity_path = config.data_dir.join("identity.bin");
-    let local_key = load_or_create_identity(&identity_path)?;
-    let local_peer_id = PeerId::from(local_key.public());
-    info!(%local_peer_id, "node identity loaded");
+    fn current_key(&self, channel: &str) -> Option<Signed<ChannelKey>> {
+        self.channels.read().current_key(channel).cloned()
+    }
 
-    let (api_tx, mut api_rx) = mpsc::channel::<ApiRequest>(32);
-    let (voice_tx, mut voice_rx) = mpsc::channel::<VoiceSignal>(64);
-    let voice_signals: Arc<RwLock<Vec<VoiceSignal>>> = Arc::new(RwLock::new(Vec::new()));
-    const MAX_VOICE_SIGNALS: usize = 200;
-    let mut api_enabled = false;
-    if let Some(bind) = api_socket {
-        api_enabled = true;
-        let api_state = ApiContext {
-            channel_state: channel_state.clone(),
-            sender: api_tx.clone(),
-            fallback_author: config.nickname.clone(),
-            telemetry: telemetry.clone(),
-            peer_id: local_peer_id.to_string(),
-            voice_tx: voice_tx.clone(),
-            voice_signals: voice_signals.clone(),
-        };
-        tokio::spawn(async move {
-            if let Err(err) = serve_api(bind, api_state).await {
-                warn!(%err, "api server stopped");
-            }
-        });
-        info!(%bind, "api server listening");
+    fn set_key(&self, key: Signed<ChannelKey>) -> Result<()> {
+        let mut channels = self.channels.write();
+        if channels.set_key(key) {
+            channels.save(self.channel_set_path())?;
+        }
+        Ok(())
     }
 
-    let mut swarm = build_swarm(local_key, topic.clone()).await?;
-    swarm.listen_on(listen_addr)?;
+    /// Pinned messages of a channel, most recently pinned first.
+    fn pins(&self, channel: &str) -> Vec<Signed<Pin>> {
+        self.channels
+            .read()
+            .pins(channel)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
 
-    for addr in &config.bootstrap_nodes {
-        match addr.parse::<Multiaddr>() {
-            Ok(multiaddr) => {
-                if let Err(err) = swarm.dial(multiaddr.clone()) {
-                    warn!(%multiaddr, %err, "failed to dial bootstrap node");
-                }
-            }
-            Err(err) => warn!(%addr, %err, "invalid bootstrap address"),
+    fn set_pin(&self, pin: Signed<Pin>) -> Result<()> {
+        let mut channels = self.channels.write();
+        if channels.set_pin(pin) {
+            channels.save(self.channel_set_path())?;
         }
+        Ok(())
     }
 
-    let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(16);
-    tokio::spawn(read_stdin(stdin_tx));
-    let mut shutdown = Box::pin(tokio::signal::ctrl_c());
-    let mut stdin_done = false;
+    fn add_grant(&self, grant: Signed<RoleGrant>) -> Result<()> {
+        let mut channels = self.channels.write();
+        if channels.add_grant(grant) {
+            channels.save(self.channel_set_path())?;
+        }
+        Ok(())
+    }
 
-    loop {
-        tokio::select! {
+    /// Applies a signed deletion and drops uploaded blobs no other message refers to.
+    fn delete_message(&self, channel: &str, tombstone: Signed<Tombstone>) -> Result<()> {
+        let Some(store) = self.get_store(channel) else {
+            return Ok(());
+        };
+        let Some(removed) = store.delete(tombstone)? else {
+            return Ok(());
+        };
+        self.search.write().remove(removed.id);
+        self.release_blobs(
+            removed
+                .attachments
+                .iter()
+                .filter_map(|a| a.sha256.as_deref()),
+        )
+    }
+
+    /// Removes uploaded blobs that no stored attachment or avatar refers to any more.
+    fn release_blobs<'a>(&self, hashes: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let mut hashes = hashes.into_iter().peekable();
+        if hashes.peek().is_none() {
+            return Ok(());
+        }
+        let mut referenced = HashSet::new();
+        for store in self.stores.read().values() {
+            store.attachment_hashes(&mut referenced);
+        }
+        let profiles = self.profiles.read();
+        for hash in hashes {
+            if !referenced.contains(hash) && !profiles.references(hash) {
+                self.blobs.remove(hash)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tombstones(&self) -> Vec<(String, Vec<Signed<Tombstone>>)> {
+        self.stores
+            .read()
+            .iter()
+            .map(|(name, store)| (name.clone(), store.tombstones()))
+            .filter(|(_, tombstones)| !tombstones.is_empty())
+            .collect()
+    }
+
+    /// Looks a message up by id across all channels.
+    fn find_message(&self, id: Uuid) -> Option<(String, ChatMessage)> {
+        let found = self
+            .stores
+            .read()
+            .iter()
+            .find_map(|(name, store)| store.message(id).map(|m| (name.clone(), m)));
+        found.filter(|(channel, message)| !message.is_expired(self.message_ttl(channel)))
+    }
+
+    /// Revisions of a message that has not expired.
+    fn history(&self, id: Uuid) -> Option<Vec<MessageRevision>> {
+        let (channel, _) = self.find_message(id)?;
+        self.get_store(&channel)?.history(id)
+    }
+
+    /// Returns `false` when the channel is unknown or the reaction was superseded.
+    fn apply_reaction(&self, channel: &str, reaction: Signed<Reaction>) -> Result<bool> {
+        match self.get_store(channel) {
+            Some(store) => store.apply_reaction(reaction),
+            None => Ok(false),
+        }
+    }
+
+    fn reactions(&self, channel: &str) -> HashMap<Uuid, Vec<ReactionSummary>> {
+        self.get_store(channel)
+            .map(|s| s.reactions())
+            .unwrap_or_default()
+    }
+
+    /// Unexpired messages of the thread started by `root`.
+    fn thread(&self, channel: &str, root: Uuid) -> Vec<ChatMessage> {
+        let ttl = self.message_ttl(channel);
+        let mut thread = self
+            .get_store(channel)
+            .map(|s| s.thread(root))
+            .unwrap_or_default();
+        thread.retain(|m| !m.is_expired(ttl));
+        thread
+    }
+}
+
+/// Direct message conversations, one store per peer with messages kept sealed
+/// at rest, plus the outbox of DMs waiting for their recipient.
+#[derive(Clone)]
+struct DirectMessages {
+    dir: PathBuf,
+    outbox_path: PathBuf,
+    local_peer: String,
+    secret: Option<StaticSecret>,
+    /// Grid topic, carrying DMs for peers that are not reachable directly.
+    topic: gossipsub::IdentTopic,
+    conversations: Arc<RwLock<HashMap<String, Arc<ChatStore>>>>,
+    outbox: Arc<RwLock<Outbox>>,
+}
+
+impl DirectMessages {
+    fn open(
+        data_dir: &Path,
+        topic: gossipsub::IdentTopic,
+        local_peer: String,
+        secret: Option<StaticSecret>,
+    ) -> Result<Self> {
+        let dir = data_dir.join("dms");
+        let mut conversations = HashMap::new();
+        if dir.exists() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if let Some(peer) = path.file_stem().and_then(|stem| stem.to_str())
+                    && path.extension().is_some_and(|ext| ext == "json")
+                    && peer.parse::<PeerId>().is_ok()
+                {
+                    conversations.insert(peer.to_string(), Arc::new(ChatStore::open(&path)?));
+                }
+            }
+        }
+        let outbox_path = data_dir.join("dm-outbox.json");
+        let mut outbox = Outbox::load(&outbox_path)?;
+        if outbox.expire() {
+            outbox.save(&outbox_path)?;
+        }
+        Ok(Self {
+            dir,
+            outbox_path,
+            local_peer,
+            secret,
+            topic,
+            conversations: Arc::new(RwLock::new(conversations)),
+            outbox: Arc::new(RwLock::new(outbox)),
+        })
+    }
+
+    /// Topic a peer listens on for DMs sent to it directly.
+    fn inbox(&self, peer: &str) -> gossipsub::IdentTopic {
+        gossipsub::IdentTopic::new(format!("{}/dm/{}", self.topic, peer))
+    }
+
+    /// Key for the conversation with `peer`, if both sides have X25519 keys.
+    fn key(&self, peer: &str) -> Option<GroupKey> {
+        let secret = self.secret.as_ref()?;
+        direct::conversation_key(secret, &self.local_peer, peer, &dh_public(peer)?).ok()
+    }
+
+    fn peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.conversations.read().keys().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    /// Messages exchanged with `peer` that have not expired, still sealed.
+    fn messages(&self, peer: &str) -> Vec<ChatMessage> {
+        let mut messages = self
+            .conversations
+            .read()
+            .get(peer)
+            .map(|store| store.messages())
+            .unwrap_or_default();
+        messages.retain(|m| !m.is_expired(None));
+        messages
+    }
+
+    /// Deletes expired messages from every conversation.
+    fn expire(&self) -> Result<()> {
+        let stores: Vec<Arc<ChatStore>> = self.conversations.read().values().cloned().collect();
+        for store in stores {
+            store.expire(None)?;
+        }
+        Ok(())
+    }
+
+    fn append(&self, peer: &str, message: ChatMessage) -> Result<bool> {
+        if message.is_expired(None) {
+            return Ok(false);
+        }
+        let store = match self.conversations.write().entry(peer.to_string()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                let store = ChatStore::open(self.dir.join(format!("{peer}.json")))?;
+                entry.insert(Arc::new(store)).clone()
+            }
+        };
+        store.append(message)
+    }
+
+    /// Stores a DM addressed to this node; returns `false` if it was already
+    /// stored. DMs that do not open with the claimed sender's conversation key
+    /// are rejected.
+    fn receive(&self, dm: DirectMessage) -> Result<bool> {
+        let sender = dm
+            .sender()
+            .ok_or_else(|| anyhow!("direct message without sender"))?;
+        let key = self
+            .key(sender)
+            .ok_or_else(|| anyhow!("no conversation key for {sender}"))?;
+        if dm.message.clone().opened(|_| Some(key)).is_sealed() {
+            return Err(anyhow!("direct message from {sender} does not open"));
+        }
+        let sender = sender.to_string();
+        self.append(&sender, dm.message)
+    }
+
+    fn queue(&self, dm: DirectMessage, relayed: bool) -> Result<()> {
+        let mut outbox = self.outbox.write();
+        outbox.expire();
+        if outbox.push(dm, relayed) {
+            outbox.save(&self.outbox_path)?;
+        }
+        Ok(())
+    }
+
+    /// Stops holding DMs that `peer` reports it has received.
+    fn forget_delivered(&self, peer: &str, ids: &[Uuid]) -> Result<()> {
+        let mut outbox = self.outbox.write();
+        if outbox.forget_delivered(peer, ids) {
+            outbox.save(&self.outbox_path)?;
+        }
+        Ok(())
+    }
+
+    fn take_for(&self, peer: &str) -> Result<Vec<DirectMessage>> {
+        let mut outbox = self.outbox.write();
+        let taken = outbox.take_for(peer);
+        if !taken.is_empty() {
+            outbox.save(&self.outbox_path)?;
+        }
+        Ok(taken)
+    }
+
+    /// Number of DMs to `peer` still waiting to be delivered.
+    fn pending(&self, peer: &str) -> usize {
+        self.outbox.read().pending_for(peer)
+    }
+}
+
+/// Peers this node hides from its API; their messages are still stored and
+/// relayed for the rest of the grid.
+#[derive(Clone)]
+struct BlockList {
+    path: PathBuf,
+    peers: Arc<RwLock<BTreeSet<String>>>,
+}
+
+impl BlockList {
+    fn open(path: PathBuf) -> Result<Self> {
+        let peers = load_json(&path, "block list")?;
+        Ok(Self {
+            path,
+            peers: Arc::new(RwLock::new(peers)),
+        })
+    }
+
+    fn contains(&self, peer_id: &str) -> bool {
+        self.peers.read().contains(peer_id)
+    }
+
+    fn list(&self) -> Vec<String> {
+        self.peers.read().iter().cloned().collect()
+    }
+
+    /// Blocks or unblocks a peer. Returns `false` when nothing changed.
+    fn set(&self, peer_id: &str, blocked: bool) -> Result<bool> {
+        let mut peers = self.peers.write();
+        let changed = if blocked {
+            peers.insert(peer_id.to_string())
+        } else {
+            peers.remove(peer_id)
+        };
+        if changed {
+            save_json(&self.path, &*peers)?;
+        }
+        Ok(changed)
+    }
+}
+
+#[derive(Clone)]
+struct ApiContext {
+    channel_state: ChannelState,
+    sender: mpsc::Sender<ApiRequest>,
+    fallback_author: String,
+    telemetry: Telemetry,
+    peer_id: String,
+    voice_tx: mpsc::Sender<VoiceSignal>,
+    voice_signals: Arc<RwLock<Vec<VoiceSignal>>>,
+    keypair: identity::Keypair,
+    /// X25519 secret matching `keypair`, for private channel keys.
+    dh_secret: Option<StaticSecret>,
+    dms: DirectMessages,
+    blocked: BlockList,
+    /// Where the user stopped reading each channel.
+    read_markers: Arc<RwLock<ReadMarkers>>,
+    /// Which messages notify the user, per channel.
+    notifications: Arc<RwLock<NotificationSettings>>,
+    /// Messages waiting for their `send_at`.
+    scheduled: Arc<RwLock<Schedule>>,
+    /// Search indexes over the opened messages of readable private channels.
+    private_search: Arc<RwLock<HashMap<String, PrivateIndex>>>,
+}
+
+/// Search index of one private channel, built from its opened messages. It
+/// stays in memory only and is rebuilt once the store or the group keys it
+/// was built from change.
+struct PrivateIndex {
+    revision: u64,
+    keys: usize,
+    index: SearchIndex,
+}
+
+/// How long a typing indicator lasts unless refreshed.
+const TYPING_TTL: Duration = Duration::from_secs(6);
+
+/// Least time between two typing events from one peer in one channel.
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often expired messages are deleted; the API hides them in between.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the schedule is checked for messages that are due.
+const SCHEDULE_TICK: Duration = Duration::from_secs(1);
+
+/// How often channels are pruned to their retention rules.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Live event pushed to `/events` subscribers; never persisted.
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    Typing {
+        /// Channel id.
+        channel: String,
+        peer_id: String,
+        typing: bool,
+        expires_in_ms: u64,
+    },
+    /// The user read a channel up to a message, from any client of this node.
+    Read {
+        /// Channel id.
+        channel: String,
+        message_id: Uuid,
+    },
+    /// A message from a peer was stored. Each `/events` subscriber turns it
+    /// into a `Notification` or drops it; it is never streamed as is.
+    Received {
+        /// Channel id.
+        channel: String,
+        message_id: Uuid,
+    },
+    /// A received message notifies the user.
+    Notification(Box<NotificationView>),
+}
+
+impl StreamEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            StreamEvent::Typing { .. } => "typing",
+            StreamEvent::Read { .. } => "read",
+            StreamEvent::Received { .. } => "received",
+            StreamEvent::Notification(_) => "notification",
+        }
+    }
+}
+
+#[derive(Clone)]
+struct EventBus(broadcast::Sender<StreamEvent>);
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self(broadcast::channel(256).0)
+    }
+}
+
+impl EventBus {
+    /// Events sent while nobody is subscribed are dropped.
+    fn send(&self, event: StreamEvent) {
+        let _ = self.0.send(event);
+    }
+}
+
+#[derive(Clone, Default)]
+struct Telemetry {
+    peers: Arc<RwLock<HashSet<String>>>,
+    last_message: Arc<RwLock<Option<String>>>,
+    /// Heartbeats heard from peers.
+    presence: Arc<RwLock<PresenceBook>>,
+    /// Status this node announces.
+    status: Arc<RwLock<PresenceStatus>>,
+    /// Last typing event per (channel id, peer), this node included.
+    typing: Arc<RwLock<HashMap<(String, String), Instant>>>,
+    events: EventBus,
+}
+
+impl Telemetry {
+    fn note_peer_online(&self, peer: &PeerId) {
+        self.peers.write().insert(peer.to_string());
+    }
+
+    fn note_peer_offline(&self, peer: &PeerId) {
+        self.peers.write().remove(&peer.to_string());
+    }
+
+    fn note_message(&self, timestamp: String) {
+        *self.last_message.write() = Some(timestamp);
+    }
+
+    fn note_heartbeat(&self, peer: &str, heartbeat: Heartbeat) {
+        self.presence.write().record(peer, heartbeat);
+    }
+
+    fn status(&self) -> PresenceStatus {
+        *self.status.read()
+    }
+
+    /// Records a typing event and streams it. Repeated starts within
+    /// [`TYPING_INTERVAL`] are ignored; returns whether the event was kept.
+    fn note_typing(&self, channel: &str, peer: &str, typing: bool) -> bool {
+        let key = (channel.to_string(), peer.to_string());
+        {
+            let mut guard = self.typing.write();
+            let now = Instant::now();
+            guard.retain(|_, at| now.duration_since(*at) < TYPING_TTL);
+            if typing {
+                if guard
+                    .get(&key)
+                    .is_some_and(|at| now.duration_since(*at) < TYPING_INTERVAL)
+                {
+                    return false;
+                }
+                guard.insert(key, now);
+            } else if guard.remove(&key).is_none() {
+                return false;
+            }
+        }
+        self.events.send(StreamEvent::Typing {
+            channel: channel.to_string(),
+            peer_id: peer.to_string(),
+            typing,
+            expires_in_ms: if typing {
+                TYPING_TTL.as_millis() as u64
+            } else {
+                0
+            },
+        });
+        true
+    }
+
+    /// Peers currently typing in a channel.
+    fn typing_in(&self, channel: &str) -> Vec<String> {
+        let now = Instant::now();
+        let mut peers: Vec<String> = self
+            .typing
+            .read()
+            .iter()
+            .filter(|((ch, _), at)| ch == channel && now.duration_since(**at) < TYPING_TTL)
+            .map(|((_, peer), _)| peer.clone())
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    fn snapshot(&self) -> TelemetrySnapshot {
+        TelemetrySnapshot {
+            peers: self.peers.read().iter().cloned().collect(),
+            last_message: self.last_message.read().clone(),
+        }
+    }
+}
+
+struct TelemetrySnapshot {
+    peers: Vec<String>,
+    last_message: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_target(false)
+        .init();
+
+    let cli = Cli::parse();
+    let config_path = cli.config.unwrap_or_else(default_config_path);
+    match cli
+        .command
+        .unwrap_or_else(|| Commands::Run(RunCommand::default()))
+    {
+        Commands::Run(run) => run_node(run, config_path).await,
+        Commands::PrintConfig => {
+            let cfg = load_or_create_config(&config_path)?;
+            println!("{}", toml::to_string_pretty(&cfg)?);
+            Ok(())
+        }
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    NodeConfig::config_file(&NodeConfig::default().data_dir)
+}
+
+async fn run_node(args: RunCommand, config_path: PathBuf) -> Result<()> {
+    let RunCommand {
+        listen,
+        topic,
+        api_bind,
+    } = args;
+    let config = load_or_create_config(&config_path)
+        .with_context(|| format!("unable to load config {:?}", config_path))?;
+
+    let listen_addr: Multiaddr = listen
+        .as_deref()
+        .unwrap_or("/ip4/0.0.0.0/tcp/0")
+        .parse()
+        .map_err(|e| anyhow!("invalid listen address: {e}"))?;
+
+    let api_socket = {
+        let trimmed = api_bind.trim();
+        if trimmed.is_empty() {
+            None
+        } else {
+            Some(
+                trimmed
+                    .parse::<SocketAddr>()
+                    .map_err(|err| anyhow!("invalid API bind address: {err}"))?,
+            )
+        }
+    };
+
+    let topic_name = topic.unwrap_or(config.topic.clone());
+    let topic = gossipsub::IdentTopic::new(topic_name.clone());
+
+    let config_path = config_path.clone();
+    let channel_state = ChannelState::open(&config, &config_path)?;
+
+    let telemetry = Telemetry::default();
+    *telemetry.status.write() = load_presence_status(&config.data_dir);
+
+    let identity_path = config.data_dir.join("identity.bin");
+    let local_key = load_or_create_identity(&identity_path)?;
+    let local_peer_id = PeerId::from(local_key.public());
+    info!(%local_peer_id, "node identity loaded");
+
+    if let Err(err) = sync_own_profile(&channel_state, &local_key, &config.nickname) {
+        warn!(%err, "unable to update own profile");
+    }
+
+    let (api_tx, mut api_rx) = mpsc::channel::<ApiRequest>(32);
+    let (voice_tx, mut voice_rx) = mpsc::channel::<VoiceSignal>(64);
+    let voice_signals: Arc<RwLock<Vec<VoiceSignal>>> = Arc::new(RwLock::new(Vec::new()));
+    let dms = DirectMessages::open(
+        &config.data_dir,
+        topic.clone(),
+        local_peer_id.to_string(),
+        dh_secret(&local_key),
+    )?;
+    let mut api_enabled = false;
+    if let Some(bind) = api_socket {
+        api_enabled = true;
+        let api_state = ApiContext {
+            channel_state: channel_state.clone(),
+            sender: api_tx.clone(),
+            fallback_author: config.nickname.clone(),
+            telemetry: telemetry.clone(),
+            peer_id: local_peer_id.to_string(),
+            voice_tx: voice_tx.clone(),
+            voice_signals: voice_signals.clone(),
+            keypair: local_key.clone(),
+            dh_secret: dh_secret(&local_key),
+            dms: dms.clone(),
+            blocked: BlockList::open(config.data_dir.join("blocked.json"))?,
+            read_markers: Arc::new(RwLock::new(ReadMarkers::load(
+                config.data_dir.join("read-markers.json"),
+            )?)),
+            notifications: Arc::new(RwLock::new(NotificationSettings::load(
+                config.data_dir.join("notification-settings.json"),
+            )?)),
+            scheduled: Arc::new(RwLock::new(Schedule::load(
+                config.data_dir.join("scheduled.json"),
+            )?)),
+            private_search: Arc::new(RwLock::new(HashMap::new())),
+        };
+        tokio::spawn(send_scheduled(api_state.clone()));
+        tokio::spawn(async move {
+            if let Err(err) = serve_api(bind, api_state).await {
+                warn!(%err, "api server stopped");
+            }
+        });
+        info!(%bind, "api server listening");
+    }
+
+    {
+        let channel_state = channel_state.clone();
+        let dms = dms.clone();
+        tokio::spawn(async move {
+            let mut expiry = tokio::time::interval(EXPIRY_INTERVAL);
+            loop {
+                expiry.tick().await;
+                if let Err(err) = channel_state.expire_messages().and_then(|()| dms.expire()) {
+                    warn!(%err, "unable to delete expired messages");
+                }
+            }
+        });
+    }
+    if !config.retention.is_empty() {
+        let channel_state = channel_state.clone();
+        tokio::spawn(async move {
+            let mut prune = tokio::time::interval(RETENTION_INTERVAL);
+            loop {
+                prune.tick().await;
+                if let Err(err) = channel_state.apply_retention() {
+                    warn!(%err, "unable to apply retention rules");
+                }
+            }
+        });
+    }
+
+    let mut swarm = build_swarm(local_key, topic.clone()).await?;
+    swarm.listen_on(listen_addr)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&dms.inbox(&local_peer_id.to_string()))?;
+    apply_bans(&channel_state, &mut swarm);
+
+    for addr in &config.bootstrap_nodes {
+        match addr.parse::<Multiaddr>() {
+            Ok(multiaddr) => {
+                if let Err(err) = swarm.dial(multiaddr.clone()) {
+                    warn!(%multiaddr, %err, "failed to dial bootstrap node");
+                }
+            }
+            Err(err) => warn!(%addr, %err, "invalid bootstrap address"),
+        }
+    }
+
+    let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(16);
+    tokio::spawn(read_stdin(stdin_tx));
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(HEARTBEAT_SECS));
+    let mut shutdown = Box::pin(tokio::signal::ctrl_c());
+    let mut stdin_done = false;
+
+    loop {
+        tokio::select! {
             line = stdin_rx.recv(), if !stdin_done => {
                 if let Some(line) = line {
-                    if let Some(store) = channel_state.get_store("general") {
-                        if let Err(err) = publish_line(&line, &config.nickname, &store, "general", &topic, &mut swarm, &telemetry) {
-                            warn!(%err, "failed to send message");
+                    if let Err(err) = publish_line(&line, &config.nickname, &channel_state, DEFAULT_CHANNEL, &topic, &mut swarm, &telemetry) {
+                        warn!(%err, "failed to send message");
+                    }
+                } else {
+                    stdin_done = true;
+                    info!("stdin closed; node continues (API and P2P active)");
+                }
+            }
+            event = swarm.select_next_some() => {
+                handle_swarm_event(&mut swarm, event, channel_state.clone(), dms.clone(), telemetry.clone(), voice_signals.clone(), local_peer_id);
+            }
+            _ = heartbeat.tick() => {
+                if telemetry.status() != PresenceStatus::Invisible
+                    && let Err(err) = publish_heartbeat(telemetry.status(), &topic.hash(), &mut swarm)
+                {
+                    warn!(%err, "failed to publish heartbeat");
+                }
+            }
+            voice_signal = voice_rx.recv(), if api_enabled => {
+                if let Some(sig) = voice_signal
+                    && let Err(err) = publish_voice_signal(sig, &topic, &mut swarm)
+                {
+                    warn!(%err, "failed to publish voice signal");
+                }
+            }
+            api_request = api_rx.recv(), if api_enabled => {
+                match api_request {
+                    Some(ApiRequest::SendMessage { channel, message, sent }) => {
+                        let author = message.author.clone();
+                        let result = publish_chat_message(&channel, message, &channel_state, &topic, &mut swarm, &telemetry);
+                        match &result {
+                            Err(err) => warn!(%err, "failed to relay api message"),
+                            Ok(()) => info!(%author, %channel, "api message relayed"),
+                        }
+                        let _ = sent.send(result);
+                    }
+                    Some(ApiRequest::SendDirect { dm }) => {
+                        let to = dm.to.clone();
+                        if let Err(err) = dms.append(&to, dm.message.clone()) {
+                            warn!(%err, "unable to persist direct message");
+                        } else if let Err(err) = send_direct_message(dm, false, &dms, &mut swarm) {
+                            warn!(%err, "failed to send direct message");
+                        } else {
+                            info!(%to, "direct message sent");
+                        }
+                    }
+                    Some(ApiRequest::EditMessage { channel, edit }) => {
+                        let message_id = edit.payload.message_id;
+                        if let Err(err) = publish_message_edit(&channel, edit, &channel_state, &topic, &mut swarm) {
+                            warn!(%err, "failed to relay message edit");
+                        } else {
+                            info!(%message_id, %channel, "message edit relayed");
+                        }
+                    }
+                    Some(ApiRequest::DeleteMessage { channel, tombstone }) => {
+                        let message_id = tombstone.payload.message_id;
+                        if let Err(err) = publish_message_delete(&channel, tombstone, &channel_state, &topic, &mut swarm) {
+                            warn!(%err, "failed to relay message deletion");
+                        } else {
+                            info!(%message_id, %channel, "message deletion relayed");
+                        }
+                    }
+                    Some(ApiRequest::React { channel, reaction }) => {
+                        if let Err(err) = publish_reaction(&channel, reaction, &channel_state, &topic, &mut swarm) {
+                            warn!(%err, "failed to relay reaction");
+                        }
+                    }
+                    Some(ApiRequest::Moderate { action }) => {
+                        let peer = action.payload.peer.clone();
+                        if let Err(err) = publish_moderation(action, &channel_state, &topic, &mut swarm) {
+                            warn!(%err, "failed to publish moderation action");
+                        } else {
+                            info!(%peer, "moderation action published");
+                        }
+                    }
+                    Some(ApiRequest::UpdateProfile { profile }) => {
+                        if let Err(err) = publish_profile(profile, &channel_state, &topic, &mut swarm) {
+                            warn!(%err, "failed to publish profile");
+                        }
+                    }
+                    Some(ApiRequest::AnnouncePresence) => {
+                        // Sent even when turning invisible, so peers stop
+                        // showing this node right away.
+                        if let Err(err) = publish_heartbeat(telemetry.status(), &topic.hash(), &mut swarm) {
+                            warn!(%err, "failed to announce presence");
+                        }
+                    }
+                    Some(ApiRequest::Typing { channel, typing }) => {
+                        let envelope = serde_json::json!({ "channel": channel, "typing": typing });
+                        if let Err(err) = publish_ephemeral(&envelope, &topic.hash(), &mut swarm) {
+                            warn!(%err, "failed to publish typing state");
+                        }
+                    }
+                    Some(ApiRequest::BroadcastChannels) => {
+                        if let Err(err) = publish_channel_set(&channel_state.channel_set(), &topic.hash(), &mut swarm) {
+                            warn!(%err, "failed to broadcast channel set");
+                        }
+                    }
+                    None => {
+                        api_enabled = false;
+                        warn!("api channel closed");
+                    }
+                }
+            }
+            result = &mut shutdown => {
+                if let Err(err) = result {
+                    warn!(%err, "ctrl+c listener failed");
+                }
+                info!("shutdown requested");
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn publish_line(
+    line: &str,
+    nickname: &str,
+    channel_state: &ChannelState,
+    channel: &str,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+    telemetry: &Telemetry,
+) -> Result<()> {
+    if line.trim().is_empty() {
+        return Ok(());
+    }
+    let message = ChatMessage::new(nickname.to_string(), line.to_owned())
+        .with_peer_id(swarm.local_peer_id().to_string())
+        .with_hlc(channel_state.clock.tick());
+    let envelope = serde_json::json!({ "channel": channel, "message": message });
+    let bytes = serde_json::to_vec(&envelope)?;
+    channel_state.append_message(channel, message.clone())?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    telemetry.note_message(message.timestamp.to_rfc3339());
+    println!("[{}] you :: {}", channel, message.body);
+    Ok(())
+}
+
+fn publish_chat_message(
+    channel: &str,
+    message: ChatMessage,
+    channel_state: &ChannelState,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+    telemetry: &Telemetry,
+) -> Result<()> {
+    let envelope = serde_json::json!({ "channel": channel, "message": message });
+    let bytes = serde_json::to_vec(&envelope)?;
+    // Published before it is stored, so a message the grid refuses is not
+    // kept here either. Without peers it is only stored.
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)
+    {
+        Ok(_) | Err(gossipsub::PublishError::InsufficientPeers) => {}
+        Err(err) => return Err(err.into()),
+    }
+    channel_state.append_message(channel, message.clone())?;
+    telemetry.note_message(message.timestamp.to_rfc3339());
+    Ok(())
+}
+
+fn publish_message_edit(
+    channel: &str,
+    edit: Signed<MessageEdit>,
+    channel_state: &ChannelState,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    channel_state.apply_edit(channel, edit.clone())?;
+    let envelope = serde_json::json!({ "channel": channel, "message_edit": edit });
+    let bytes = serde_json::to_vec(&envelope)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    Ok(())
+}
+
+fn publish_message_delete(
+    channel: &str,
+    tombstone: Signed<Tombstone>,
+    channel_state: &ChannelState,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    channel_state.delete_message(channel, tombstone.clone())?;
+    let envelope = serde_json::json!({ "channel": channel, "message_delete": tombstone });
+    let bytes = serde_json::to_vec(&envelope)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    Ok(())
+}
+
+fn publish_reaction(
+    channel: &str,
+    reaction: Signed<Reaction>,
+    channel_state: &ChannelState,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    channel_state.apply_reaction(channel, reaction.clone())?;
+    let envelope = serde_json::json!({ "channel": channel, "reaction": reaction });
+    let bytes = serde_json::to_vec(&envelope)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    Ok(())
+}
+
+fn publish_moderation(
+    action: Signed<ModerationAction>,
+    channel_state: &ChannelState,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    channel_state.apply_moderation(action.clone())?;
+    apply_bans(channel_state, swarm);
+    let envelope = serde_json::json!({ "moderation": [action] });
+    let bytes = serde_json::to_vec(&envelope)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    Ok(())
+}
+
+/// Announces this node's presence status.
+fn publish_heartbeat(
+    status: PresenceStatus,
+    topic: &gossipsub::TopicHash,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    publish_ephemeral(
+        &serde_json::json!({ "presence": Heartbeat { status } }),
+        topic,
+        swarm,
+    )
+}
+
+/// Publishes an event that only matters to peers online right now, so having
+/// no peers is not an error.
+fn publish_ephemeral(
+    envelope: &serde_json::Value,
+    topic: &gossipsub::TopicHash,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    let bytes = serde_json::to_vec(envelope)?;
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)
+    {
+        Ok(_) | Err(gossipsub::PublishError::InsufficientPeers) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Status chosen through the API, kept across restarts in `presence.json`.
+fn load_presence_status(data_dir: &Path) -> PresenceStatus {
+    fs::read_to_string(data_dir.join("presence.json"))
+        .ok()
+        .and_then(|raw| serde_json::from_str::<Heartbeat>(&raw).ok())
+        .map(|saved| saved.status)
+        .unwrap_or_default()
+}
+
+fn save_presence_status(data_dir: &Path, status: &Heartbeat) -> Result<()> {
+    save_json(data_dir.join("presence.json"), status)
+}
+
+/// Records this node's profile and announces it with its avatar.
+fn publish_profile(
+    profile: Signed<Profile>,
+    channel_state: &ChannelState,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    channel_state.apply_profile(profile.clone(), None)?;
+    announce_profile(&profile, channel_state, &topic.hash(), swarm)
+}
+
+fn announce_profile(
+    profile: &Signed<Profile>,
+    channel_state: &ChannelState,
+    topic: &gossipsub::TopicHash,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    let avatar = profile
+        .payload
+        .avatar
+        .as_ref()
+        .and_then(|a| channel_state.blobs.read(&a.sha256).ok())
+        .map(|bytes| base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes));
+    let envelope = serde_json::json!({ "profile": profile, "avatar": avatar });
+    let bytes = serde_json::to_vec(&envelope)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    Ok(())
+}
+
+/// Makes sure this node has a signed profile and that its display name
+/// follows the configured nickname.
+fn sync_own_profile(
+    channel_state: &ChannelState,
+    keypair: &identity::Keypair,
+    nickname: &str,
+) -> Result<()> {
+    let peer_id = PeerId::from(keypair.public()).to_string();
+    let current = channel_state.profile(&peer_id).map(|p| p.payload);
+    if current.as_ref().is_some_and(|p| p.display_name == nickname) {
+        return Ok(());
+    }
+    let hlc = channel_state.clock.tick();
+    let mut profile = current.unwrap_or_else(|| Profile::new(nickname, hlc));
+    profile.display_name = nickname.to_string();
+    profile.hlc = hlc;
+    channel_state.apply_profile(sign_payload(keypair, profile)?, None)?;
+    Ok(())
+}
+
+/// Blacklists banned peers in gossipsub, so nothing they publish is accepted
+/// or forwarded, and lifts the blacklist for lifted or lapsed bans.
+fn apply_bans(channel_state: &ChannelState, swarm: &mut Swarm<GridBehaviour>) {
+    for action in channel_state.moderation_actions() {
+        if action.payload.sanction != Sanction::Ban {
+            continue;
+        }
+        let Ok(peer) = action.payload.peer.parse::<PeerId>() else {
+            continue;
+        };
+        if action.payload.in_force() {
+            swarm.behaviour_mut().gossipsub.blacklist_peer(&peer);
+        } else {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .remove_blacklisted_peer(&peer);
+        }
+    }
+}
+
+/// Largest gossip message this node sends or accepts. A chat message carries
+/// its attachments inline, base64-encoded and, in private channels, sealed
+/// and encoded again, so this leaves room for [`MAX_ATTACHMENT_BYTES`] twice over.
+const MAX_TRANSMIT_BYTES: usize = 2 * 1024 * 1024;
+
+/// Size replicated state is batched to when it is announced, so a peer
+/// catching up receives it in several modest messages.
+const MAX_PUBLISH_BYTES: usize = 60 * 1024;
+
+/// Announces the replicated state a newly subscribed peer may have missed.
+/// Each part is published on its own and a part that fails is only logged,
+/// so one oversized or refused payload does not hold back the rest.
+fn publish_sync_state(
+    channel_state: &ChannelState,
+    topic: &gossipsub::TopicHash,
+    swarm: &mut Swarm<GridBehaviour>,
+) {
+    if let Err(err) = publish_channel_set(&channel_state.channel_set(), topic, swarm) {
+        warn!(%err, "failed to announce channel set");
+    }
+    for (channel, tombstones) in channel_state.tombstones() {
+        let base = serde_json::json!({ "channel": channel });
+        if let Err(err) = publish_batched(base, "tombstones", &tombstones, topic, swarm) {
+            warn!(%err, %channel, "failed to announce tombstones");
+        }
+    }
+    let actions = channel_state.moderation_actions();
+    if let Err(err) = publish_batched(serde_json::json!({}), "moderation", &actions, topic, swarm) {
+        warn!(%err, "failed to announce moderation actions");
+    }
+    // Our own profile travels with its avatar; cached ones without.
+    let local_peer_id = swarm.local_peer_id().to_string();
+    let (own, cached): (Vec<_>, Vec<_>) = channel_state
+        .profiles()
+        .into_iter()
+        .partition(|p| p.signer == local_peer_id);
+    if let Some(profile) = own.first()
+        && let Err(err) = announce_profile(profile, channel_state, topic, swarm)
+    {
+        warn!(%err, "failed to announce own profile");
+    }
+    if let Err(err) = publish_batched(serde_json::json!({}), "profiles", &cached, topic, swarm) {
+        warn!(%err, "failed to announce cached profiles");
+    }
+}
+
+/// Publishes `items` as the `key` field of copies of `base`, as many per
+/// message as fit in [`MAX_PUBLISH_BYTES`]. An item too large on its own is
+/// sent alone; a batch gossipsub refuses is logged and skipped.
+fn publish_batched<T: Serialize>(
+    base: serde_json::Value,
+    key: &str,
+    items: &[T],
+    topic: &gossipsub::TopicHash,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    let mut envelope = base;
+    envelope[key] = serde_json::json!([]);
+    let overhead = serde_json::to_vec(&envelope)?.len();
+    let mut batches: Vec<Vec<&T>> = Vec::new();
+    let mut size = overhead;
+    for item in items {
+        // Each item also costs a separating comma.
+        let len = serde_json::to_vec(item)?.len() + 1;
+        match batches.last_mut() {
+            Some(batch) if size + len <= MAX_PUBLISH_BYTES => batch.push(item),
+            _ => {
+                batches.push(vec![item]);
+                size = overhead;
+            }
+        }
+        size += len;
+    }
+    for batch in batches {
+        envelope[key] = serde_json::to_value(&batch)?;
+        let bytes = serde_json::to_vec(&envelope)?;
+        if let Err(err) = swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), bytes)
+        {
+            warn!(%err, key, items = batch.len(), "failed to publish batch");
+        }
+    }
+    Ok(())
+}
+
+/// Publishes the channel set, one channel per message when the whole set is
+/// over [`MAX_PUBLISH_BYTES`].
+fn publish_channel_set(
+    set: &ChannelSet,
+    topic: &gossipsub::TopicHash,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    let bytes = serde_json::to_vec(&serde_json::json!({ "channel_set": set }))?;
+    if bytes.len() <= MAX_PUBLISH_BYTES {
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), bytes)?;
+        return Ok(());
+    }
+    for part in set.split() {
+        let bytes = serde_json::to_vec(&serde_json::json!({ "channel_set": part }))?;
+        if let Err(err) = swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), bytes)
+        {
+            warn!(%err, "failed to publish part of the channel set");
+        }
+    }
+    Ok(())
+}
+
+async fn build_swarm(
+    local_key: identity::Keypair,
+    topic: gossipsub::IdentTopic,
+) -> Result<Swarm<GridBehaviour>> {
+    let msg_auth = gossipsub::MessageAuthenticity::Signed(local_key.clone());
+    let gossip_config = gossipsub::ConfigBuilder::default()
+        // Every event must carry its signed source; authorship and
+        // sanctions are checked against it.
+        .validation_mode(gossipsub::ValidationMode::Strict)
+        .heartbeat_interval(Duration::from_secs(1))
+        .max_transmit_size(MAX_TRANSMIT_BYTES)
+        .build()?;
+    let mut gossipsub = gossipsub::Behaviour::new(msg_auth, gossip_config)
+        .map_err(|err| anyhow::Error::msg(err.to_string()))?;
+    gossipsub.subscribe(&topic)?;
+
+    let mdns =
+        mdns::tokio::Behaviour::new(mdns::Config::default(), PeerId::from(local_key.public()))?;
+    let identify = identify::Behaviour::new(identify::Config::new(
+        "/gridspeak/0.1.0".into(),
+        local_key.public(),
+    ));
+
+    let behaviour = GridBehaviour {
+        gossipsub,
+        mdns,
+        identify,
+    };
+
+    let swarm = SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )?
+        .with_behaviour(|_| behaviour)?
+        .build();
+
+    Ok(swarm)
+}
+
+/// Sends a DM straight to its recipient when it is connected and listening on
+/// its inbox topic. Otherwise the DM is published on the grid topic for other
+/// peers to hold, and queued here to retry when the recipient comes online.
+/// A DM that cannot be published to a reachable recipient is queued as well.
+///
+/// DMs travel over gossipsub rather than a dedicated stream protocol: the
+/// per-peer inbox topic reaches the recipient over the connections the grid
+/// already keeps, including through peers when there is no direct one, and
+/// the sealed payload means relays learn nothing but the two peer ids.
+fn send_direct_message(
+    dm: DirectMessage,
+    relayed: bool,
+    dms: &DirectMessages,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    let bytes = serde_json::to_vec(&serde_json::json!({ "dm": dm }))?;
+    let inbox = dms.inbox(&dm.to).hash();
+    let reachable = swarm
+        .behaviour()
+        .gossipsub
+        .all_peers()
+        .any(|(peer, topics)| peer.to_string() == dm.to && topics.contains(&&inbox));
+    if reachable {
+        let Err(err) = swarm.behaviour_mut().gossipsub.publish(inbox, bytes) else {
+            return Ok(());
+        };
+        dms.queue(dm, relayed)?;
+        return Err(err.into());
+    }
+    dms.queue(dm, relayed)?;
+    if !relayed {
+        match swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(dms.topic.clone(), bytes)
+        {
+            Ok(_) | Err(gossipsub::PublishError::InsufficientPeers) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Hands a peer that just came online the DMs queued for it.
+fn flush_direct_messages(
+    peer: &str,
+    dms: &DirectMessages,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    for dm in dms.take_for(peer)? {
+        let relayed = dm.sender() != Some(dms.local_peer.as_str());
+        if let Err(err) = send_direct_message(dm, relayed, dms, swarm) {
+            warn!(%err, %peer, "failed to deliver queued direct message");
+        }
+    }
+    Ok(())
+}
+
+fn publish_voice_signal(
+    signal: VoiceSignal,
+    topic: &gossipsub::IdentTopic,
+    swarm: &mut Swarm<GridBehaviour>,
+) -> Result<()> {
+    let envelope = serde_json::json!({ "voice_signal": signal });
+    let bytes = serde_json::to_vec(&envelope)?;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)?;
+    Ok(())
+}
+
+/// Most recent voice signals kept for the API to poll.
+const MAX_VOICE_SIGNALS: usize = 200;
+
+fn handle_swarm_event(
+    swarm: &mut Swarm<GridBehaviour>,
+    event: SwarmEvent<GridEvent>,
+    channel_state: ChannelState,
+    dms: DirectMessages,
+    telemetry: Telemetry,
+    voice_signals: Arc<RwLock<Vec<VoiceSignal>>>,
+    local_peer_id: PeerId,
+) {
+    match event {
+        SwarmEvent::Behaviour(GridEvent::Gossipsub(gossipsub::Event::Message {
+            propagation_source,
+            message_id,
+            message,
+        })) => {
+            // Sanctions apply to the signed source, and to the signer of
+            // records relayed on someone else's behalf.
+            let Some(author) = message.source.map(|p| p.to_string()) else {
+                warn!(%propagation_source, "dropping event without source");
+                return;
+            };
+            if channel_state.is_banned(&author) {
+                warn!(%propagation_source, %author, "dropping event from banned peer");
+                return;
+            }
+            if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&message.data) {
+                if let Some(voice) = value.get("voice_signal")
+                    && let Ok(sig) = serde_json::from_value::<VoiceSignal>(voice.clone())
+                {
+                    if sig.from != local_peer_id.to_string() {
+                        let mut guard = voice_signals.write();
+                        guard.push(sig);
+                        let n = guard.len();
+                        if n > MAX_VOICE_SIGNALS {
+                            let drop = n - MAX_VOICE_SIGNALS;
+                            guard.drain(0..drop);
                         }
                     }
-                } else {
-                    stdin_done = true;
-                    info!("stdin closed; node continues (API and P2P active)");
+                    return;
+                }
+                if let Some(dm) = value.get("dm") {
+                    let Ok(dm) = serde_json::from_value::<DirectMessage>(dm.clone()) else {
+                        warn!(%propagation_source, "dropping malformed direct message");
+                        return;
+                    };
+                    if dm.to == local_peer_id.to_string() {
+                        channel_state.observe(dm.message.hlc);
+                        let id = dm.message.id;
+                        match dms.receive(dm) {
+                            Ok(true) => {
+                                info!(%propagation_source, %message_id, "direct message received");
+                                // Tell the peers holding it that it arrived.
+                                let receipt = serde_json::json!({ "dm_delivered": [id] });
+                                if let Err(err) =
+                                    publish_ephemeral(&receipt, &dms.topic.hash(), swarm)
+                                {
+                                    warn!(%err, "failed to publish direct message receipt");
+                                }
+                            }
+                            Ok(false) => {}
+                            Err(err) => warn!(%err, "rejected direct message"),
+                        }
+                    } else if dm.sender() != Some(author.as_str()) {
+                        // Only the sender asks peers to hold a DM; anyone
+                        // else would be spending the sender's relay quota.
+                        warn!(%propagation_source, %author, "dropping direct message relayed for another sender");
+                    } else if dm.sender() != Some(dms.local_peer.as_str()) {
+                        // Held for the recipient; it can only be opened by its two peers.
+                        let to = dm.to.clone();
+                        if let Err(err) = send_direct_message(dm, true, &dms, swarm) {
+                            warn!(%err, %to, "unable to hold direct message");
+                        }
+                    }
+                    return;
+                }
+                if let Some(ids) = value.get("dm_delivered") {
+                    let Ok(ids) = serde_json::from_value::<Vec<Uuid>>(ids.clone()) else {
+                        warn!(%propagation_source, "dropping malformed direct message receipt");
+                        return;
+                    };
+                    // The receipt's signed source is the recipient.
+                    if let Err(err) = dms.forget_delivered(&author, &ids) {
+                        warn!(%err, "failed to drop delivered direct messages");
+                    }
+                    return;
+                }
+                if let Some(actions) = value.get("moderation") {
+                    let Ok(actions) =
+                        serde_json::from_value::<Vec<Signed<ModerationAction>>>(actions.clone())
+                    else {
+                        warn!(%propagation_source, "dropping malformed moderation actions");
+                        return;
+                    };
+                    let mut changed = false;
+                    for action in actions {
+                        if !verify_signed(&action) {
+                            warn!(%propagation_source, peer = %action.payload.peer, "dropping moderation action with bad signature");
+                            continue;
+                        }
+                        channel_state.observe(Some(action.payload.hlc));
+                        match channel_state.apply_moderation(action) {
+                            Ok(applied) => changed |= applied,
+                            Err(err) => warn!(%err, "rejected moderation action"),
+                        }
+                    }
+                    if changed {
+                        apply_bans(&channel_state, swarm);
+                        info!(%propagation_source, "moderation list updated");
+                    }
+                    return;
+                }
+                if let Some(heartbeat) = value.get("presence") {
+                    match serde_json::from_value::<Heartbeat>(heartbeat.clone()) {
+                        Ok(heartbeat) => telemetry.note_heartbeat(&author, heartbeat),
+                        _ => warn!(%propagation_source, "dropping malformed heartbeat"),
+                    }
+                    return;
+                }
+                if let Some(profile) = value.get("profile") {
+                    match serde_json::from_value::<Signed<Profile>>(profile.clone()) {
+                        Ok(profile) if verify_signed(&profile) => {
+                            if let Err(err) = profile.payload.validate() {
+                                warn!(%err, %propagation_source, "dropping invalid profile");
+                                return;
+                            }
+                            let avatar = value
+                                .get("avatar")
+                                .and_then(|v| v.as_str())
+                                .and_then(|data| {
+                                    base64::Engine::decode(
+                                        &base64::engine::general_purpose::STANDARD,
+                                        data,
+                                    )
+                                    .ok()
+                                })
+                                .filter(|bytes| bytes.len() <= MAX_AVATAR_BYTES);
+                            channel_state.observe(Some(profile.payload.hlc));
+                            match channel_state.apply_profile(profile, avatar.as_deref()) {
+                                Ok(true) => info!(%propagation_source, "profile updated"),
+                                Ok(false) => {}
+                                Err(err) => warn!(%err, "unable to store profile"),
+                            }
+                        }
+                        _ => warn!(%propagation_source, "dropping unsigned or malformed profile"),
+                    }
+                    return;
+                }
+                if let Some(profiles) = value.get("profiles") {
+                    let Ok(profiles) =
+                        serde_json::from_value::<Vec<Signed<Profile>>>(profiles.clone())
+                    else {
+                        warn!(%propagation_source, "dropping malformed profiles");
+                        return;
+                    };
+                    for profile in profiles.into_iter().filter(verify_signed) {
+                        if let Err(err) = profile.payload.validate() {
+                            warn!(%err, %propagation_source, "dropping invalid profile");
+                            continue;
+                        }
+                        channel_state.observe(Some(profile.payload.hlc));
+                        if let Err(err) = channel_state.apply_profile(profile, None) {
+                            warn!(%err, "unable to store profile");
+                        }
+                    }
+                    return;
+                }
+                if let Some(set) = value.get("channel_set") {
+                    match serde_json::from_value::<ChannelSet>(set.clone()) {
+                        Ok(mut set) => {
+                            // Signatures are checked here; roles are checked
+                            // against the merged grants while merging.
+                            set.retain_meta(|record| {
+                                let valid = verify_signed(record);
+                                if valid {
+                                    channel_state.observe(record.payload.hlc);
+                                } else {
+                                    warn!(%propagation_source, channel = %record.payload.id(), "dropping channel record with bad signature");
+                                }
+                                valid
+                            });
+                            set.retain_grants(|grant| {
+                                let valid = verify_signed(grant);
+                                if !valid {
+                                    warn!(%propagation_source, channel = %grant.payload.channel, "dropping role grant with bad signature");
+                                }
+                                valid
+                            });
+                            set.retain_keys(|key| {
+                                let valid = verify_signed(key);
+                                if !valid {
+                                    warn!(%propagation_source, channel = %key.payload.channel, "dropping channel key with bad signature");
+                                }
+                                valid
+                            });
+                            set.retain_removals(|removal| {
+                                let valid = verify_signed(removal);
+                                if !valid {
+                                    warn!(%propagation_source, channel = %removal.payload.channel, "dropping channel removal with bad signature");
+                                }
+                                valid
+                            });
+                            set.retain_pins(|pin| {
+                                let valid = verify_signed(pin);
+                                if valid {
+                                    channel_state.observe(Some(pin.payload.hlc));
+                                } else {
+                                    warn!(%propagation_source, channel = %pin.payload.channel, "dropping pin with bad signature");
+                                }
+                                valid
+                            });
+                            match channel_state.merge_channels_from_remote(&set) {
+                                Ok(true) => info!(%propagation_source, "channel set updated"),
+                                Ok(false) => {}
+                                Err(err) => warn!(%err, "unable to merge channel set"),
+                            }
+                        }
+                        Err(_) => warn!(%propagation_source, "dropping malformed channel set"),
+                    }
+                    return;
+                }
+                if let Some(list) = value.get("channel_list").and_then(|v| v.as_array()) {
+                    let channels: Vec<String> = list
+                        .iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect();
+                    if let Err(err) = channel_state.merge_legacy_channel_list(&channels) {
+                        warn!(%err, "unable to merge channel list");
+                    }
+                    return;
+                }
+                if let Some(removed) = value.get("channel_removed").and_then(|v| v.as_str()) {
+                    // Unsigned removals from older nodes are only honoured when
+                    // the (gossip-signed) author is a grid moderator.
+                    if !channel_state.is_moderator(&author) {
+                        warn!(%propagation_source, channel = %removed, "ignoring unsigned channel removal");
+                    } else if let Err(err) = channel_state.remove_channel(removed) {
+                        warn!(%err, "unable to remove channel");
+                    }
+                    return;
+                }
+                if let (Some(ch), Some(typing)) = (
+                    value.get("channel").and_then(|v| v.as_str()),
+                    value.get("typing").and_then(|v| v.as_bool()),
+                ) {
+                    // Typing in private channels is never published; ignore
+                    // any that claims to be, and typing in unknown channels.
+                    if channel_state.describe_channel(ch).is_none() || channel_state.is_private(ch)
+                    {
+                        warn!(%propagation_source, channel = %ch, "dropping typing event for unknown or private channel");
+                    } else if !channel_state.is_muted(&author) {
+                        telemetry.note_typing(ch, &author, typing);
+                    }
+                    return;
+                }
+                if let (Some(ch), Some(edit)) = (
+                    value.get("channel").and_then(|v| v.as_str()),
+                    value.get("message_edit"),
+                ) {
+                    match serde_json::from_value::<Signed<MessageEdit>>(edit.clone()) {
+                        Ok(edit) if channel_state.is_muted(&edit.signer) => {
+                            warn!(%propagation_source, signer = %edit.signer, "dropping edit from muted peer");
+                        }
+                        // Private channels only take sealed edits from members.
+                        Ok(edit)
+                            if channel_state.is_private(ch)
+                                && (edit.payload.sealed_body.is_none()
+                                    || !edit.payload.body.is_empty()
+                                    || !channel_state.is_member(ch, &edit.signer)) =>
+                        {
+                            warn!(%propagation_source, channel = %ch, "dropping unsealed or non-member edit for private channel");
+                        }
+                        Ok(edit) if verify_signed(&edit) => {
+                            channel_state.observe(edit.payload.hlc);
+                            match channel_state.apply_edit(ch, edit) {
+                                Ok(true) => {
+                                    info!(%propagation_source, %message_id, "message edit received")
+                                }
+                                Ok(false) => {}
+                                Err(err) => warn!(%err, "rejected message edit"),
+                            }
+                        }
+                        _ => {
+                            warn!(%propagation_source, "dropping unsigned or malformed message edit")
+                        }
+                    }
+                    return;
+                }
+                if let (Some(ch), Some(tombstone)) = (
+                    value.get("channel").and_then(|v| v.as_str()),
+                    value.get("message_delete"),
+                ) {
+                    match serde_json::from_value::<Signed<Tombstone>>(tombstone.clone()) {
+                        Ok(tombstone) => apply_remote_tombstone(&channel_state, ch, tombstone),
+                        Err(_) => warn!(%propagation_source, "dropping malformed message deletion"),
+                    }
+                    return;
+                }
+                if let (Some(ch), Some(reaction)) = (
+                    value.get("channel").and_then(|v| v.as_str()),
+                    value.get("reaction"),
+                ) {
+                    match serde_json::from_value::<Signed<Reaction>>(reaction.clone()) {
+                        Ok(reaction) if channel_state.is_muted(&reaction.signer) => {
+                            warn!(%propagation_source, signer = %reaction.signer, "dropping reaction from muted peer");
+                        }
+                        Ok(reaction)
+                            if channel_state.is_private(ch)
+                                && !channel_state.is_member(ch, &reaction.signer) =>
+                        {
+                            warn!(%propagation_source, channel = %ch, "dropping non-member reaction for private channel");
+                        }
+                        Ok(reaction) if verify_signed(&reaction) => {
+                            channel_state.observe(reaction.payload.hlc);
+                            if let Err(err) = channel_state.apply_reaction(ch, reaction) {
+                                warn!(%err, "unable to persist reaction");
+                            }
+                        }
+                        _ => warn!(%propagation_source, "dropping unsigned or malformed reaction"),
+                    }
+                    return;
+                }
+                if let (Some(ch), Some(list)) = (
+                    value.get("channel").and_then(|v| v.as_str()),
+                    value.get("tombstones"),
+                ) {
+                    if let Ok(tombstones) =
+                        serde_json::from_value::<Vec<Signed<Tombstone>>>(list.clone())
+                    {
+                        for tombstone in tombstones {
+                            apply_remote_tombstone(&channel_state, ch, tombstone);
+                        }
+                    }
+                    return;
+                }
+                if let (Some(ch), Some(msg)) = (
+                    value.get("channel").and_then(|v| v.as_str()),
+                    value.get("message"),
+                ) && let Ok(mut chat) = serde_json::from_value::<ChatMessage>(msg.clone())
+                {
+                    // The gossip source is signed; the claimed `peer_id` is not.
+                    if chat.peer_id.as_ref().is_some_and(|peer| *peer != author) {
+                        warn!(%propagation_source, "dropping message with mismatched peer id");
+                        return;
+                    }
+                    // Private channels only take sealed messages from members.
+                    if channel_state.is_private(ch)
+                        && (!chat.is_sealed()
+                            || chat.has_plaintext()
+                            || !channel_state.is_member(ch, &author))
+                    {
+                        warn!(%propagation_source, channel = %ch, "dropping unsealed or non-member message for private channel");
+                        return;
+                    }
+                    chat.peer_id = Some(author);
+                    if chat
+                        .peer_id
+                        .as_deref()
+                        .is_some_and(|peer| channel_state.is_muted(peer))
+                    {
+                        warn!(%propagation_source, "dropping message from muted peer");
+                        return;
+                    }
+                    channel_state.observe(chat.hlc);
+                    match channel_state.append_message(ch, chat.clone()) {
+                        Err(err) => warn!(%err, "unable to persist message"),
+                        Ok(false) => {}
+                        Ok(true) => {
+                            telemetry.note_message(chat.timestamp.to_rfc3339());
+                            telemetry.events.send(StreamEvent::Received {
+                                channel: ch.to_string(),
+                                message_id: chat.id,
+                            });
+                            println!("[{}] {} :: {}", ch, chat.author, chat.body);
+                            info!(%propagation_source, %message_id, "message received");
+                        }
+                    }
+                }
+            }
+        }
+        SwarmEvent::Behaviour(GridEvent::Gossipsub(gossipsub::Event::Subscribed {
+            peer_id,
+            topic,
+        })) if topic == dms.inbox(&peer_id.to_string()).hash() => {
+            if let Err(err) = flush_direct_messages(&peer_id.to_string(), &dms, swarm) {
+                warn!(%err, %peer_id, "failed to flush direct messages");
+            }
+        }
+        SwarmEvent::Behaviour(GridEvent::Gossipsub(gossipsub::Event::Subscribed {
+            peer_id,
+            topic,
+        })) => {
+            info!(%peer_id, "peer subscribed; announcing sync state");
+            apply_bans(&channel_state, swarm);
+            publish_sync_state(&channel_state, &topic, swarm);
+            if telemetry.status() != PresenceStatus::Invisible
+                && let Err(err) = publish_heartbeat(telemetry.status(), &topic, swarm)
+            {
+                warn!(%err, "failed to publish heartbeat");
+            }
+        }
+        SwarmEvent::Behaviour(GridEvent::Mdns(mdns::Event::Discovered(list))) => {
+            for (peer, _addr) in list {
+                info!(%peer, "mdns peer discovered");
+                telemetry.note_peer_online(&peer);
+                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
+            }
+        }
+        SwarmEvent::Behaviour(GridEvent::Mdns(mdns::Event::Expired(list))) => {
+            for (peer, _addr) in list {
+                info!(%peer, "mdns peer expired");
+                telemetry.note_peer_offline(&peer);
+                swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer);
+            }
+        }
+        SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+            telemetry.note_peer_online(&peer_id);
+        }
+        SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            telemetry.note_peer_offline(&peer_id);
+        }
+        SwarmEvent::NewListenAddr { address, .. } => {
+            info!(%address, "listening");
+            println!("listening on {address}");
+        }
+        SwarmEvent::Behaviour(GridEvent::Identify(event)) => {
+            info!(?event, "identify event");
+        }
+        _ => {}
+    }
+}
+
+/// Signs a payload with the node identity so peers can attribute it.
+fn sign_payload<T: Serialize>(keypair: &identity::Keypair, payload: T) -> Result<Signed<T>> {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let signature = keypair.sign(&signing_bytes(&payload)?)?;
+    Ok(Signed {
+        payload,
+        signer: PeerId::from(keypair.public()).to_string(),
+        public_key: base64::Engine::encode(&b64, keypair.public().encode_protobuf()),
+        signature: base64::Engine::encode(&b64, signature),
+    })
+}
+
+/// Checks the signature and that the embedded public key belongs to the claimed signer.
+fn verify_signed<T: Serialize>(signed: &Signed<T>) -> bool {
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let (Ok(key), Ok(signature), Ok(bytes)) = (
+        base64::Engine::decode(&b64, &signed.public_key),
+        base64::Engine::decode(&b64, &signed.signature),
+        signing_bytes(&signed.payload),
+    ) else {
+        return false;
+    };
+    let Ok(public_key) = identity::PublicKey::try_decode_protobuf(&key) else {
+        return false;
+    };
+    PeerId::from(public_key.clone()).to_string() == signed.signer
+        && public_key.verify(&bytes, &signature)
+}
+
+/// X25519 secret derived from an ed25519 node identity.
+fn dh_secret(keypair: &identity::Keypair) -> Option<StaticSecret> {
+    let keypair = keypair.clone().try_into_ed25519().ok()?;
+    Some(crypto::secret_from_ed25519(keypair.secret().as_ref()))
+}
+
+/// X25519 public key of a peer, derived from the ed25519 key its PeerId embeds.
+fn dh_public(peer_id: &str) -> Option<PublicKey> {
+    let peer_id: PeerId = peer_id.parse().ok()?;
+    let multihash = peer_id.as_ref();
+    if multihash.code() != 0 {
+        return None;
+    }
+    let public = identity::PublicKey::try_decode_protobuf(multihash.digest()).ok()?;
+    crypto::public_from_ed25519(public.try_into_ed25519().ok()?.to_bytes())
+}
+
+/// Verifies a deletion received from the mesh and applies it. Moderator
+/// deletions are only honoured from grid moderators and the channel's owners
+/// and moderators.
+fn apply_remote_tombstone(
+    channel_state: &ChannelState,
+    channel: &str,
+    tombstone: Signed<Tombstone>,
+) {
+    if !verify_signed(&tombstone) {
+        warn!(signer = %tombstone.signer, "dropping message deletion with invalid signature");
+        return;
+    }
+    if channel_state.is_banned(&tombstone.signer) {
+        warn!(signer = %tombstone.signer, "dropping message deletion from banned peer");
+        return;
+    }
+    if tombstone.payload.as_moderator && !channel_state.may_moderate(channel, &tombstone.signer) {
+        warn!(signer = %tombstone.signer, "dropping moderator deletion from non-moderator");
+        return;
+    }
+    channel_state.observe(tombstone.payload.hlc);
+    if let Err(err) = channel_state.delete_message(channel, tombstone) {
+        warn!(%err, "rejected message deletion");
+    }
+}
+
+async fn read_stdin(sender: mpsc::Sender<String>) {
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if sender.send(line).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct PublishRequest {
+    channel: String,
+    body: String,
+    author: Option<String>,
+    #[serde(default)]
+    attachments: Vec<AttachmentPayload>,
+    reply_to: Option<Uuid>,
+    /// Seconds after which the message disappears.
+    ttl_secs: Option<u32>,
+    /// RFC 3339 time to send the message at instead of now.
+    send_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AttachmentPayload {
+    content_type: String,
+    filename: String,
+    data_base64: String,
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    peer_id: String,
+    peers: Vec<String>,
+    message_count: usize,
+    last_message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VoiceSignalRequest {
+    #[serde(rename = "type")]
+    kind: String,
+    data: String,
+    to: Option<String>,
+}
+
+async fn serve_api(bind: SocketAddr, state: ApiContext) -> Result<()> {
+    let app = Router::new()
+        .route("/health", get(api_health))
+        .route("/channels", get(api_channels).post(api_create_channel))
+        .route(
+            "/channels/:name",
+            patch(api_update_channel).delete(api_delete_channel),
+        )
+        .route(
+            "/channels/:name/roles/:peer",
+            put(api_set_role).delete(api_remove_role),
+        )
+        .route(
+            "/channels/:name/pins",
+            get(api_pins).post(api_pin).delete(api_unpin),
+        )
+        .route(
+            "/channels/:name/typing",
+            get(api_typing)
+                .put(api_start_typing)
+                .delete(api_stop_typing),
+        )
+        .route("/channels/:name/read", put(api_mark_read))
+        .route(
+            "/channels/:name/notifications",
+            get(api_channel_notifications).put(api_set_channel_notifications),
+        )
+        .route("/unread", get(api_unread))
+        .route("/notifications", get(api_notifications))
+        .route("/events", get(api_events))
+        .route("/messages", get(api_messages).post(api_publish))
+        .route("/messages/upload", post(api_upload))
+        .route("/scheduled", get(api_scheduled))
+        .route("/scheduled/:id", delete(api_cancel_scheduled))
+        .route("/search", get(api_search))
+        .route(
+            "/messages/:id",
+            patch(api_edit_message).delete(api_delete_message),
+        )
+        .route("/messages/:id/history", get(api_message_history))
+        .route("/messages/:id/thread", get(api_thread))
+        .route(
+            "/messages/:id/reactions/:emoji",
+            put(api_add_reaction).delete(api_remove_reaction),
+        )
+        .route("/moderation", get(api_moderation))
+        .route(
+            "/moderation/:peer/:sanction",
+            put(api_impose_sanction).delete(api_lift_sanction),
+        )
+        .route("/profile", get(api_own_profile).patch(api_update_profile))
+        .route(
+            "/profile/avatar",
+            put(api_set_avatar).delete(api_remove_avatar),
+        )
+        .route("/profiles", get(api_profiles))
+        .route("/profiles/:peer", get(api_profile))
+        .route("/profiles/:peer/avatar", get(api_avatar))
+        .route("/blocks", get(api_blocks))
+        .route("/blocks/:peer", put(api_block).delete(api_unblock))
+        .route("/dms", get(api_conversations))
+        .route("/dms/:peer", get(api_direct_messages).post(api_send_direct))
+        .route("/presence", get(api_presence).put(api_set_presence))
+        .route("/status", get(api_status))
+        .route("/voice/signals", get(api_voice_signals))
+        .route("/voice/signal", post(api_voice_signal_post))
+        .with_state(state);
+
+    let listener = TcpListener::bind(bind).await?;
+    axum::serve(listener, app)
+        .await
+        .map_err(|err| anyhow!(err.to_string()))
+}
+
+async fn api_health() -> impl IntoResponse {
+    StatusCode::OK
+}
+
+#[derive(Deserialize)]
+struct MessagesQuery {
+    #[serde(default = "default_channel")]
+    channel: String,
+    /// Include thread replies inline instead of only their roots.
+    #[serde(default)]
+    replies: bool,
+}
+
+/// A message as returned by the API, with fields derived from the local store.
+#[derive(Serialize)]
+struct MessageView {
+    #[serde(flatten)]
+    message: ChatMessage,
+    /// Number of replies in the thread started by this message.
+    #[serde(skip_serializing_if = "is_zero")]
+    reply_count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reactions: Vec<ReactionCount>,
+    /// Current profile of the publishing peer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<Profile>,
+    /// When the message disappears (RFC 3339), from its TTL or the channel's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReactionCount {
+    emoji: String,
+    count: usize,
+    /// Whether this node is among the reacting peers.
+    me: bool,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+fn message_views(
+    state: &ApiContext,
+    channel: &str,
+    messages: Vec<ChatMessage>,
+    include_replies: bool,
+) -> Vec<MessageView> {
+    let mut reply_counts: HashMap<Uuid, usize> = HashMap::new();
+    for root in messages.iter().filter_map(|m| m.thread_root) {
+        *reply_counts.entry(root).or_default() += 1;
+    }
+    let mut reactions = state.channel_state.reactions(channel);
+    let keys = group_keys(state, channel);
+    let ttl = state.channel_state.message_ttl(channel);
+    messages
+        .into_iter()
+        .filter(|m| include_replies || m.thread_root.is_none())
+        .filter(|m| !is_hidden(state, m))
+        .map(|message| message.opened(|epoch| keys.get(&epoch).cloned().unwrap_or_default()))
+        .map(|message| MessageView {
+            profile: message
+                .peer_id
+                .as_deref()
+                .and_then(|peer| state.channel_state.profile(peer))
+                .map(|p| p.payload),
+            reply_count: reply_counts.get(&message.id).copied().unwrap_or(0),
+            expires_at: message.expires_at(ttl).map(|at| at.to_rfc3339()),
+            reactions: reactions
+                .remove(&message.id)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|r| {
+                    let count = r
+                        .peers
+                        .iter()
+                        .filter(|peer| !is_hidden_peer(state, peer))
+                        .count();
+                    (count > 0).then(|| ReactionCount {
+                        count,
+                        me: r.peers.contains(&state.peer_id),
+                        emoji: r.emoji,
+                    })
+                })
+                .collect(),
+            message,
+        })
+        .collect()
+}
+
+/// Whether a message's publisher is banned from the grid or blocked by this node.
+fn is_hidden(state: &ApiContext, message: &ChatMessage) -> bool {
+    message
+        .peer_id
+        .as_deref()
+        .is_some_and(|peer| is_hidden_peer(state, peer))
+}
+
+/// Whether a peer is banned from the grid or blocked by this node.
+fn is_hidden_peer(state: &ApiContext, peer: &str) -> bool {
+    state.channel_state.is_banned(peer) || state.blocked.contains(peer)
+}
+
+fn default_channel() -> String {
+    "general".to_string()
+}
+
+/// A channel record as listed by the API, with the roles currently in effect.
+#[derive(Serialize)]
+struct ChannelView {
+    #[serde(flatten)]
+    channel: Channel,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    roles: BTreeMap<String, ChannelRole>,
+}
+
+fn channel_view(state: &ApiContext, channel: Channel) -> ChannelView {
+    ChannelView {
+        roles: state.channel_state.roles(channel.id()),
+        channel,
+    }
+}
+
+async fn api_channels(State(state): State<ApiContext>) -> impl IntoResponse {
+    let channels: Vec<ChannelView> = state
+        .channel_state
+        .describe_channels()
+        .into_iter()
+        .filter(|channel| can_read(&state, channel.id()))
+        .map(|channel| channel_view(&state, channel))
+        .collect();
+    Json(channels)
+}
+
+#[derive(Deserialize)]
+struct CreateChannelRequest {
+    name: String,
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default)]
+    topic: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    category: Option<String>,
+    /// Invite-only channel with end-to-end encrypted messages.
+    #[serde(default)]
+    private: bool,
+    /// Seconds after which messages in the channel disappear.
+    #[serde(default)]
+    message_ttl_secs: Option<u32>,
+}
+
+/// Fields of a channel record to change; absent fields are left as they are,
+/// an empty `category` clears it and a `message_ttl_secs` of 0 turns
+/// disappearing messages off.
+#[derive(Deserialize)]
+struct UpdateChannelRequest {
+    /// New name; the old one keeps resolving for a while.
+    name: Option<String>,
+    display_name: Option<String>,
+    topic: Option<String>,
+    description: Option<String>,
+    category: Option<String>,
+    message_ttl_secs: Option<u32>,
+}
+
+/// Trims a free-text channel field, mapping blank input to `None`.
+fn channel_text(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+async fn api_create_channel(
+    State(state): State<ApiContext>,
+    Json(payload): Json<CreateChannelRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.trim().to_lowercase();
+    let record = |id: &str| {
+        let mut record = Channel::new(
+            id,
+            &name,
+            state.peer_id.clone(),
+            state.channel_state.clock.tick(),
+        );
+        if let Some(display_name) = channel_text(payload.display_name) {
+            record.display_name = display_name;
+        }
+        record.topic = payload.topic.trim().to_string();
+        record.description = payload.description.trim().to_string();
+        record.category = channel_text(payload.category);
+        record.private = payload.private;
+        record.message_ttl_secs = payload.message_ttl_secs.filter(|&secs| secs > 0);
+        sign_payload(&state.keypair, record)
+    };
+    let id = match state.channel_state.add_channel_local(&name, record) {
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            return (
+                StatusCode::CONFLICT,
+                "channel name already in use".to_string(),
+            )
+                .into_response();
+        }
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    if payload.private
+        && let Err(e) = share_channel_key(&state, &id, true)
+    {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    if state
+        .sender
+        .send(ApiRequest::BroadcastChannels)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "channel created but broadcast failed".to_string(),
+        )
+            .into_response();
+    }
+    let Some(record) = state.channel_state.describe_channel(&id) else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "channel removed while being created".to_string(),
+        )
+            .into_response();
+    };
+    (StatusCode::CREATED, Json(channel_view(&state, record))).into_response()
+}
+
+/// Renames a channel or updates its display name, topic, description, category
+/// or message TTL.
+async fn api_update_channel(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+    Json(payload): Json<UpdateChannelRequest>,
+) -> impl IntoResponse {
+    let Some(mut record) = state
+        .channel_state
+        .resolve(&name)
+        .and_then(|id| state.channel_state.describe_channel(&id))
+    else {
+        return (StatusCode::NOT_FOUND, "unknown channel".to_string()).into_response();
+    };
+    if !state.channel_state.may_manage(record.id(), &state.peer_id) {
+        return (
+            StatusCode::FORBIDDEN,
+            "only owners and moderators may edit this channel".to_string(),
+        )
+            .into_response();
+    }
+    if let Some(new_name) = payload.name {
+        let new_name = new_name.trim().to_lowercase();
+        if let Err(e) = validate_channel_name(&new_name) {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+        if state
+            .channel_state
+            .find_by_name(&new_name)
+            .is_some_and(|id| id != record.id())
+        {
+            return (
+                StatusCode::CONFLICT,
+                "channel name already in use".to_string(),
+            )
+                .into_response();
+        }
+        record.rename(new_name);
+    }
+    if let Some(display_name) = payload.display_name {
+        record.display_name =
+            channel_text(Some(display_name)).unwrap_or_else(|| record.name.clone());
+    }
+    if let Some(topic) = payload.topic {
+        record.topic = topic.trim().to_string();
+    }
+    if let Some(description) = payload.description {
+        record.description = description.trim().to_string();
+    }
+    if payload.category.is_some() {
+        record.category = channel_text(payload.category);
+    }
+    if let Some(secs) = payload.message_ttl_secs {
+        record.message_ttl_secs = (secs > 0).then_some(secs);
+    }
+    record.hlc = Some(state.channel_state.clock.tick());
+    let Ok(signed) = sign_payload(&state.keypair, record.clone()) else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to sign channel record".to_string(),
+        )
+            .into_response();
+    };
+    if let Err(e) = state.channel_state.update_channel_local(signed) {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    if state
+        .sender
+        .send(ApiRequest::BroadcastChannels)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "channel updated but broadcast failed".to_string(),
+        )
+            .into_response();
+    }
+    Json(channel_view(&state, record)).into_response()
+}
+
+async fn api_delete_channel(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(id) = state.channel_state.resolve(&name) else {
+        return (StatusCode::NO_CONTENT, String::new());
+    };
+    if id != DEFAULT_CHANNEL && !state.channel_state.may_own(&id, &state.peer_id) {
+        return (
+            StatusCode::FORBIDDEN,
+            "only owners may delete this channel".to_string(),
+        );
+    }
+    if let Err(e) = state
+        .channel_state
+        .remove_channel_local(&id, |removal| sign_payload(&state.keypair, removal))
+    {
+        return (StatusCode::BAD_REQUEST, e.to_string());
+    }
+    if state
+        .sender
+        .send(ApiRequest::BroadcastChannels)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "channel deleted but broadcast failed".to_string(),
+        );
+    }
+    (StatusCode::NO_CONTENT, String::new())
+}
+
+#[derive(Deserialize)]
+struct RoleRequest {
+    role: ChannelRole,
+}
+
+/// Gives a peer a role in a channel; only owners and grid moderators may.
+async fn api_set_role(
+    State(state): State<ApiContext>,
+    AxumPath((name, peer)): AxumPath<(String, String)>,
+    Json(payload): Json<RoleRequest>,
+) -> impl IntoResponse {
+    send_role_grant(&state, &name, peer, Some(payload.role)).await
+}
+
+async fn api_remove_role(
+    State(state): State<ApiContext>,
+    AxumPath((name, peer)): AxumPath<(String, String)>,
+) -> impl IntoResponse {
+    send_role_grant(&state, &name, peer, None).await
+}
+
+async fn send_role_grant(
+    state: &ApiContext,
+    name: &str,
+    peer: String,
+    role: Option<ChannelRole>,
+) -> (StatusCode, String) {
+    let Some(id) = state.channel_state.resolve(name) else {
+        return (StatusCode::NOT_FOUND, "unknown channel".to_string());
+    };
+    if peer.parse::<PeerId>().is_err() {
+        return (StatusCode::BAD_REQUEST, "invalid peer id".to_string());
+    }
+    if !state.channel_state.may_own(&id, &state.peer_id) {
+        return (
+            StatusCode::FORBIDDEN,
+            "only owners may change roles".to_string(),
+        );
+    }
+    let grant = RoleGrant::new(&id, peer, role, state.channel_state.clock.tick());
+    let Ok(grant) = sign_payload(&state.keypair, grant) else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to sign role grant".to_string(),
+        );
+    };
+    if let Err(e) = state.channel_state.add_grant(grant) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    // Invitees get the current key; removing a member rotates it so they
+    // cannot read what follows.
+    if state.channel_state.is_private(&id)
+        && let Err(e) = share_channel_key(state, &id, role.is_none())
+    {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    if state
+        .sender
+        .send(ApiRequest::BroadcastChannels)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "role changed but broadcast failed".to_string(),
+        );
+    }
+    (StatusCode::NO_CONTENT, String::new())
+}
+
+/// A pinned message as listed by the API.
+#[derive(Serialize)]
+struct PinView {
+    message_id: Uuid,
+    pinned_by: String,
+    /// The message, when this node has it and its author is not hidden.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<MessageView>,
+}
+
+async fn api_pins(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(channel) = state.channel_state.resolve(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let pins = state.channel_state.pins(&channel);
+    let pinned: HashSet<Uuid> = pins.iter().map(|pin| pin.payload.message_id).collect();
+    let messages = state
+        .channel_state
+        .messages(&channel)
+        .into_iter()
+        .filter(|m| pinned.contains(&m.id))
+        .collect();
+    let mut views: HashMap<Uuid, MessageView> = message_views(&state, &channel, messages, true)
+        .into_iter()
+        .map(|view| (view.message.id, view))
+        .collect();
+    let pins: Vec<PinView> = pins
+        .into_iter()
+        .map(|pin| PinView {
+            message: views.remove(&pin.payload.message_id),
+            message_id: pin.payload.message_id,
+            pinned_by: pin.signer,
+        })
+        .collect();
+    Json(pins).into_response()
+}
+
+#[derive(Deserialize)]
+struct PinRequest {
+    message_id: Uuid,
+}
+
+async fn api_pin(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+    Json(payload): Json<PinRequest>,
+) -> impl IntoResponse {
+    send_pin(&state, &name, payload.message_id, true).await
+}
+
+async fn api_unpin(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+    Json(payload): Json<PinRequest>,
+) -> impl IntoResponse {
+    send_pin(&state, &name, payload.message_id, false).await
+}
+
+/// Pins or unpins a message; channel owners and moderators only.
+async fn send_pin(
+    state: &ApiContext,
+    name: &str,
+    message_id: Uuid,
+    pinned: bool,
+) -> (StatusCode, String) {
+    let Some(id) = state.channel_state.resolve(name) else {
+        return (StatusCode::NOT_FOUND, "unknown channel".to_string());
+    };
+    if !state.channel_state.may_moderate(&id, &state.peer_id) {
+        return (
+            StatusCode::FORBIDDEN,
+            "only owners and moderators may pin messages".to_string(),
+        );
+    }
+    let current = state.channel_state.pins(&id);
+    let is_pinned = current
+        .iter()
+        .any(|pin| pin.payload.message_id == message_id);
+    if pinned {
+        if state
+            .channel_state
+            .find_message(message_id)
+            .is_none_or(|(channel, _)| channel != id)
+        {
+            return (StatusCode::NOT_FOUND, "unknown message".to_string());
+        }
+        if is_pinned {
+            return (StatusCode::NO_CONTENT, String::new());
+        }
+        if current.len() >= MAX_PINS {
+            return (
+                StatusCode::CONFLICT,
+                format!("at most {MAX_PINS} messages may be pinned"),
+            );
+        }
+    } else if !is_pinned {
+        return (StatusCode::NOT_FOUND, "message is not pinned".to_string());
+    }
+    let pin = Pin {
+        channel: id,
+        message_id,
+        pinned,
+        hlc: state.channel_state.clock.tick(),
+    };
+    let Ok(pin) = sign_payload(&state.keypair, pin) else {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to sign pin".to_string(),
+        );
+    };
+    if let Err(e) = state.channel_state.set_pin(pin) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    if state
+        .sender
+        .send(ApiRequest::BroadcastChannels)
+        .await
+        .is_err()
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "pin changed but broadcast failed".to_string(),
+        );
+    }
+    (StatusCode::NO_CONTENT, String::new())
+}
+
+/// Peers typing in a channel, with their display names.
+#[derive(Serialize)]
+struct TypingView {
+    peer_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+}
+
+async fn api_typing(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(channel) = state.channel_state.resolve(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let typing: Vec<TypingView> = state
+        .telemetry
+        .typing_in(&channel)
+        .into_iter()
+        .filter(|peer| *peer != state.peer_id && !state.blocked.contains(peer))
+        .map(|peer_id| TypingView {
+            display_name: state
+                .channel_state
+                .profile(&peer_id)
+                .map(|p| p.payload.display_name),
+            peer_id,
+        })
+        .collect();
+    Json(typing).into_response()
+}
+
+/// Reports that the user is typing. Clients repeat this while typing; it is
+/// passed on to peers at most every [`TYPING_INTERVAL`].
+async fn api_start_typing(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    send_typing(&state, &name, true).await
+}
+
+async fn api_stop_typing(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    send_typing(&state, &name, false).await
+}
+
+async fn send_typing(state: &ApiContext, name: &str, typing: bool) -> StatusCode {
+    let channel = match publish_target(state, name) {
+        Ok(channel) => channel,
+        Err(status) => return status,
+    };
+    // Typing goes out unsealed, so it stays local in private channels rather
+    // than tell every peer when their members are active.
+    if !state
+        .telemetry
+        .note_typing(&channel, &state.peer_id, typing)
+        || state.channel_state.is_private(&channel)
+    {
+        return StatusCode::NO_CONTENT;
+    }
+    match state
+        .sender
+        .send(ApiRequest::Typing { channel, typing })
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(Deserialize)]
+struct MarkReadRequest {
+    message_id: Uuid,
+}
+
+/// Marks a channel read up to `message_id`, or up to its latest message when
+/// no body is sent. Moving the marker back marks later messages unread.
+async fn api_mark_read(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+    payload: Option<Json<MarkReadRequest>>,
+) -> impl IntoResponse {
+    let Some(channel) = state.channel_state.resolve(&name) else {
+        return StatusCode::NOT_FOUND;
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN;
+    }
+    let Some(store) = state.channel_state.get_store(&channel) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let message = match payload {
+        Some(Json(request)) => match store.message(request.message_id) {
+            Some(message) => message,
+            None => return StatusCode::NOT_FOUND,
+        },
+        None => match state.channel_state.messages(&channel).pop() {
+            Some(message) => message,
+            None => return StatusCode::NO_CONTENT,
+        },
+    };
+    let marker = ReadMarker::at(&message);
+    let saved = {
+        let mut markers = state.read_markers.write();
+        markers.set(channel.clone(), marker);
+        markers.save(state.channel_state.data_dir.join("read-markers.json"))
+    };
+    if let Err(err) = saved {
+        warn!(%err, "unable to save read markers");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    state.telemetry.events.send(StreamEvent::Read {
+        channel,
+        message_id: message.id,
+    });
+    StatusCode::NO_CONTENT
+}
+
+/// Unread state of a channel for this node's user.
+#[derive(Serialize)]
+struct UnreadView {
+    /// Channel id.
+    channel: String,
+    name: String,
+    unread: usize,
+    /// Unread messages that mention this node's user, `@here` or `@everyone`.
+    mentions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_read: Option<Uuid>,
+}
+
+/// Lists readable channels with their unread and mention counts. The user's
+/// own messages and those of hidden peers never count as unread.
+async fn api_unread(State(state): State<ApiContext>) -> impl IntoResponse {
+    let markers = state.read_markers.read();
+    let unread: Vec<UnreadView> = state
+        .channel_state
+        .describe_channels()
+        .into_iter()
+        .filter(|channel| can_read(&state, channel.id()))
+        .map(|channel| {
+            let id = channel.id().to_string();
+            let keys = group_keys(&state, &id);
+            let mut view = UnreadView {
+                last_read: markers.get(&id).map(|m| m.message_id),
+                unread: 0,
+                mentions: 0,
+                name: channel.name,
+                channel: id,
+            };
+            for message in state.channel_state.messages(&view.channel) {
+                if message.peer_id.as_deref() == Some(state.peer_id.as_str())
+                    || is_hidden(&state, &message)
+                    || !markers.is_unread(&view.channel, &message)
+                {
+                    continue;
                 }
-            }
-            event = swarm.select_next_some() => {
-                handle_swarm_event(&mut swarm, event, channel_state.clone(), telemetry.clone(), voice_signals.clone(), local_peer_id, MAX_VOICE_SIGNALS);
-            }
-            voice_signal = voice_rx.recv(), if api_enabled => {
-                if let Some(sig) = voice_signal {
-                    if let Err(err) = publish_voice_signal(sig, &topic, &mut swarm) {
-                        warn!(%err, "failed to publish voice signal");
-                    }
+                view.unread += 1;
+                let message = message.opened(|epoch| keys.get(&epoch).cloned().unwrap_or_default());
+                if notification_reason(&state, &view.channel, &message)
+                    .is_some_and(NotificationReason::is_mention)
+                {
+                    view.mentions += 1;
                 }
             }
-            api_request = api_rx.recv(), if api_enabled => {
-                match api_request {
-                    Some(ApiRequest::SendMessage { channel, message }) => {
-                        let author = message.author.clone();
-                        if let Err(err) = publish_chat_message(&channel, message, &channel_state, &topic, &mut swarm, &telemetry) {
-                            warn!(%err, "failed to relay api message");
-                        } else {
-                            info!(%author, %channel, "api message relayed");
-                        }
-                    }
-                    Some(ApiRequest::BroadcastChannelList) => {
-                        let list = channel_state.list();
-                        if let Err(err) = publish_channel_list(&list, &topic, &mut swarm) {
-                            warn!(%err, "failed to broadcast channel list");
-                        }
-                    }
-                    Some(ApiRequest::BroadcastChannelRemoved(name)) => {
-                        if let Err(err) = publish_channel_removed(&name, &topic, &mut swarm) {
-                            warn!(%err, "failed to broadcast channel removed");
-                        }
-                    }
-                    None => {
-                        api_enabled = false;
-                        warn!("api channel closed");
-                    }
+            view
+        })
+        .collect();
+    Json(unread)
+}
+
+/// A message that notifies the user, as listed by `/notifications` and streamed
+/// as a `notification` event.
+#[derive(Clone, Serialize)]
+struct NotificationView {
+    /// Channel id.
+    channel: String,
+    channel_name: String,
+    reason: NotificationReason,
+    /// Whether the channel's read marker covers the message.
+    read: bool,
+    message: ChatMessage,
+}
+
+/// Why an (opened) message notifies this node's user, per the channel's
+/// settings. `@here` reaches the user while their status is online or idle.
+fn notification_reason(
+    state: &ApiContext,
+    channel: &str,
+    message: &ChatMessage,
+) -> Option<NotificationReason> {
+    let sender = message.peer_id.as_deref()?;
+    if sender == state.peer_id || is_hidden(state, message) {
+        return None;
+    }
+    let online = matches!(
+        state.telemetry.status(),
+        PresenceStatus::Online | PresenceStatus::Idle
+    );
+    state.notifications.read().get(channel).reason(
+        message,
+        &state.peer_id,
+        state.channel_state.may_moderate(channel, sender),
+        online,
+    )
+}
+
+fn notification(
+    state: &ApiContext,
+    channel: &str,
+    message: ChatMessage,
+) -> Option<NotificationView> {
+    if !can_read(state, channel) {
+        return None;
+    }
+    let keys = group_keys(state, channel);
+    let message = message.opened(|epoch| keys.get(&epoch).cloned().unwrap_or_default());
+    let reason = notification_reason(state, channel, &message)?;
+    Some(NotificationView {
+        channel_name: state.channel_state.describe_channel(channel)?.name,
+        read: !state.read_markers.read().is_unread(channel, &message),
+        channel: channel.to_string(),
+        reason,
+        message,
+    })
+}
+
+#[derive(Deserialize)]
+struct NotificationsQuery {
+    #[serde(default = "default_notification_limit")]
+    limit: usize,
+    /// Only list notifications not yet covered by a read marker.
+    #[serde(default)]
+    unread: bool,
+}
+
+fn default_notification_limit() -> usize {
+    50
+}
+
+/// Lists the user's notifications across channels, newest first.
+async fn api_notifications(
+    State(state): State<ApiContext>,
+    Query(q): Query<NotificationsQuery>,
+) -> impl IntoResponse {
+    let mut notifications: Vec<NotificationView> = state
+        .channel_state
+        .ids()
+        .into_iter()
+        .flat_map(|channel| {
+            state
+                .channel_state
+                .messages(&channel)
+                .into_iter()
+                .filter_map(|message| notification(&state, &channel, message))
+                .collect::<Vec<_>>()
+        })
+        .filter(|n| !(q.unread && n.read))
+        .collect();
+    notifications.sort_by(|a, b| b.message.order_key().cmp(&a.message.order_key()));
+    notifications.truncate(q.limit);
+    Json(notifications)
+}
+
+async fn api_channel_notifications(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(channel) = state.channel_state.resolve(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    Json(state.notifications.read().get(&channel)).into_response()
+}
+
+/// Longest timed mute, a year; `level: nothing` mutes for good.
+const MAX_MUTE_SECS: i64 = 365 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+struct ChannelNotificationsRequest {
+    #[serde(default)]
+    level: NotifyLevel,
+    #[serde(default)]
+    suppress_everyone: bool,
+    /// Mutes the channel for this long, whatever its level.
+    #[serde(default)]
+    mute_secs: Option<i64>,
+}
+
+/// Replaces the user's notification settings for a channel.
+async fn api_set_channel_notifications(
+    State(state): State<ApiContext>,
+    AxumPath(name): AxumPath<String>,
+    Json(payload): Json<ChannelNotificationsRequest>,
+) -> impl IntoResponse {
+    let Some(channel) = state.channel_state.resolve(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if payload
+        .mute_secs
+        .is_some_and(|secs| secs <= 0 || secs > MAX_MUTE_SECS)
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let settings =
+        ChannelNotifications::new(payload.level, payload.suppress_everyone, payload.mute_secs);
+    let saved = {
+        let mut notifications = state.notifications.write();
+        notifications.set(channel, settings.clone());
+        notifications.save(
+            state
+                .channel_state
+                .data_dir
+                .join("notification-settings.json"),
+        )
+    };
+    match saved {
+        Ok(()) => Json(settings).into_response(),
+        Err(err) => {
+            warn!(%err, "unable to save notification settings");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The form of a live event shown to this node's clients, if any.
+fn client_event(state: &ApiContext, event: StreamEvent) -> Option<StreamEvent> {
+    match event {
+        StreamEvent::Typing {
+            ref channel,
+            ref peer_id,
+            ..
+        } => (*peer_id != state.peer_id
+            && !state.blocked.contains(peer_id)
+            && can_read(state, channel))
+        .then_some(event),
+        StreamEvent::Read { .. } | StreamEvent::Notification(_) => Some(event),
+        StreamEvent::Received {
+            channel,
+            message_id,
+        } => {
+            let message = state
+                .channel_state
+                .get_store(&channel)?
+                .message(message_id)?;
+            notification(state, &channel, message).map(|n| StreamEvent::Notification(Box::new(n)))
+        }
+    }
+}
+
+/// Server-sent events stream of live events (typing indicators, read markers,
+/// notifications).
+async fn api_events(
+    State(state): State<ApiContext>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events =
+        futures::stream::unfold(state.telemetry.events.0.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
                 }
             }
-            result = &mut shutdown => {
-                if let Err(err) = result {
-                    warn!(%err, "ctrl+c listener failed");
+        });
+    let stream = events
+        .filter_map(move |event| futures::future::ready(client_event(&state, event)))
+        .map(|event| {
+            let data = serde_json::to_string(&event).unwrap_or_default();
+            Ok(Event::default().event(event.name()).data(data))
+        });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn api_messages(
+    State(state): State<ApiContext>,
+    Query(q): Query<MessagesQuery>,
+) -> impl IntoResponse {
+    let channel = state.channel_state.resolve(&q.channel).unwrap_or(q.channel);
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let messages = state.channel_state.messages(&channel);
+    Json(message_views(&state, &channel, messages, q.replies)).into_response()
+}
+
+/// Lists a thread: the root message followed by all of its replies.
+async fn api_thread(
+    State(state): State<ApiContext>,
+    AxumPath(id): AxumPath<Uuid>,
+) -> impl IntoResponse {
+    let Some((channel, message)) = state.channel_state.find_message(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let root = message.thread_root.unwrap_or(message.id);
+    let messages = state.channel_state.thread(&channel, root);
+    Json(message_views(&state, &channel, messages, true)).into_response()
+}
+
+/// Most results one search returns.
+const MAX_SEARCH_RESULTS: usize = 100;
+
+/// Length of the body snippet in a search result, in characters.
+const SNIPPET_CHARS: usize = 160;
+
+/// A search hit as returned by the API.
+#[derive(Serialize)]
+struct SearchResult {
+    /// Channel id.
+    channel: String,
+    channel_name: String,
+    score: f64,
+    /// Part of the body around the first match, HTML-escaped with matches in `<mark>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+    /// Matching attachment filenames, highlighted the same way.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    filenames: Vec<String>,
+    message: ChatMessage,
+}
+
+/// Searches message bodies and attachment filenames in the channels this node
+/// can read. Private channels are not in the index, whose contents would
+/// otherwise sit decrypted in memory; they are searched by opening their
+/// messages for the query.
+async fn api_search(
+    State(state): State<ApiContext>,
+    Query(mut query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let terms = search::query_terms(&query.q);
+    if terms.is_empty() {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    if let Some(name) = &query.channel {
+        let Some(channel) = state.channel_state.resolve(name) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if !can_read(&state, &channel) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        query.channel = Some(channel);
+    }
+    // Hidden and unreadable hits are dropped below, so ask for every hit.
+    let limit = query.limit.min(MAX_SEARCH_RESULTS);
+    query.limit = usize::MAX;
+
+    let mut hits = state.channel_state.search(&query);
+    hits.extend(search_private(&state, &query));
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    // Opened messages of the channels hits come from, by id.
+    let mut channels: HashMap<String, HashMap<Uuid, ChatMessage>> = HashMap::new();
+    let mut results = Vec::new();
+    for hit in hits {
+        if results.len() == limit {
+            break;
+        }
+        if !can_read(&state, &hit.channel) {
+            continue;
+        }
+        let messages = channels.entry(hit.channel.clone()).or_insert_with(|| {
+            let keys = group_keys(&state, &hit.channel);
+            state
+                .channel_state
+                .messages(&hit.channel)
+                .into_iter()
+                .map(|m| {
+                    (
+                        m.id,
+                        m.opened(|epoch| keys.get(&epoch).cloned().unwrap_or_default()),
+                    )
+                })
+                .collect()
+        });
+        let Some(message) = messages.remove(&hit.message_id) else {
+            continue;
+        };
+        if is_hidden(&state, &message) {
+            continue;
+        }
+        let Some(channel) = state.channel_state.describe_channel(&hit.channel) else {
+            continue;
+        };
+        results.push(SearchResult {
+            snippet: search::highlight(&message.body, &terms, SNIPPET_CHARS),
+            filenames: message
+                .attachments
+                .iter()
+                .filter_map(|a| search::highlight(&a.filename, &terms, SNIPPET_CHARS))
+                .collect(),
+            channel: hit.channel,
+            channel_name: channel.name,
+            score: hit.score,
+            message,
+        });
+    }
+    Json(results).into_response()
+}
+
+/// Searches the private channels this node can read, through indexes of their
+/// opened messages that are kept until the channel changes.
+fn search_private(state: &ApiContext, query: &SearchQuery) -> Vec<SearchHit> {
+    let readable: Vec<String> = state
+        .channel_state
+        .ids()
+        .into_iter()
+        .filter(|channel| state.channel_state.is_private(channel) && can_read(state, channel))
+        .collect();
+    let mut cache = state.private_search.write();
+    cache.retain(|channel, _| readable.contains(channel));
+    let mut hits = Vec::new();
+    for channel in readable {
+        if query.channel.as_ref().is_some_and(|c| *c != channel) {
+            continue;
+        }
+        let Some(store) = state.channel_state.get_store(&channel) else {
+            continue;
+        };
+        let revision = store.revision();
+        let keys = group_keys(state, &channel);
+        let key_count = keys.values().map(Vec::len).sum();
+        let current = cache
+            .get(&channel)
+            .is_some_and(|cached| cached.revision == revision && cached.keys == key_count);
+        if !current {
+            let mut index = SearchIndex::default();
+            for message in state.channel_state.messages(&channel) {
+                index.insert(
+                    &channel,
+                    &message.opened(|epoch| keys.get(&epoch).cloned().unwrap_or_default()),
+                );
+            }
+            cache.insert(
+                channel.clone(),
+                PrivateIndex {
+                    revision,
+                    keys: key_count,
+                    index,
+                },
+            );
+        }
+        hits.extend(cache[&channel].index.search(query));
+    }
+    hits
+}
+
+/// Max total size of attachment data (base64 decoded) per message to keep gossip payloads safe.
+const MAX_ATTACHMENT_BYTES: usize = 512 * 1024; // 512 KB
+
+/// Publishes a message now, or with `send_at` holds it until then and
+/// answers 201 with the scheduled entry.
+async fn api_publish(
+    State(state): State<ApiContext>,
+    Json(payload): Json<PublishRequest>,
+) -> impl IntoResponse {
+    let channel = match publish_target(&state, &payload.channel) {
+        Ok(channel) => channel,
+        Err(status) => return status.into_response(),
+    };
+    if (payload.body.trim().is_empty() && payload.attachments.is_empty())
+        || payload.ttl_secs == Some(0)
+    {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let parent = match reply_parent(&state, &channel, payload.reply_to) {
+        Ok(parent) => parent,
+        Err(status) => return status.into_response(),
+    };
+
+    let author = payload.author.unwrap_or_else(|| default_author(&state));
+
+    let attachments = decode_attachments(&payload.attachments);
+    let mentions = resolve_mentions(&state, &channel, &payload.body);
+
+    let mut message = ChatMessage::with_attachments(author, payload.body, attachments)
+        .with_peer_id(state.peer_id.clone())
+        .with_mentions(mentions)
+        .with_ttl(payload.ttl_secs);
+    if let Some(parent) = &parent {
+        message = message.in_reply_to(parent);
+    }
+    if let Some(send_at) = payload.send_at {
+        return schedule_message(&state, channel, message, &send_at);
+    }
+    let message = match seal_message(
+        &state,
+        &channel,
+        message.with_hlc(state.channel_state.clock.tick()),
+    ) {
+        Ok(message) => message,
+        Err(status) => return status.into_response(),
+    };
+
+    match send_message(&state, channel, message).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Hands a message to the network task and waits until it is published:
+/// 413 when it is too large to gossip, 502 when publishing fails otherwise.
+async fn send_message(
+    state: &ApiContext,
+    channel: String,
+    message: ChatMessage,
+) -> Result<(), StatusCode> {
+    let (sent, result) = oneshot::channel();
+    state
+        .sender
+        .send(ApiRequest::SendMessage {
+            channel,
+            message,
+            sent,
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match result
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        Ok(()) => Ok(()),
+        Err(err) => match err.downcast_ref::<gossipsub::PublishError>() {
+            Some(gossipsub::PublishError::MessageTooLarge) => Err(StatusCode::PAYLOAD_TOO_LARGE),
+            _ => Err(StatusCode::BAD_GATEWAY),
+        },
+    }
+}
+
+/// A message waiting to be sent, as listed by the API.
+#[derive(Serialize)]
+struct ScheduledView {
+    id: Uuid,
+    channel_name: String,
+    #[serde(flatten)]
+    scheduled: ScheduledMessage,
+}
+
+fn scheduled_view(state: &ApiContext, scheduled: ScheduledMessage) -> ScheduledView {
+    ScheduledView {
+        id: scheduled.id(),
+        channel_name: state
+            .channel_state
+            .describe_channel(&scheduled.channel)
+            .map(|c| c.name)
+            .unwrap_or_else(|| scheduled.channel.clone()),
+        scheduled,
+    }
+}
+
+/// Holds an unsealed, unstamped message until `send_at`; 400 when the time
+/// is malformed or not in the future.
+fn schedule_message(
+    state: &ApiContext,
+    channel: String,
+    message: ChatMessage,
+    send_at: &str,
+) -> Response {
+    let scheduled = match ScheduledMessage::new(channel, message, send_at) {
+        Ok(scheduled) => scheduled,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let saved = {
+        let mut schedule = state.scheduled.write();
+        schedule.add(scheduled.clone());
+        schedule.save(state.channel_state.data_dir.join("scheduled.json"))
+    };
+    if let Err(err) = saved {
+        warn!(%err, "unable to save scheduled messages");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (StatusCode::CREATED, Json(scheduled_view(state, scheduled))).into_response()
+}
+
+/// Sends scheduled messages once they are due, stamped and sealed at that
+/// point. Messages whose channel is gone or no longer writable, whose parent
+/// was deleted or that are too large to publish are dropped; other failed
+/// sends are retried on the next tick.
+///
+/// A due message is taken out of the schedule before it is sent, so a
+/// cancel from then on answers 404 instead of racing the send. The file
+/// keeps it until the outcome is known, so a node stopping mid-send sends
+/// it again on restart, under the same id.
+async fn send_scheduled(state: ApiContext) {
+    let mut tick = tokio::time::interval(SCHEDULE_TICK);
+    loop {
+        tick.tick().await;
+        let due: Vec<Uuid> = state
+            .scheduled
+            .read()
+            .due()
+            .iter()
+            .map(ScheduledMessage::id)
+            .collect();
+        for id in due {
+            let Some(scheduled) = state.scheduled.write().cancel(id) else {
+                continue;
+            };
+            match prepare_scheduled(&state, scheduled.clone()) {
+                Err((channel, status)) => {
+                    warn!(%id, %channel, %status, "dropping scheduled message")
                 }
-                info!("shutdown requested");
-                break;
+                Ok((channel, message)) => match send_message(&state, channel, message).await {
+                    Ok(()) => {}
+                    Err(StatusCode::PAYLOAD_TOO_LARGE) => {
+                        warn!(%id, "dropping scheduled message too large to publish")
+                    }
+                    Err(status) => {
+                        warn!(%id, %status, "unable to send scheduled message, retrying");
+                        state.scheduled.write().add(scheduled);
+                    }
+                },
             }
+            save_schedule(&state);
         }
     }
-
-    Ok(())
 }
 
-fn publish_line(
-    line: &str,
-    nickname: &str,
-    store: &Arc<ChatStore>,
-    channel: &str,
-    topic: &gossipsub::IdentTopic,
-    swarm: &mut Swarm<GridBehaviour>,
-    telemetry: &Telemetry,
-) -> Result<()> {
-    if line.trim().is_empty() {
-        return Ok(());
+/// Builds a due message as if it were published now: the channel, the
+/// parent it replies to and its mentions are resolved again, since any of
+/// them may have changed while it waited. Fails with the channel and the
+/// status the API would have answered.
+fn prepare_scheduled(
+    state: &ApiContext,
+    scheduled: ScheduledMessage,
+) -> Result<(String, ChatMessage), (String, StatusCode)> {
+    let channel = publish_target(state, &scheduled.channel)
+        .map_err(|status| (scheduled.channel.clone(), status))?;
+    let failed = |status| (channel.clone(), status);
+    let mut message = scheduled.into_message();
+    if let Some(parent) = reply_parent(state, &channel, message.reply_to).map_err(failed)? {
+        message = message.in_reply_to(&parent);
     }
-    let message = ChatMessage::new(nickname.to_string(), line.to_owned());
-    let envelope = serde_json::json!({ "channel": channel, "message": message });
-    let bytes = serde_json::to_vec(&envelope)?;
-    store.append(message.clone())?;
-    swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes)?;
-    telemetry.note_message(message.timestamp.to_rfc3339());
-    println!("[{}] you :: {}", channel, message.body);
-    Ok(())
+    let mentions = resolve_mentions(state, &channel, &message.body);
+    let message = message
+        .with_mentions(mentions)
+        .with_hlc(state.channel_state.clock.tick());
+    let message = seal_message(state, &channel, message).map_err(failed)?;
+    Ok((channel, message))
 }
 
-fn publish_chat_message(
-    channel: &str,
-    message: ChatMessage,
-    channel_state: &ChannelState,
-    topic: &gossipsub::IdentTopic,
-    swarm: &mut Swarm<GridBehaviour>,
-    telemetry: &Telemetry,
-) -> Result<()> {
-    channel_state.append_message(channel, message.clone())?;
-    let envelope = serde_json::json!({ "channel": channel, "message": message });
-    let bytes = serde_json::to_vec(&envelope)?;
-    swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes)?;
-    telemetry.note_message(message.timestamp.to_rfc3339());
-    Ok(())
+/// Saves the schedule once a due message has been sent, dropped or put back.
+fn save_schedule(state: &ApiContext) {
+    if let Err(err) = state
+        .scheduled
+        .read()
+        .save(state.channel_state.data_dir.join("scheduled.json"))
+    {
+        warn!(%err, "unable to save scheduled messages");
+    }
 }
 
-fn publish_channel_list(
-    list: &[String],
-    topic: &gossipsub::IdentTopic,
-    swarm: &mut Swarm<GridBehaviour>,
-) -> Result<()> {
-    let envelope = serde_json::json!({ "channel_list": list });
-    let bytes = serde_json::to_vec(&envelope)?;
-    swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes)?;
-    Ok(())
+#[derive(Deserialize)]
+struct ScheduledQuery {
+    channel: Option<String>,
 }
 
-fn publish_channel_removed(
-    name: &str,
-    topic: &gossipsub::IdentTopic,
-    swarm: &mut Swarm<GridBehaviour>,
-) -> Result<()> {
-    let envelope = serde_json::json!({ "channel_removed": name });
-    let bytes = serde_json::to_vec(&envelope)?;
-    swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes)?;
-    Ok(())
+/// Pending scheduled messages, earliest first, optionally for one channel.
+async fn api_scheduled(
+    State(state): State<ApiContext>,
+    Query(query): Query<ScheduledQuery>,
+) -> impl IntoResponse {
+    let channel = match query.channel {
+        Some(name) => match state.channel_state.resolve(&name) {
+            Some(id) => Some(id),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+        None => None,
+    };
+    let pending: Vec<ScheduledMessage> = state
+        .scheduled
+        .read()
+        .pending()
+        .iter()
+        .filter(|s| channel.as_ref().is_none_or(|c| *c == s.channel))
+        .cloned()
+        .collect();
+    let views: Vec<ScheduledView> = pending
+        .into_iter()
+        .map(|s| scheduled_view(&state, s))
+        .collect();
+    Json(views).into_response()
 }
 
-async fn build_swarm(
-    local_key: identity::Keypair,
-    topic: gossipsub::IdentTopic,
-) -> Result<Swarm<GridBehaviour>> {
-    let msg_auth = gossipsub::MessageAuthenticity::Signed(local_key.clone());
-    let gossip_config = gossipsub::ConfigBuilder::default()
-        .validation_mode(gossipsub::ValidationMode::Permissive)
-        .heartbeat_interval(Duration::from_secs(1))
-        .build()?;
-    let mut gossipsub = gossipsub::Behaviour::new(msg_auth, gossip_config)
-        .map_err(|err| anyhow::Error::msg(err.to_string()))?;
-    gossipsub.subscribe(&topic)?;
-
-    let mdns =
-        mdns::tokio::Behaviour::new(mdns::Config::default(), PeerId::from(local_key.public()))?;
-    let identify = identify::Behaviour::new(identify::Config::new(
-        "/gridspeak/0.1.0".into(),
-        local_key.public(),
-    ));
+/// Cancels a scheduled message; 404 when it is unknown or already sent.
+async fn api_cancel_scheduled(
+    State(state): State<ApiContext>,
+    AxumPath(id): AxumPath<Uuid>,
+) -> impl IntoResponse {
+    let mut schedule = state.scheduled.write();
+    if schedule.cancel(id).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+    match schedule.save(state.channel_state.data_dir.join("scheduled.json")) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            warn!(%err, "unable to save scheduled messages");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let behaviour = GridBehaviour {
-        gossipsub,
-        mdns,
-        identify,
+/// Resolves the mentions in a body sent to `channel`. `@name` matches peers by
+/// PeerId or by display name (case-insensitive, spaces written as `_`);
+/// `#name` matches channels this node can read. `@here` and `@everyone` are
+/// kept only when this node moderates the channel.
+fn resolve_mentions(state: &ApiContext, channel: &str, body: &str) -> Vec<Mention> {
+    let profiles = state.channel_state.profiles();
+    let resolve_user = |name: &str| -> Vec<String> {
+        if name.parse::<PeerId>().is_ok() {
+            return vec![name.to_string()];
+        }
+        let name = name.to_lowercase();
+        profiles
+            .iter()
+            .filter(|p| !state.channel_state.is_banned(&p.signer))
+            .filter(|p| p.payload.display_name.to_lowercase().replace(' ', "_") == name)
+            .map(|p| p.signer.clone())
+            .collect()
     };
+    let resolve_channel = |name: &str| {
+        state
+            .channel_state
+            .resolve(name)
+            .filter(|id| can_read(state, id))
+    };
+    let mut mentions = parse_mentions(body, resolve_user, resolve_channel);
+    if !state.channel_state.may_moderate(channel, &state.peer_id) {
+        mentions.retain(|m| !m.is_broadcast());
+    }
+    mentions
+}
 
-    let swarm = SwarmBuilder::with_existing_identity(local_key)
-        .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_behaviour(|_| behaviour)?
-        .build();
-
-    Ok(swarm)
+/// Decodes inline attachments, dropping any that are not valid base64 or would
+/// take the message past [`MAX_ATTACHMENT_BYTES`].
+fn decode_attachments(payloads: &[AttachmentPayload]) -> Vec<Attachment> {
+    let mut total_attachment_bytes: usize = 0;
+    payloads
+        .iter()
+        .filter_map(|a| {
+            let decoded = base64::Engine::decode(
+                &base64::engine::general_purpose::STANDARD,
+                a.data_base64.as_bytes(),
+            )
+            .ok()?;
+            let decoded_len = decoded.len();
+            if total_attachment_bytes + decoded_len > MAX_ATTACHMENT_BYTES {
+                return None;
+            }
+            total_attachment_bytes += decoded_len;
+            Some(Attachment {
+                content_type: a.content_type.clone(),
+                filename: a.filename.clone(),
+                data_base64: a.data_base64.clone(),
+                size: Some(decoded_len as u64),
+                sha256: None,
+            })
+        })
+        .collect()
 }
 
-fn publish_voice_signal(
-    signal: VoiceSignal,
-    topic: &gossipsub::IdentTopic,
-    swarm: &mut Swarm<GridBehaviour>,
-) -> Result<()> {
-    let envelope = serde_json::json!({ "voice_signal": signal });
-    let bytes = serde_json::to_vec(&envelope)?;
-    swarm.behaviour_mut().gossipsub.publish(topic.clone(), bytes)?;
-    Ok(())
+/// Accepts `multipart/form-data` with `channel`, `body`, optional `author` and any
+/// number of file fields. Files are streamed into the blob store, hashed there and
+/// attached inline so peers receive them over gossip as usual.
+async fn api_upload(State(state): State<ApiContext>, multipart: Multipart) -> impl IntoResponse {
+    let mut stored = Vec::new();
+    let result = upload_message(&state, multipart, &mut stored).await;
+    if result.is_err()
+        && let Err(err) = state
+            .channel_state
+            .release_blobs(stored.iter().map(String::as_str))
+    {
+        warn!(%err, "unable to remove uploaded blobs");
+    }
+    result.unwrap_or_else(|status| status)
 }
 
-fn handle_swarm_event(
-    swarm: &mut Swarm<GridBehaviour>,
-    event: SwarmEvent<GridEvent>,
-    channel_state: ChannelState,
-    telemetry: Telemetry,
-    voice_signals: Arc<RwLock<Vec<VoiceSignal>>>,
-    local_peer_id: PeerId,
-    max_voice_signals: usize,
-) {
-    match event {
-        SwarmEvent::Behaviour(GridEvent::Gossipsub(gossipsub::Event::Message {
-            propagation_source,
-            message_id,
-            message,
-        })) => {
-            if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&message.data) {
-                if let Some(voice) = value.get("voice_signal") {
-                    if let Ok(sig) = serde_json::from_value::<VoiceSignal>(voice.clone()) {
-                        if sig.from != local_peer_id.to_string() {
-                            let mut guard = voice_signals.write();
-                            guard.push(sig);
-                            let n = guard.len();
-                            if n > max_voice_signals {
-                                let drop = n - max_voice_signals;
-                                guard.drain(0..drop);
-                            }
-                        }
-                        return;
-                    }
-                }
-                if let Some(list) = value.get("channel_list").and_then(|v| v.as_array()) {
-                    let channels: Vec<String> = list
-                        .iter()
-                        .filter_map(|v| v.as_str().map(String::from))
-                        .collect();
-                    if !channels.is_empty() {
-                        channel_state.merge_channels_from_remote(&channels);
-                    }
-                    return;
-                }
-                if let Some(removed) = value.get("channel_removed").and_then(|v| v.as_str()) {
-                    channel_state.remove_channel_in_memory(removed);
-                    return;
-                }
-                if let (Some(ch), Some(msg)) = (value.get("channel").and_then(|v| v.as_str()), value.get("message")) {
-                    if let Ok(chat) = serde_json::from_value::<ChatMessage>(msg.clone()) {
-                        if let Err(err) = channel_state.append_message(ch, chat.clone()) {
-                            warn!(%err, "unable to persist message");
-                        } else {
-                            telemetry.note_message(chat.timestamp.to_rfc3339());
-                            println!("[{}] {} :: {}", ch, chat.author, chat.body);
-                            info!(%propagation_source, %message_id, "message received");
-                        }
-                    }
+/// Reads an upload and sends its message, recording in `stored` the blobs it
+/// wrote so the caller can release them if it fails. When the text fields
+/// come before the files, the channel and the reply are checked before any
+/// file is written.
+async fn upload_message(
+    state: &ApiContext,
+    mut multipart: Multipart,
+    stored: &mut Vec<String>,
+) -> Result<StatusCode, StatusCode> {
+    let mut channel = None;
+    let mut target = None;
+    let mut body = String::new();
+    let mut author = None;
+    let mut reply_to = None;
+    let mut ttl_secs = None;
+    let mut attachments = Vec::new();
+    let mut total_attachment_bytes: u64 = 0;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+    {
+        let Some(filename) = field.file_name().map(String::from) else {
+            let name = field.name().unwrap_or_default().to_string();
+            let text = field.text().await.map_err(|_| StatusCode::BAD_REQUEST)?;
+            match name.as_str() {
+                "channel" => channel = Some(text),
+                "body" => body = text,
+                "author" => author = Some(text),
+                "reply_to" => {
+                    reply_to = Some(
+                        text.trim()
+                            .parse::<Uuid>()
+                            .map_err(|_| StatusCode::BAD_REQUEST)?,
+                    )
                 }
+                "ttl_secs" => match text.trim().parse::<u32>() {
+                    Ok(secs) if secs > 0 => ttl_secs = Some(secs),
+                    _ => return Err(StatusCode::BAD_REQUEST),
+                },
+                _ => {}
             }
+            continue;
+        };
+        if target.is_none()
+            && let Some(name) = &channel
+        {
+            let id = publish_target(state, name)?;
+            reply_parent(state, &id, reply_to)?;
+            target = Some(id);
         }
-        SwarmEvent::Behaviour(GridEvent::Mdns(mdns::Event::Discovered(list))) => {
-            for (peer, _addr) in list {
-                info!(%peer, "mdns peer discovered");
-                telemetry.note_peer_online(&peer);
-                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer);
-            }
-        }
-        SwarmEvent::Behaviour(GridEvent::Mdns(mdns::Event::Expired(list))) => {
-            for (peer, _addr) in list {
-                info!(%peer, "mdns peer expired");
-                telemetry.note_peer_offline(&peer);
-                swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer);
-            }
-        }
-        SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-            telemetry.note_peer_online(&peer_id);
-        }
-        SwarmEvent::ConnectionClosed { peer_id, .. } => {
-            telemetry.note_peer_offline(&peer_id);
-        }
-        SwarmEvent::NewListenAddr { address, .. } => {
-            info!(%address, "listening");
-            println!("listening on {address}");
+        let content_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+
+        let remaining = MAX_ATTACHMENT_BYTES as u64 - total_attachment_bytes;
+        let mut writer = state
+            .channel_state
+            .blobs
+            .writer(remaining)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let mut encoder =
+            base64::write::EncoderStringWriter::new(&base64::engine::general_purpose::STANDARD);
+        while let Some(chunk) = field.chunk().await.map_err(|_| StatusCode::BAD_REQUEST)? {
+            writer
+                .write(&chunk)
+                .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE)?;
+            encoder
+                .write_all(&chunk)
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
         }
-        SwarmEvent::Behaviour(GridEvent::Identify(event)) => {
-            info!(?event, "identify event");
+        let info = writer
+            .finish()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        stored.push(info.sha256.clone());
+        total_attachment_bytes += info.size;
+        attachments.push(Attachment {
+            content_type,
+            filename,
+            data_base64: encoder.into_inner(),
+            size: Some(info.size),
+            sha256: Some(info.sha256),
+        });
+    }
+
+    let channel = publish_target(state, channel.as_deref().unwrap_or_default())?;
+    if body.trim().is_empty() && attachments.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let parent = reply_parent(state, &channel, reply_to)?;
+
+    let author = author.unwrap_or_else(|| default_author(state));
+    let mentions = resolve_mentions(state, &channel, &body);
+    let mut message = ChatMessage::with_attachments(author, body, attachments)
+        .with_peer_id(state.peer_id.clone())
+        .with_hlc(state.channel_state.clock.tick())
+        .with_mentions(mentions)
+        .with_ttl(ttl_secs);
+    if let Some(parent) = &parent {
+        message = message.in_reply_to(parent);
+    }
+    let message = seal_message(state, &channel, message)?;
+    // Sealed attachments only live inside the message; drop the plaintext blobs.
+    if message.is_sealed()
+        && let Err(err) = state
+            .channel_state
+            .release_blobs(stored.iter().map(String::as_str))
+    {
+        warn!(%err, "unable to remove uploaded blobs");
+    }
+
+    send_message(state, channel, message).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Deserialize)]
+struct EditRequest {
+    body: String,
+}
+
+/// Edits a message published by this node; edits of other peers' messages are refused.
+async fn api_edit_message(
+    State(state): State<ApiContext>,
+    AxumPath(id): AxumPath<Uuid>,
+    Json(payload): Json<EditRequest>,
+) -> impl IntoResponse {
+    let Some((channel, message)) = state.channel_state.find_message(id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    if message.peer_id.as_deref() != Some(state.peer_id.as_str())
+        || state.channel_state.is_muted(&state.peer_id)
+    {
+        return StatusCode::FORBIDDEN;
+    }
+    if payload.body.trim().is_empty()
+        && message.attachments.is_empty()
+        && message.sealed_attachments.is_none()
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+    let mut edit = MessageEdit::new(id, payload.body, state.channel_state.clock.tick());
+    if state.channel_state.is_private(&channel) {
+        let Some((epoch, key)) = current_group_key(&state, &channel) else {
+            return StatusCode::FORBIDDEN;
+        };
+        let Ok(sealed) = edit.seal(epoch, &key) else {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        };
+        edit = sealed;
+    }
+    let Ok(edit) = sign_payload(&state.keypair, edit) else {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+    match state
+        .sender
+        .send(ApiRequest::EditMessage { channel, edit })
+        .await
+    {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Deletes a message published by this node, or any message when this node
+/// moderates the channel (or the grid).
+async fn api_delete_message(
+    State(state): State<ApiContext>,
+    AxumPath(id): AxumPath<Uuid>,
+) -> impl IntoResponse {
+    let Some((channel, message)) = state.channel_state.find_message(id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let as_moderator = if message.peer_id.as_deref() == Some(state.peer_id.as_str()) {
+        false
+    } else if state.channel_state.may_moderate(&channel, &state.peer_id) {
+        true
+    } else {
+        return StatusCode::FORBIDDEN;
+    };
+    let tombstone = Tombstone::new(id, as_moderator, state.channel_state.clock.tick());
+    let Ok(tombstone) = sign_payload(&state.keypair, tombstone) else {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+    match state
+        .sender
+        .send(ApiRequest::DeleteMessage { channel, tombstone })
+        .await
+    {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn api_add_reaction(
+    State(state): State<ApiContext>,
+    AxumPath((id, emoji)): AxumPath<(Uuid, String)>,
+) -> impl IntoResponse {
+    send_reaction(&state, id, emoji, true).await
+}
+
+async fn api_remove_reaction(
+    State(state): State<ApiContext>,
+    AxumPath((id, emoji)): AxumPath<(Uuid, String)>,
+) -> impl IntoResponse {
+    send_reaction(&state, id, emoji, false).await
+}
+
+async fn send_reaction(state: &ApiContext, id: Uuid, emoji: String, active: bool) -> StatusCode {
+    let emoji = emoji.trim().to_string();
+    if !Reaction::valid_emoji(&emoji) {
+        return StatusCode::BAD_REQUEST;
+    }
+    let Some((channel, _)) = state.channel_state.find_message(id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    if !can_read(state, &channel) || state.channel_state.is_muted(&state.peer_id) {
+        return StatusCode::FORBIDDEN;
+    }
+    let reaction = Reaction::new(id, emoji, active, state.channel_state.clock.tick());
+    let Ok(reaction) = sign_payload(&state.keypair, reaction) else {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    };
+    match state
+        .sender
+        .send(ApiRequest::React { channel, reaction })
+        .await
+    {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn api_message_history(
+    State(state): State<ApiContext>,
+    AxumPath(id): AxumPath<Uuid>,
+) -> impl IntoResponse {
+    let Some((channel, message)) = state.channel_state.find_message(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !can_read(&state, &channel) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    if is_hidden(&state, &message) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let keys = group_keys(&state, &channel);
+    match state.channel_state.history(id) {
+        Some(history) => {
+            let history: Vec<MessageRevision> = history
+                .into_iter()
+                .map(|revision| {
+                    revision.opened(id, |epoch| keys.get(&epoch).cloned().unwrap_or_default())
+                })
+                .collect();
+            Json(history).into_response()
         }
-        _ => {}
+        None => StatusCode::NOT_FOUND.into_response(),
     }
 }
 
-async fn read_stdin(sender: mpsc::Sender<String>) {
-    let mut lines = BufReader::new(io::stdin()).lines();
-    while let Ok(Some(line)) = lines.next_line().await {
-        if sender.send(line).await.is_err() {
-            break;
+/// Resolves the message a reply targets; it must live in the same channel.
+fn reply_parent(
+    state: &ApiContext,
+    channel: &str,
+    reply_to: Option<Uuid>,
+) -> Result<Option<ChatMessage>, StatusCode> {
+    let Some(id) = reply_to else {
+        return Ok(None);
+    };
+    match state.channel_state.find_message(id) {
+        Some((parent_channel, parent)) if parent_channel == channel => Ok(Some(parent)),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Resolves the channel named in a publish request to its id; private
+/// channels only accept messages from members.
+fn publish_target(state: &ApiContext, channel: &str) -> Result<String, StatusCode> {
+    if channel.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let channel = state
+        .channel_state
+        .resolve(channel)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if state.channel_state.is_muted(&state.peer_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if state.channel_state.is_private(&channel)
+        && !state.channel_state.is_member(&channel, &state.peer_id)
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(channel)
+}
+
+/// Private channels are listed and readable for members only.
+fn can_read(state: &ApiContext, channel: &str) -> bool {
+    !state.channel_state.is_private(channel)
+        || state.channel_state.is_member(channel, &state.peer_id)
+}
+
+/// Encrypts a message for a private channel with its current group key.
+fn seal_message(
+    state: &ApiContext,
+    channel: &str,
+    message: ChatMessage,
+) -> Result<ChatMessage, StatusCode> {
+    if !state.channel_state.is_private(channel) {
+        return Ok(message);
+    }
+    let (epoch, key) = current_group_key(state, channel).ok_or(StatusCode::FORBIDDEN)?;
+    message
+        .seal(epoch, &key)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Unwraps this node's copy of the group key in a key record.
+fn unwrap_group_key(state: &ApiContext, record: &Signed<ChannelKey>) -> Option<GroupKey> {
+    let secret = state.dh_secret.as_ref()?;
+    let wrapped = record.payload.wrapped.get(&state.peer_id)?;
+    let context = ChannelKey::wrap_context(
+        &record.payload.channel,
+        record.payload.epoch,
+        &state.peer_id,
+    );
+    crypto::unwrap_key(secret, &dh_public(&record.signer)?, &context, wrapped).ok()
+}
+
+fn current_group_key(state: &ApiContext, channel: &str) -> Option<(u32, GroupKey)> {
+    let record = state.channel_state.current_key(channel)?;
+    Some((record.payload.epoch, unwrap_group_key(state, &record)?))
+}
+
+/// This node's group keys for a channel by epoch; empty for public channels.
+/// An epoch two owners rotated to concurrently has a key from each.
+fn group_keys(state: &ApiContext, channel: &str) -> HashMap<u32, Vec<GroupKey>> {
+    let mut keys: HashMap<u32, Vec<GroupKey>> = HashMap::new();
+    for record in state.channel_state.channel_keys(channel) {
+        if let Some(key) = unwrap_group_key(state, &record) {
+            keys.entry(record.payload.epoch).or_default().push(key);
         }
     }
+    keys
 }
 
-#[derive(Deserialize)]
-struct PublishRequest {
-    channel: String,
-    body: String,
-    author: Option<String>,
-    #[serde(default)]
-    attachments: Vec<AttachmentPayload>,
+/// Wraps a private channel's group key for its current members. With
+/// `rotate` (or when this node does not hold the current key) a fresh key
+/// starts a new epoch; otherwise the current key is re-shared.
+fn share_channel_key(state: &ApiContext, channel: &str, rotate: bool) -> Result<()> {
+    let secret = state
+        .dh_secret
+        .as_ref()
+        .ok_or_else(|| anyhow!("node identity cannot hold encryption keys"))?;
+    let current = state.channel_state.current_key(channel);
+    let reused = current
+        .as_ref()
+        .filter(|_| !rotate)
+        .and_then(|record| Some((record.payload.epoch, unwrap_group_key(state, record)?)));
+    let (epoch, key) = match reused {
+        Some(reused) => reused,
+        None => (
+            current.map_or(0, |r| r.payload.epoch + 1),
+            crypto::random_key(),
+        ),
+    };
+    let mut wrapped = BTreeMap::new();
+    for member in state.channel_state.roles(channel).into_keys() {
+        let Some(public) = dh_public(&member) else {
+            warn!(%member, %channel, "cannot derive member key; not sharing channel key");
+            continue;
+        };
+        let context = ChannelKey::wrap_context(channel, epoch, &member);
+        wrapped.insert(member, crypto::wrap_key(secret, &public, &context, &key)?);
+    }
+    let record = ChannelKey {
+        channel: channel.to_string(),
+        epoch,
+        wrapped,
+        hlc: state.channel_state.clock.tick(),
+    };
+    state
+        .channel_state
+        .set_key(sign_payload(&state.keypair, record)?)
 }
 
-#[derive(Deserialize)]
-struct AttachmentPayload {
-    content_type: String,
-    filename: String,
-    data_base64: String,
+/// A moderation action as listed by the API.
+#[derive(Serialize)]
+struct ModerationView {
+    #[serde(flatten)]
+    action: ModerationAction,
+    issued_by: String,
+    /// Whether the sanction currently applies (active and not lapsed).
+    in_force: bool,
+}
+
+async fn api_moderation(State(state): State<ApiContext>) -> impl IntoResponse {
+    let actions: Vec<ModerationView> = state
+        .channel_state
+        .moderation_actions()
+        .into_iter()
+        .map(|signed| ModerationView {
+            in_force: signed.payload.in_force(),
+            action: signed.payload,
+            issued_by: signed.signer,
+        })
+        .collect();
+    Json(actions)
+}
+
+/// Longest timed sanction, ten years; longer ones should be indefinite.
+const MAX_SANCTION_SECS: i64 = 10 * 365 * 24 * 60 * 60;
+
+#[derive(Deserialize, Default)]
+struct SanctionRequest {
+    reason: Option<String>,
+    /// Lifts the sanction automatically after this many seconds.
+    duration_secs: Option<i64>,
+}
+
+async fn api_impose_sanction(
+    State(state): State<ApiContext>,
+    AxumPath((peer, sanction)): AxumPath<(String, Sanction)>,
+    payload: Option<Json<SanctionRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    if payload
+        .duration_secs
+        .is_some_and(|secs| secs <= 0 || secs > MAX_SANCTION_SECS)
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+    let action = ModerationAction::impose(
+        peer,
+        sanction,
+        channel_text(payload.reason),
+        payload.duration_secs,
+        state.channel_state.clock.tick(),
+    );
+    send_moderation(&state, action).await
+}
+
+async fn api_lift_sanction(
+    State(state): State<ApiContext>,
+    AxumPath((peer, sanction)): AxumPath<(String, Sanction)>,
+) -> impl IntoResponse {
+    let action = ModerationAction::lift(peer, sanction, state.channel_state.clock.tick());
+    send_moderation(&state, action).await
+}
+
+/// Signs and publishes a moderation action; only grid moderators may.
+async fn send_moderation(state: &ApiContext, action: ModerationAction) -> StatusCode {
+    if !state.channel_state.is_moderator(&state.peer_id) {
+        return StatusCode::FORBIDDEN;
+    }
+    if action.peer.parse::<PeerId>().is_err() || action.peer == state.peer_id {
+        return StatusCode::BAD_REQUEST;
+    }
+    let action = match sign_payload(&state.keypair, action) {
+        Ok(action) => action,
+        Err(err) => {
+            warn!(%err, "unable to sign moderation action");
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    match state.sender.send(ApiRequest::Moderate { action }).await {
+        Ok(_) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
 }
 
+/// A profile as returned by the API.
 #[derive(Serialize)]
-struct StatusResponse {
+struct ProfileView {
     peer_id: String,
-    peers: Vec<String>,
-    message_count: usize,
-    last_message: Option<String>,
+    #[serde(flatten)]
+    profile: Profile,
 }
 
-#[derive(Deserialize)]
-struct VoiceSignalRequest {
-    #[serde(rename = "type")]
-    kind: String,
-    data: String,
-    to: Option<String>,
+impl From<Signed<Profile>> for ProfileView {
+    fn from(signed: Signed<Profile>) -> Self {
+        Self {
+            peer_id: signed.signer,
+            profile: signed.payload,
+        }
+    }
 }
 
-async fn serve_api(bind: SocketAddr, state: ApiContext) -> Result<()> {
-    let app = Router::new()
-        .route("/health", get(api_health))
-        .route("/channels", get(api_channels).post(api_create_channel))
-        .route("/channels/:name", delete(api_delete_channel))
-        .route("/messages", get(api_messages).post(api_publish))
-        .route("/status", get(api_status))
-        .route("/voice/signals", get(api_voice_signals))
-        .route("/voice/signal", post(api_voice_signal_post))
-        .with_state(state);
+/// This node's profile, falling back to the configured nickname.
+fn own_profile(state: &ApiContext) -> Profile {
+    state
+        .channel_state
+        .profile(&state.peer_id)
+        .map(|p| p.payload)
+        .unwrap_or_else(|| Profile::new(state.fallback_author.clone(), Hlc::default()))
+}
 
-    let listener = TcpListener::bind(bind).await?;
-    axum::serve(listener, app)
-        .await
-        .map_err(|err| anyhow!(err.to_string()))
+/// Author name for messages sent without one.
+fn default_author(state: &ApiContext) -> String {
+    own_profile(state).display_name
 }
 
-async fn api_health() -> impl IntoResponse {
-    StatusCode::OK
+async fn api_own_profile(State(state): State<ApiContext>) -> impl IntoResponse {
+    Json(ProfileView {
+        peer_id: state.peer_id.clone(),
+        profile: own_profile(&state),
+    })
 }
 
-#[derive(Deserialize)]
-struct MessagesQuery {
-    #[serde(default = "default_channel")]
-    channel: String,
+async fn api_profiles(State(state): State<ApiContext>) -> impl IntoResponse {
+    let profiles: Vec<ProfileView> = state
+        .channel_state
+        .profiles()
+        .into_iter()
+        .map(ProfileView::from)
+        .collect();
+    Json(profiles)
 }
 
-fn default_channel() -> String {
-    "general".to_string()
+async fn api_profile(
+    State(state): State<ApiContext>,
+    AxumPath(peer): AxumPath<String>,
+) -> impl IntoResponse {
+    match state.channel_state.profile(&peer) {
+        Some(profile) => Json(ProfileView::from(profile)).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
 }
 
-async fn api_channels(State(state): State<ApiContext>) -> impl IntoResponse {
-    Json(state.channel_state.list())
+async fn api_avatar(
+    State(state): State<ApiContext>,
+    AxumPath(peer): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(avatar) = state
+        .channel_state
+        .profile(&peer)
+        .and_then(|p| p.payload.avatar)
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if avatar.validate().is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match state.channel_state.blobs.read(&avatar.sha256) {
+        Ok(bytes) => (
+            [
+                (header::CONTENT_TYPE, avatar.content_type),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
 }
 
 #[derive(Deserialize)]
-struct CreateChannelRequest {
-    name: String,
+struct ProfileUpdateRequest {
+    display_name: Option<String>,
+    /// Empty strings clear the optional fields.
+    bio: Option<String>,
+    pronouns: Option<String>,
+    status: Option<String>,
 }
 
-async fn api_create_channel(
+/// Applies an optional text field update, enforcing a length limit.
+fn profile_text(
+    field: &mut Option<String>,
+    value: Option<String>,
+    max_chars: usize,
+) -> Result<(), String> {
+    if let Some(value) = value {
+        let value = channel_text(Some(value));
+        if value
+            .as_ref()
+            .is_some_and(|v| v.chars().count() > max_chars)
+        {
+            return Err(format!("at most {max_chars} characters"));
+        }
+        *field = value;
+    }
+    Ok(())
+}
+
+/// Updates this node's profile; a new display name also becomes the
+/// configured nickname.
+async fn api_update_profile(
     State(state): State<ApiContext>,
-    Json(payload): Json<CreateChannelRequest>,
+    Json(payload): Json<ProfileUpdateRequest>,
 ) -> impl IntoResponse {
-    if let Err(e) = state.channel_state.add_channel_local(&payload.name) {
-        return (StatusCode::BAD_REQUEST, e.to_string());
+    let mut profile = own_profile(&state);
+    if let Some(name) = payload.display_name {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("display_name must be 1-{MAX_DISPLAY_NAME_CHARS} characters"),
+            );
+        }
+        profile.display_name = name;
+    }
+    for (field, value, max_chars, label) in [
+        (&mut profile.bio, payload.bio, MAX_BIO_CHARS, "bio"),
+        (
+            &mut profile.pronouns,
+            payload.pronouns,
+            MAX_PRONOUNS_CHARS,
+            "pronouns",
+        ),
+        (
+            &mut profile.status,
+            payload.status,
+            MAX_STATUS_CHARS,
+            "status",
+        ),
+    ] {
+        if let Err(err) = profile_text(field, value, max_chars) {
+            return (StatusCode::BAD_REQUEST, format!("{label}: {err}"));
+        }
     }
-    if state.sender.send(ApiRequest::BroadcastChannelList).await.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "channel created but broadcast failed".to_string());
+    let config_path = &state.channel_state.config_path;
+    if let Err(err) = load_or_create_config(config_path)
+        .and_then(|mut config| config.set_nickname(&profile.display_name, config_path))
+    {
+        warn!(%err, "unable to save nickname");
     }
-    (StatusCode::CREATED, String::new())
+    send_profile(&state, profile).await
 }
 
-async fn api_delete_channel(
+#[derive(Deserialize)]
+struct AvatarRequest {
+    content_type: String,
+    data_base64: String,
+}
+
+async fn api_set_avatar(
     State(state): State<ApiContext>,
-    AxumPath(name): AxumPath<String>,
+    Json(payload): Json<AvatarRequest>,
 ) -> impl IntoResponse {
-    if let Err(e) = state.channel_state.remove_channel_local(&name) {
-        return (StatusCode::BAD_REQUEST, e.to_string());
+    if !AVATAR_CONTENT_TYPES.contains(&payload.content_type.as_str()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "avatar must be a PNG, JPEG, GIF or WebP image".to_string(),
+        );
     }
-    if state.sender.send(ApiRequest::BroadcastChannelRemoved(name)).await.is_err() {
-        return (StatusCode::INTERNAL_SERVER_ERROR, "channel deleted but broadcast failed".to_string());
+    let Ok(bytes) = base64::Engine::decode(
+        &base64::engine::general_purpose::STANDARD,
+        &payload.data_base64,
+    ) else {
+        return (StatusCode::BAD_REQUEST, "invalid base64".to_string());
+    };
+    if bytes.len() > MAX_AVATAR_BYTES {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("avatar exceeds {} KB", MAX_AVATAR_BYTES / 1024),
+        );
     }
-    (StatusCode::NO_CONTENT, String::new())
+    let info = match state.channel_state.blobs.put(&bytes) {
+        Ok(info) => info,
+        Err(err) => {
+            warn!(%err, "unable to store avatar");
+            return (StatusCode::INTERNAL_SERVER_ERROR, String::new());
+        }
+    };
+    let mut profile = own_profile(&state);
+    profile.avatar = Some(Avatar {
+        sha256: info.sha256,
+        content_type: payload.content_type,
+        size: info.size,
+    });
+    send_profile(&state, profile).await
 }
 
-async fn api_messages(
-    State(state): State<ApiContext>,
-    Query(q): Query<MessagesQuery>,
-) -> impl IntoResponse {
-    Json(state.channel_state.messages(&q.channel))
+async fn api_remove_avatar(State(state): State<ApiContext>) -> impl IntoResponse {
+    let mut profile = own_profile(&state);
+    profile.avatar = None;
+    send_profile(&state, profile).await
 }
 
-/// Max total size of attachment data (base64 decoded) per message to keep gossip payloads safe.
-const MAX_ATTACHMENT_BYTES: usize = 512 * 1024; // 512 KB
+/// Stamps, signs and publishes a new version of this node's profile.
+async fn send_profile(state: &ApiContext, mut profile: Profile) -> (StatusCode, String) {
+    profile.hlc = state.channel_state.clock.tick();
+    let profile = match sign_payload(&state.keypair, profile) {
+        Ok(profile) => profile,
+        Err(err) => {
+            warn!(%err, "unable to sign profile");
+            return (StatusCode::INTERNAL_SERVER_ERROR, String::new());
+        }
+    };
+    match state
+        .sender
+        .send(ApiRequest::UpdateProfile { profile })
+        .await
+    {
+        Ok(_) => (StatusCode::ACCEPTED, String::new()),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, String::new()),
+    }
+}
 
-async fn api_publish(
+async fn api_blocks(State(state): State<ApiContext>) -> impl IntoResponse {
+    Json(state.blocked.list())
+}
+
+async fn api_block(
     State(state): State<ApiContext>,
-    Json(payload): Json<PublishRequest>,
+    AxumPath(peer): AxumPath<String>,
 ) -> impl IntoResponse {
-    let channel = payload.channel.trim().to_lowercase();
-    if channel.is_empty() {
+    if peer.parse::<PeerId>().is_err() || peer == state.peer_id {
         return StatusCode::BAD_REQUEST;
     }
-    if !state.channel_state.list().contains(&channel) {
-        return StatusCode::NOT_FOUND;
+    match state.blocked.set(&peer, true) {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            warn!(%err, "unable to save block list");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
-    if payload.body.trim().is_empty() && payload.attachments.is_empty() {
-        return StatusCode::BAD_REQUEST;
+}
+
+async fn api_unblock(
+    State(state): State<ApiContext>,
+    AxumPath(peer): AxumPath<String>,
+) -> impl IntoResponse {
+    match state.blocked.set(&peer, false) {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            warn!(%err, "unable to save block list");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
+}
 
-    let author = payload
-        .author
-        .unwrap_or_else(|| state.fallback_author.clone());
+/// A DM conversation as listed by the API.
+#[derive(Serialize)]
+struct ConversationView {
+    peer: String,
+    message_count: usize,
+    last_message: Option<ChatMessage>,
+    /// DMs to the peer not yet handed to it directly.
+    #[serde(skip_serializing_if = "is_zero")]
+    pending: usize,
+}
 
-    let mut total_attachment_bytes: usize = 0;
-    let attachments: Vec<Attachment> = payload
-        .attachments
-        .iter()
-        .filter_map(|a| {
-            let decoded = base64::Engine::decode(
-                &base64::engine::general_purpose::STANDARD,
-                a.data_base64.as_bytes(),
-            )
-            .ok()?;
-            let decoded_len = decoded.len();
-            if total_attachment_bytes + decoded_len > MAX_ATTACHMENT_BYTES {
-                return None;
+async fn api_conversations(State(state): State<ApiContext>) -> impl IntoResponse {
+    let dms = &state.dms;
+    let conversations: Vec<ConversationView> = dms
+        .peers()
+        .into_iter()
+        .filter(|peer| !state.blocked.contains(peer))
+        .map(|peer| {
+            let messages = dms.messages(&peer);
+            let key = dms.key(&peer);
+            ConversationView {
+                message_count: messages.len(),
+                last_message: messages.into_iter().last().map(|m| m.opened(|_| key)),
+                pending: dms.pending(&peer),
+                peer,
             }
-            total_attachment_bytes += decoded_len;
-            Some(Attachment {
-                content_type: a.content_type.clone(),
-                filename: a.filename.clone(),
-                data_base64: a.data_base64.clone(),
-            })
         })
         .collect();
+    Json(conversations)
+}
+
+async fn api_direct_messages(
+    State(state): State<ApiContext>,
+    AxumPath(peer): AxumPath<String>,
+) -> impl IntoResponse {
+    let key = state.dms.key(&peer);
+    let messages: Vec<ChatMessage> = state
+        .dms
+        .messages(&peer)
+        .into_iter()
+        .filter(|m| !is_hidden(&state, m))
+        .map(|m| m.opened(|_| key))
+        .collect();
+    Json(messages)
+}
 
-    let message = ChatMessage::with_attachments(author, payload.body, attachments);
+#[derive(Deserialize)]
+struct DirectMessageRequest {
+    body: String,
+    author: Option<String>,
+    #[serde(default)]
+    attachments: Vec<AttachmentPayload>,
+    /// Seconds after which the message disappears on both sides.
+    ttl_secs: Option<u32>,
+}
 
-    match state.sender.send(ApiRequest::SendMessage { channel, message }).await {
+/// Sends a DM, sealed with the key shared with `peer`. Fails with 400 when the
+/// peer id carries no ed25519 key to derive it from.
+async fn api_send_direct(
+    State(state): State<ApiContext>,
+    AxumPath(peer): AxumPath<String>,
+    Json(payload): Json<DirectMessageRequest>,
+) -> impl IntoResponse {
+    if peer == state.peer_id
+        || (payload.body.trim().is_empty() && payload.attachments.is_empty())
+        || payload.ttl_secs == Some(0)
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+    let Some(key) = state.dms.key(&peer) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let author = payload.author.unwrap_or_else(|| default_author(&state));
+    let message = ChatMessage::with_attachments(
+        author,
+        payload.body,
+        decode_attachments(&payload.attachments),
+    )
+    .with_peer_id(state.peer_id.clone())
+    .with_hlc(state.channel_state.clock.tick())
+    .with_ttl(payload.ttl_secs);
+    let message = match message.seal(0, &key) {
+        Ok(message) => message,
+        Err(err) => {
+            warn!(%err, "unable to seal direct message");
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let dm = DirectMessage { to: peer, message };
+    match state.sender.send(ApiRequest::SendDirect { dm }).await {
         Ok(_) => StatusCode::ACCEPTED,
         Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
+/// A peer's presence with its current display name.
+#[derive(Serialize)]
+struct PresenceView {
+    #[serde(flatten)]
+    entry: PresenceEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PresenceResponse {
+    /// Status this node announces.
+    status: PresenceStatus,
+    peers: Vec<PresenceView>,
+}
+
+async fn api_presence(State(state): State<ApiContext>) -> impl IntoResponse {
+    let entries = state.telemetry.presence.read().entries();
+    let peers = entries
+        .into_iter()
+        .filter(|entry| !state.blocked.contains(&entry.peer_id))
+        .map(|entry| PresenceView {
+            display_name: state
+                .channel_state
+                .profile(&entry.peer_id)
+                .map(|p| p.payload.display_name),
+            entry,
+        })
+        .collect();
+    Json(PresenceResponse {
+        status: state.telemetry.status(),
+        peers,
+    })
+}
+
+async fn api_set_presence(
+    State(state): State<ApiContext>,
+    Json(payload): Json<Heartbeat>,
+) -> impl IntoResponse {
+    if payload.status == PresenceStatus::Offline {
+        return StatusCode::BAD_REQUEST;
+    }
+    *state.telemetry.status.write() = payload.status;
+    if let Err(err) = save_presence_status(&state.channel_state.data_dir, &payload) {
+        warn!(%err, "unable to save presence status");
+    }
+    match state.sender.send(ApiRequest::AnnouncePresence).await {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 async fn api_status(State(state): State<ApiContext>) -> impl IntoResponse {
     let snapshot = state.telemetry.snapshot();
-    let message_count: usize = state.channel_state.list()
+    let message_count: usize = state
+        .channel_state
+        .ids()
         .iter()
         .map(|ch| state.channel_state.messages(ch).len())
         .sum();
@@ -10,6 +10,9 @@ import { useMessages } from './hooks/useMessages';
 import { useChannels } from './hooks/useChannels';
 import { useNodeStatus } from './hooks/useNodeStatus';
 import { useBlocklist } from './hooks/useBlocklist';
+import { useTyping } from './hooks/useTyping';
+import { useUnread } from './hooks/useUnread';
+import { shortenPeerId } from './lib/utils';
 
 const NICKNAME_KEY = 'gridspeak-nickname';
 
@@ -20,7 +23,7 @@ function getCurrentNickname(): string {
 
 export default function App() {
   const [currentChannel, setCurrentChannel] = useState('general');
-  const { channels, addChannel, removeChannel } = useChannels();
+  const { channels, channelInfo, addChannel, removeChannel } = useChannels();
 
   useEffect(() => {
     if (channels.length > 0 && !channels.includes(currentChannel)) {
@@ -32,13 +35,25 @@ export default function App() {
   const currentUser = getCurrentNickname();
   const memberCount = (status?.peers.length ?? 0) + (currentUser ? 1 : 0);
   const { blocked, block, unblock } = useBlocklist();
-  const visibleMessages = messages.filter(m => !blocked.has(m.author));
+  const visibleMessages = messages.filter(m => !m.peer_id || !blocked.has(m.peer_id));
+  const channelId = channelInfo.find(c => c.name === currentChannel)?.id ?? currentChannel;
+  const { typingPeers, notifyTyping } = useTyping(currentChannel, channelId);
+  const typingNames = typingPeers
+    .filter(peer => !blocked.has(peer))
+    .map(peer => messages.find(m => m.peer_id === peer)?.profile?.display_name ?? shortenPeerId(peer));
+  const { unread, markRead } = useUnread();
+  const lastMessageId = messages[messages.length - 1]?.id;
+
+  useEffect(() => {
+    if (lastMessageId) markRead(currentChannel).catch(() => {});
+  }, [currentChannel, lastMessageId, markRead]);
 
   return (
     <div className="app-shell">
       <Sidebar
         channels={channels}
         currentChannel={currentChannel}
+        unread={unread}
         onSelectChannel={setCurrentChannel}
         onCreateChannel={addChannel}
         onDeleteChannel={removeChannel}
@@ -57,10 +72,15 @@ export default function App() {
             {error && <div className="banner error">{error}</div>}
             {loading && <div className="banner">Syncing message history…</div>}
             <section className="messages-section">
-              <MessageList messages={visibleMessages} blocked={blocked} onBlock={block} />
+              <MessageList messages={visibleMessages} selfPeerId={status?.peer_id} blocked={blocked} onBlock={block} />
             </section>
             <section className="composer-section">
-              <Composer onSend={sendMessage} />
+              {typingNames.length > 0 && (
+                <div className="typing-indicator">
+                  {typingNames.join(', ')} {typingNames.length === 1 ? 'is' : 'are'} typing…
+                </div>
+              )}
+              <Composer onSend={sendMessage} onTyping={notifyTyping} />
             </section>
           </div>
         </div>
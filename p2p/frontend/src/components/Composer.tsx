@@ -6,6 +6,7 @@ const MAX_TOTAL_ATTACHMENT_BYTES = 400 * 1024; // ~400 KB to stay under backend
 
 interface Props {
   onSend: (body: string, author?: string, attachments?: Attachment[]) => Promise<void>;
+  onTyping?: () => void;
 }
 
 function fileToAttachment(file: File): Promise<Attachment | null> {
@@ -31,7 +32,7 @@ function fileToAttachment(file: File): Promise<Attachment | null> {
   });
 }
 
-export function Composer({ onSend }: Props) {
+export function Composer({ onSend, onTyping }: Props) {
   const [body, setBody] = useState('');
   const [author, setAuthor] = useState(() => typeof localStorage !== 'undefined' ? localStorage.getItem(NICKNAME_KEY) ?? '' : '');
   const [submitting, setSubmitting] = useState(false);
@@ -134,7 +135,10 @@ export function Composer({ onSend }: Props) {
             placeholder="Type a message... Paste or attach images, files, audio, video (Enter to send)"
             value={body}
             rows={1}
-            onChange={e => setBody(e.target.value)}
+            onChange={e => {
+              setBody(e.target.value);
+              if (e.target.value) onTyping?.();
+            }}
             onKeyDown={handleKeyDown}
             onPaste={handlePaste}
             disabled={submitting}
@@ -4,7 +4,7 @@ interface Props {
   peers: string[];
   currentUser?: string | null;
   blocked?: Set<string>;
-  onUnblock?: (author: string) => void;
+  onUnblock?: (peerId: string) => void;
 }
 
 /** Discord-like right sidebar: online members + blocked users */
@@ -51,17 +51,17 @@ export function MemberList({ peers, currentUser, blocked = new Set(), onUnblock
             <span className="label">Blocked — {blocked.size}</span>
           </div>
           <ul className="member-list-items">
-            {Array.from(blocked).map(author => (
-              <li key={author} className="member-item">
-                <span className="member-avatar" style={{ backgroundColor: avatarColor(author) }}>
-                  {avatarInitial(author)}
+            {Array.from(blocked).map(peer => (
+              <li key={peer} className="member-item" title={peer}>
+                <span className="member-avatar" style={{ backgroundColor: avatarColor(peer) }}>
+                  {avatarInitial(peer)}
                 </span>
-                <span className="member-name">{author}</span>
+                <span className="member-name">{shortenPeerId(peer)}</span>
                 {onUnblock && (
                   <button
                     type="button"
                     className="member-unblock"
-                    onClick={() => onUnblock(author)}
+                    onClick={() => onUnblock(peer)}
                     title="Unblock"
                   >
                     Unblock
@@ -4,11 +4,13 @@ import { formatTimestamp, formatTimestampFull, parseMessageBody, avatarColor, av
 
 interface Props {
   messages: ChatMessage[];
+  /** This node's PeerId, to highlight messages that mention the user. */
+  selfPeerId?: string;
   blocked: Set<string>;
-  onBlock: (author: string) => void;
+  onBlock: (peerId: string) => void;
 }
 
-export function MessageList({ messages, blocked, onBlock }: Props) {
+export function MessageList({ messages, selfPeerId, blocked, onBlock }: Props) {
   const bottomRef = useRef<HTMLDivElement>(null);
 
   useEffect(() => {
@@ -32,8 +34,9 @@ export function MessageList({ messages, blocked, onBlock }: Props) {
           <MessageRow
             key={message.id}
             message={message}
+            mentionsMe={mentionsPeer(message, selfPeerId)}
             onBlock={onBlock}
-            isBlocked={blocked.has(message.author)}
+            isBlocked={!message.peer_id || blocked.has(message.peer_id)}
           />
         ))}
       </ul>
@@ -42,32 +45,47 @@ export function MessageList({ messages, blocked, onBlock }: Props) {
   );
 }
 
+function mentionsPeer(message: ChatMessage, peerId?: string): boolean {
+  return (message.mentions ?? []).some(
+    m => m.type === 'everyone' || m.type === 'here' || (m.type === 'user' && m.peer_id === peerId)
+  );
+}
+
 function MessageRow({
   message,
+  mentionsMe,
   onBlock,
   isBlocked,
 }: {
   message: ChatMessage;
-  onBlock: (author: string) => void;
+  mentionsMe: boolean;
+  onBlock: (peerId: string) => void;
   isBlocked: boolean;
 }) {
   const parts = parseMessageBody(message.body);
+  const author = message.profile?.display_name ?? message.author;
 
   const handleCopy = () => {
     navigator.clipboard.writeText(message.body);
   };
 
   return (
-    <li className="message-row">
-      <div className="message-avatar" style={{ backgroundColor: avatarColor(message.author) }} title={message.author}>
-        {avatarInitial(message.author)}
+    <li className={`message-row ${mentionsMe ? 'message-row--mention' : ''}`}>
+      <div className="message-avatar" style={{ backgroundColor: avatarColor(author) }} title={author}>
+        {avatarInitial(author)}
       </div>
       <div className="message-content">
         <div className="message-header">
-          <span className="message-author">{message.author}</span>
+          <span className="message-author" title={message.profile?.status}>{author}</span>
+          {message.profile?.pronouns && <span className="message-pronouns">{message.profile.pronouns}</span>}
           <span className="message-time" title={formatTimestampFull(message.timestamp)}>
             {formatTimestamp(message.timestamp)}
           </span>
+          {message.expires_at && (
+            <span className="message-expiry" title={`Disappears ${formatTimestampFull(message.expires_at)}`}>
+              disappearing
+            </span>
+          )}
           <button type="button" className="message-copy" onClick={handleCopy} title="Copy message">
             <svg viewBox="0 0 24 24" fill="none" stroke="currentColor" strokeWidth="2" width="14" height="14">
               <rect x="9" y="9" width="13" height="13" rx="2" ry="2" />
@@ -78,8 +96,8 @@ function MessageRow({
             <button
               type="button"
               className="message-block"
-              onClick={() => onBlock(message.author)}
-              title="Block this user (hide their messages for you)"
+              onClick={() => message.peer_id && onBlock(message.peer_id)}
+              title="Block this peer (your node hides their messages)"
             >
               Block
             </button>
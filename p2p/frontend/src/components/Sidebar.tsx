@@ -1,15 +1,17 @@
 import { useState } from 'react';
+import type { UnreadCount } from '../types';
 
 interface Props {
   channels: string[];
   currentChannel: string;
+  unread?: Record<string, UnreadCount>;
   onSelectChannel: (name: string) => void;
   onCreateChannel: (name: string) => Promise<void>;
   onDeleteChannel: (name: string) => Promise<void>;
 }
 
 /** Discord-like server/channel sidebar */
-export function Sidebar({ channels, currentChannel, onSelectChannel, onCreateChannel, onDeleteChannel }: Props) {
+export function Sidebar({ channels, currentChannel, unread = {}, onSelectChannel, onCreateChannel, onDeleteChannel }: Props) {
   const [showCreate, setShowCreate] = useState(false);
   const [newChannelName, setNewChannelName] = useState('');
   const [creating, setCreating] = useState(false);
@@ -76,34 +78,40 @@ export function Sidebar({ channels, currentChannel, onSelectChannel, onCreateCha
           </div>
         )}
         <ul className="channel-list">
-          {channels.map(ch => (
-            <li
-              key={ch}
-              className={`channel-item ${ch === currentChannel ? 'channel-item--active' : ''}`}
-              onClick={() => onSelectChannel(ch)}
-            >
-              <span className="channel-icon">#</span>
-              <span className="channel-name">{ch}</span>
-              {ch !== 'general' && (
-                <button
-                  type="button"
-                  className="channel-delete-btn"
-                  title="Delete channel"
-                  aria-label={`Delete ${ch}`}
-                  onClick={e => {
-                    e.stopPropagation();
-                    if (deleting === ch) return;
-                    if (!window.confirm(`Delete #${ch}? Messages in this channel will be lost.`)) return;
-                    setDeleting(ch);
-                    onDeleteChannel(ch).finally(() => setDeleting(null));
-                  }}
-                  disabled={deleting !== null}
-                >
-                  ×
-                </button>
-              )}
-            </li>
-          ))}
+          {channels.map(ch => {
+            const counts = ch === currentChannel ? undefined : unread[ch];
+            return (
+              <li
+                key={ch}
+                className={`channel-item ${ch === currentChannel ? 'channel-item--active' : ''} ${counts?.unread ? 'channel-item--unread' : ''}`}
+                onClick={() => onSelectChannel(ch)}
+              >
+                <span className="channel-icon">#</span>
+                <span className="channel-name">{ch}</span>
+                {counts?.mentions ? (
+                  <span className="channel-badge" title={`${counts.mentions} mentions`}>{counts.mentions}</span>
+                ) : null}
+                {ch !== 'general' && (
+                  <button
+                    type="button"
+                    className="channel-delete-btn"
+                    title="Delete channel"
+                    aria-label={`Delete ${ch}`}
+                    onClick={e => {
+                      e.stopPropagation();
+                      if (deleting === ch) return;
+                      if (!window.confirm(`Delete #${ch}? Messages in this channel will be lost.`)) return;
+                      setDeleting(ch);
+                      onDeleteChannel(ch).finally(() => setDeleting(null));
+                    }}
+                    disabled={deleting !== null}
+                  >
+                    ×
+                  </button>
+                )}
+              </li>
+            );
+          })}
         </ul>
         <div className="sidebar-voice">
           <span className="label">Voice & Video</span>
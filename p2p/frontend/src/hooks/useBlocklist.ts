@@ -1,22 +1,36 @@
-import { useCallback, useState } from 'react';
+import { useCallback, useEffect, useState } from 'react';
 import { addBlocked as add, removeBlocked as remove, getBlocked } from '../lib/blocklist';
 
 export function useBlocklist() {
-  const [blocked, setBlocked] = useState<Set<string>>(() => getBlocked());
+  const [blocked, setBlocked] = useState<Set<string>>(() => new Set());
 
-  const refresh = useCallback(() => {
-    setBlocked(getBlocked());
+  const refresh = useCallback(async () => {
+    try {
+      setBlocked(await getBlocked());
+    } catch {
+      // Keep the last known list; the node filters messages regardless.
+    }
   }, []);
 
-  const block = useCallback((author: string) => {
-    add(author);
-    setBlocked(getBlocked());
-  }, []);
+  useEffect(() => {
+    refresh();
+  }, [refresh]);
 
-  const unblock = useCallback((author: string) => {
-    remove(author);
-    setBlocked(getBlocked());
-  }, []);
+  const block = useCallback(
+    async (peerId: string) => {
+      await add(peerId);
+      await refresh();
+    },
+    [refresh]
+  );
+
+  const unblock = useCallback(
+    async (peerId: string) => {
+      await remove(peerId);
+      await refresh();
+    },
+    [refresh]
+  );
 
   return { blocked, block, unblock };
 }
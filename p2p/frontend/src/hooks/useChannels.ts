@@ -1,7 +1,8 @@
-import { useCallback, useEffect, useRef, useState } from 'react';
+import { useCallback, useEffect, useMemo, useRef, useState } from 'react';
 import { API_BASE } from '../lib/api';
+import type { ChannelInfo } from '../types';
 
-async function getChannels(): Promise<string[]> {
+async function getChannels(): Promise<ChannelInfo[]> {
   const response = await fetch(`${API_BASE}/channels`);
   if (!response.ok) {
     throw new Error(`Failed to fetch channels (${response.status})`);
@@ -33,7 +34,7 @@ async function deleteChannel(name: string): Promise<void> {
 }
 
 export function useChannels(pollMs = 5000) {
-  const [channels, setChannels] = useState<string[]>([]);
+  const [channelInfo, setChannelInfo] = useState<ChannelInfo[]>([]);
   const [loading, setLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
   const timer = useRef<number>();
@@ -41,7 +42,7 @@ export function useChannels(pollMs = 5000) {
   const refresh = useCallback(async () => {
     try {
       const data = await getChannels();
-      setChannels(data);
+      setChannelInfo(data);
       setError(null);
     } catch (err) {
       const reason = err instanceof Error ? err.message : 'Unknown error';
@@ -75,5 +76,7 @@ export function useChannels(pollMs = 5000) {
     [refresh]
   );
 
-  return { channels, loading, error, refresh, addChannel, removeChannel };
+  const channels = useMemo(() => channelInfo.map((c) => c.name), [channelInfo]);
+
+  return { channels, channelInfo, loading, error, refresh, addChannel, removeChannel };
 }
@@ -1,4 +1,4 @@
-import { useCallback, useEffect, useMemo, useRef, useState } from 'react';
+import { useCallback, useEffect, useRef, useState } from 'react';
 import type { ChatMessage, MessageComposerPayload } from '../types';
 import { API_BASE } from '../lib/api';
 
@@ -64,13 +64,7 @@ export function useMessages(channel: string, pollMs = 3000) {
     [channel, refresh]
   );
 
-  const sortedMessages = useMemo(
-    () =>
-      [...messages].sort(
-        (a, b) => new Date(a.timestamp).getTime() - new Date(b.timestamp).getTime()
-      ),
-    [messages]
-  );
-
-  return { messages: sortedMessages, loading, error, sendMessage };
+  // The node returns messages in clock order agreed by all peers; sender
+  // timestamps can be skewed, so keep that order instead of re-sorting.
+  return { messages, loading, error, sendMessage };
 }
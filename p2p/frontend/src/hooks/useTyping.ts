@@ -0,0 +1,60 @@
+import { useCallback, useEffect, useRef, useState } from 'react';
+import { API_BASE } from '../lib/api';
+
+type TypingEvent = {
+  type: 'typing';
+  channel: string;
+  peer_id: string;
+  typing: boolean;
+  expires_in_ms: number;
+};
+
+const TYPING_REPORT_MS = 2000;
+
+async function reportTyping(channel: string): Promise<void> {
+  await fetch(`${API_BASE}/channels/${encodeURIComponent(channel)}/typing`, { method: 'PUT' });
+}
+
+/**
+ * Peers typing in a channel, from the node's `/events` stream. Events carry
+ * the channel id; `channel` is the name used for API calls.
+ */
+export function useTyping(channel: string, channelId: string) {
+  const [typing, setTyping] = useState<Record<string, number>>({});
+  const lastReport = useRef(0);
+
+  useEffect(() => {
+    setTyping({});
+    const source = new EventSource(`${API_BASE}/events`);
+    source.addEventListener('typing', (e) => {
+      const event = JSON.parse((e as MessageEvent).data) as TypingEvent;
+      if (event.channel !== channelId) return;
+      setTyping((prev) => {
+        const next = { ...prev };
+        if (event.typing) next[event.peer_id] = Date.now() + event.expires_in_ms;
+        else delete next[event.peer_id];
+        return next;
+      });
+    });
+    const timer = window.setInterval(() => {
+      setTyping((prev) => {
+        const now = Date.now();
+        const live = Object.entries(prev).filter(([, until]) => until > now);
+        return live.length === Object.keys(prev).length ? prev : Object.fromEntries(live);
+      });
+    }, 1000);
+    return () => {
+      source.close();
+      clearInterval(timer);
+    };
+  }, [channelId]);
+
+  const notifyTyping = useCallback(() => {
+    const now = Date.now();
+    if (now - lastReport.current < TYPING_REPORT_MS) return;
+    lastReport.current = now;
+    reportTyping(channel).catch(() => {});
+  }, [channel]);
+
+  return { typingPeers: Object.keys(typing), notifyTyping };
+}
@@ -0,0 +1,46 @@
+import { useCallback, useEffect, useRef, useState } from 'react';
+import { API_BASE } from '../lib/api';
+import type { UnreadCount } from '../types';
+
+async function getUnread(): Promise<UnreadCount[]> {
+  const response = await fetch(`${API_BASE}/unread`);
+  if (!response.ok) {
+    throw new Error(`Failed to fetch unread counts (${response.status})`);
+  }
+  const data = await response.json();
+  return Array.isArray(data) ? data : [];
+}
+
+/** Unread and mention counts per channel name, kept by the node. */
+export function useUnread(pollMs = 5000) {
+  const [unread, setUnread] = useState<Record<string, UnreadCount>>({});
+  const timer = useRef<number>();
+
+  const refresh = useCallback(async () => {
+    try {
+      const data = await getUnread();
+      setUnread(Object.fromEntries(data.map((c) => [c.name, c])));
+    } catch {
+      // Badges are best effort; keep the last known counts.
+    }
+  }, []);
+
+  useEffect(() => {
+    refresh();
+    timer.current = window.setInterval(refresh, pollMs);
+    return () => {
+      if (timer.current) clearInterval(timer.current);
+    };
+  }, [pollMs, refresh]);
+
+  /** Marks a channel read up to its latest message. */
+  const markRead = useCallback(
+    async (channel: string) => {
+      await fetch(`${API_BASE}/channels/${encodeURIComponent(channel)}/read`, { method: 'PUT' });
+      await refresh();
+    },
+    [refresh]
+  );
+
+  return { unread, markRead };
+}
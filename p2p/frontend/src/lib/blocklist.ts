@@ -1,43 +1,33 @@
 /**
- * Local block/ignore list (stored in localStorage).
- * Blocked authors' messages are hidden only on this client.
+ * Block list kept by the node (`/blocks`), shared by every client using it.
+ * Blocked peers' messages are left out of the node's API responses.
  */
 
-const STORAGE_KEY = 'gridspeak-blocked-authors';
+import { API_BASE } from './api';
 
-function load(): Set<string> {
-  if (typeof localStorage === 'undefined') return new Set();
-  try {
-    const raw = localStorage.getItem(STORAGE_KEY);
-    if (!raw) return new Set();
-    const arr = JSON.parse(raw) as string[];
-    return new Set(Array.isArray(arr) ? arr : []);
-  } catch {
-    return new Set();
+export async function getBlocked(): Promise<Set<string>> {
+  const response = await fetch(`${API_BASE}/blocks`);
+  if (!response.ok) {
+    throw new Error(`Failed to fetch block list (${response.status})`);
   }
+  const data = await response.json();
+  return new Set(Array.isArray(data) ? data : []);
 }
 
-function save(set: Set<string>): void {
-  if (typeof localStorage === 'undefined') return;
-  localStorage.setItem(STORAGE_KEY, JSON.stringify([...set]));
-}
-
-export function getBlocked(): Set<string> {
-  return load();
-}
-
-export function addBlocked(author: string): void {
-  const set = load();
-  set.add(author.trim());
-  save(set);
-}
-
-export function removeBlocked(author: string): void {
-  const set = load();
-  set.delete(author);
-  save(set);
+export async function addBlocked(peerId: string): Promise<void> {
+  const response = await fetch(`${API_BASE}/blocks/${encodeURIComponent(peerId.trim())}`, {
+    method: 'PUT',
+  });
+  if (!response.ok) {
+    throw new Error(`Failed to block peer (${response.status})`);
+  }
 }
 
-export function isBlocked(author: string): boolean {
-  return load().has(author);
+export async function removeBlocked(peerId: string): Promise<void> {
+  const response = await fetch(`${API_BASE}/blocks/${encodeURIComponent(peerId)}`, {
+    method: 'DELETE',
+  });
+  if (!response.ok && response.status !== 404) {
+    throw new Error(`Failed to unblock peer (${response.status})`);
+  }
 }
@@ -10,6 +10,45 @@ export type ChatMessage = {
   body: string;
   timestamp: string;
   attachments?: Attachment[];
+  peer_id?: string;
+  edited_at?: string;
+  reply_to?: string;
+  thread_root?: string;
+  reply_count?: number;
+  hlc?: { wall: number; logical: number };
+  reactions?: { emoji: string; count: number; me: boolean }[];
+  profile?: Profile;
+  mentions?: Mention[];
+  ttl_secs?: number;
+  expires_at?: string;
+};
+
+export type Mention =
+  | { type: 'user'; peer_id: string; name: string }
+  | { type: 'channel'; channel: string; name: string }
+  | { type: 'here' }
+  | { type: 'everyone' };
+
+export type Profile = {
+  display_name: string;
+  avatar?: { sha256: string; content_type: string; size: number };
+  bio?: string;
+  pronouns?: string;
+  status?: string;
+};
+
+export type ChannelInfo = {
+  id: string;
+  name: string;
+  display_name: string;
+  topic: string;
+  description: string;
+  category?: string;
+  creator: string | null;
+  created_at: string | null;
+  aliases?: { name: string; until: string }[];
+  roles?: Record<string, 'owner' | 'moderator' | 'member'>;
+  private?: boolean;
 };
 
 export type MessageComposerPayload = {
@@ -31,3 +70,11 @@ export type VoiceSignal = {
   type: string; // "offer" | "answer" | "ice"
   data: string;
 };
+
+export type UnreadCount = {
+  channel: string;
+  name: string;
+  unread: number;
+  mentions: number;
+  last_read?: string;
+};